@@ -29,7 +29,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .to_owned()
     };
 
-    let eleven_labs_client = eleven_labs_client::ElevenLabsTtsClient::new(key);
+    let eleven_labs_client = eleven_labs_client::ElevenLabsTtsClient::with_config(
+        key,
+        &app_config.tts_service_config.eleven_labs,
+    )?;
     let data = eleven_labs_client
         .tts(
             "Hello world",
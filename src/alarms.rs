@@ -0,0 +1,718 @@
+use crate::{
+    configuration::{deserialize_time_of_day, serialize_time_of_day},
+    inbox::SpeechRequest,
+    quiet_hours::Priority,
+    speech_service::{AzureVoiceStyle, SequenceStep, SpeechProvider},
+    state_file::{read_json_or_move_aside, write_json_atomically},
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Local, NaiveTime, TimeZone, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::{mpsc::UnboundedSender, watch};
+use tracing::*;
+
+/// Upper bound on sleeping so that clock changes are picked up
+const MAX_SCHEDULER_SLEEP: Duration = Duration::from_secs(60);
+const DEFAULT_SNOOZE_MINUTES: u64 = 9;
+
+/// Accepts the usual five fields as well as the six or seven fields of the `cron` crate
+pub fn parse_cron(expression: &str) -> Result<cron::Schedule> {
+    let expression = if expression.split_whitespace().count() == 5 {
+        format!("0 {}", expression)
+    } else {
+        expression.to_owned()
+    };
+    cron::Schedule::from_str(&expression)
+        .map_err(|e| anyhow::anyhow!("Invalid cron expression {:?} {}", expression, e))
+}
+
+/// None if the result doesn't fit into a date
+fn checked_from_now(duration: Duration) -> Option<DateTime<Utc>> {
+    chrono::Duration::from_std(duration)
+        .ok()
+        .and_then(|duration| Utc::now().checked_add_signed(duration))
+}
+
+/// How long a scheduler loop should sleep before checking `next_fire` again
+pub(crate) fn sleep_duration(next_fire: Option<DateTime<Local>>, now: DateTime<Local>) -> Duration {
+    next_fire
+        .map(|next_fire| (next_fire - now).to_std().unwrap_or_default())
+        .unwrap_or(MAX_SCHEDULER_SLEEP)
+        .min(MAX_SCHEDULER_SLEEP)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlarmSchedule {
+    /// Fires once
+    Timer {
+        at: DateTime<Utc>,
+    },
+    Daily {
+        #[serde(
+            deserialize_with = "deserialize_time_of_day",
+            serialize_with = "serialize_time_of_day"
+        )]
+        time: NaiveTime,
+    },
+    Weekly {
+        #[serde(
+            deserialize_with = "deserialize_time_of_day",
+            serialize_with = "serialize_time_of_day"
+        )]
+        time: NaiveTime,
+        weekdays: Vec<Weekday>,
+    },
+    Cron {
+        expression: String,
+    },
+}
+
+impl AlarmSchedule {
+    /// None once a timer fired
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            AlarmSchedule::Timer { at } => {
+                let at = at.with_timezone(&Local);
+                (at > after).then_some(at)
+            }
+            AlarmSchedule::Daily { time } => next_time_of_day(*time, after, None),
+            AlarmSchedule::Weekly { time, weekdays } => {
+                next_time_of_day(*time, after, Some(weekdays))
+            }
+            AlarmSchedule::Cron { expression } => parse_cron(expression)
+                .ok()
+                .and_then(|schedule| schedule.after(&after).next()),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self {
+            AlarmSchedule::Timer { at } if *at <= Utc::now() => {
+                anyhow::bail!("Timer is in the past")
+            }
+            AlarmSchedule::Weekly { weekdays, .. } if weekdays.is_empty() => {
+                anyhow::bail!("Weekly alarm needs at least one weekday")
+            }
+            AlarmSchedule::Cron { expression } => parse_cron(expression).map(|_| ()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Local time skipped by a daylight saving change doesn't fire that day
+///
+/// Local time repeated by a daylight saving change only fires the first time.
+fn next_time_of_day<Tz: TimeZone>(
+    time: NaiveTime,
+    after: DateTime<Tz>,
+    weekdays: Option<&[Weekday]>,
+) -> Option<DateTime<Tz>> {
+    let mut date = after.date_naive();
+    // today plus a full week
+    for _ in 0..8 {
+        let allowed_day = weekdays
+            .map(|weekdays| weekdays.contains(&date.weekday()))
+            .unwrap_or(true);
+        if allowed_day {
+            if let Some(candidate) = after
+                .timezone()
+                .from_local_datetime(&date.and_time(time))
+                .earliest()
+            {
+                if candidate > after {
+                    return Some(candidate);
+                }
+            }
+        }
+        date = date.succ_opt()?;
+    }
+    None
+}
+
+/// What plays when an alarm fires
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AlarmAction {
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub provider: SpeechProvider,
+    /// Only used by Azure
+    #[serde(default)]
+    pub style: AzureVoiceStyle,
+    /// ElevenLabs voice name
+    #[serde(default)]
+    pub voice: Option<String>,
+    /// Played from the audio repository before the text
+    #[serde(default)]
+    pub sound: Option<String>,
+    /// Decides whether the alarm plays during quiet hours and do not disturb
+    #[serde(default)]
+    pub priority: Priority,
+    /// Discard the alarm if it can't play within this many seconds of firing
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+}
+
+impl AlarmAction {
+    fn steps(&self) -> Vec<SequenceStep> {
+        let mut steps = vec![];
+        if let Some(sound) = &self.sound {
+            steps.push(SequenceStep::PlayFile {
+                file: sound.clone(),
+            });
+        }
+        if let Some(text) = &self.text {
+            steps.push(SequenceStep::Say {
+                text: text.clone(),
+                provider: self.provider,
+                style: self.style,
+                voice: self.voice.clone(),
+            });
+        }
+        steps
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.text.is_none() && self.sound.is_none() {
+            anyhow::bail!("Alarm needs a text or a sound");
+        }
+        if let Some(ttl) = self.ttl_seconds {
+            checked_from_now(Duration::from_secs(ttl))
+                .with_context(|| format!("ttl_seconds {} is out of range", ttl))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Alarm {
+    pub id: String,
+    #[serde(default)]
+    pub label: Option<String>,
+    pub schedule: AlarmSchedule,
+    #[serde(flatten)]
+    pub action: AlarmAction,
+}
+
+/// ```json
+/// {"label": "tea", "in_seconds": 240, "text": "Tea is ready", "sound": "chimes/ding.mp3", "priority": "high"}
+/// {"schedule": {"type": "weekly", "time": "07:00", "weekdays": ["mon", "fri"]}, "text": "Wake up"}
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+pub struct CreateAlarm {
+    /// Generated if missing. Creating an alarm with an existing id replaces it
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub schedule: Option<AlarmSchedule>,
+    /// Shorthand for a timer
+    #[serde(default)]
+    pub in_seconds: Option<u64>,
+    #[serde(flatten)]
+    pub action: AlarmAction,
+}
+
+/// Snoozes the most recently fired alarm if no id is given
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct SnoozeAlarm {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub minutes: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct ScheduledAlarm {
+    alarm: Alarm,
+    next_fire: Option<DateTime<Local>>,
+}
+
+/// User created timers and alarms persisted to a state file
+#[derive(Debug, Clone)]
+pub struct AlarmScheduler {
+    save_file_path: Arc<PathBuf>,
+    alarms: Arc<Mutex<Vec<ScheduledAlarm>>>,
+    last_fired: Arc<Mutex<Option<Alarm>>>,
+    changes: Arc<watch::Sender<()>>,
+}
+
+impl AlarmScheduler {
+    /// Alarms that should have fired while the server was down are skipped
+    ///
+    /// An unreadable alarms file is kept next to the new one instead of being overwritten.
+    pub fn load(save_file_path: PathBuf) -> Self {
+        let alarms: Vec<Alarm> = read_json_or_move_aside(&save_file_path).unwrap_or_default();
+        info!("Loaded {} alarms", alarms.len());
+        let now = Local::now();
+        let alarms = alarms
+            .into_iter()
+            .map(|alarm| ScheduledAlarm {
+                next_fire: alarm.schedule.next_after(now),
+                alarm,
+            })
+            .collect();
+        let (changes, _) = watch::channel(());
+        Self {
+            save_file_path: Arc::new(save_file_path),
+            alarms: Arc::new(Mutex::new(alarms)),
+            last_fired: Arc::default(),
+            changes: Arc::new(changes),
+        }
+    }
+
+    pub fn alarms(&self) -> Vec<Alarm> {
+        self.alarms
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|scheduled| scheduled.alarm.clone())
+            .collect()
+    }
+
+    /// Notified whenever alarms are added or removed
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.changes.subscribe()
+    }
+
+    /// Wakes subscribers without changing anything
+    pub fn notify_subscribers(&self) {
+        self.changes.send_replace(());
+    }
+
+    pub fn create(&self, request: CreateAlarm) -> Result<Alarm> {
+        let schedule = match (request.schedule, request.in_seconds) {
+            (Some(schedule), _) => schedule,
+            (None, Some(seconds)) => AlarmSchedule::Timer {
+                at: checked_from_now(Duration::from_secs(seconds))
+                    .with_context(|| format!("in_seconds {} is out of range", seconds))?,
+            },
+            (None, None) => anyhow::bail!("Alarm needs a schedule or in_seconds"),
+        };
+        schedule.validate()?;
+        request.action.validate()?;
+        let alarm = Alarm {
+            id: request
+                .id
+                .unwrap_or_else(|| format!("{:08x}", rand::random::<u32>())),
+            label: request.label,
+            schedule,
+            action: request.action,
+        };
+        info!("Creating alarm {:?}", alarm);
+        self.insert(alarm.clone())?;
+        Ok(alarm)
+    }
+
+    pub fn delete(&self, id: &str) -> Result<()> {
+        {
+            let mut alarms = self.alarms.lock().unwrap();
+            let count = alarms.len();
+            alarms.retain(|scheduled| scheduled.alarm.id != id);
+            if alarms.len() == count {
+                anyhow::bail!("No alarm with id {:?}", id);
+            }
+            save(&self.save_file_path, &alarms)?;
+        }
+        info!("Deleted alarm {:?}", id);
+        self.notify_subscribers();
+        Ok(())
+    }
+
+    /// Plays the alarm again later as a one-off timer
+    pub fn snooze(&self, request: SnoozeAlarm) -> Result<Alarm> {
+        let original = match &request.id {
+            Some(id) => self
+                .alarms()
+                .into_iter()
+                .find(|alarm| &alarm.id == id)
+                .or_else(|| {
+                    self.last_fired
+                        .lock()
+                        .unwrap()
+                        .clone()
+                        .filter(|alarm| &alarm.id == id)
+                })
+                .with_context(|| format!("No alarm with id {:?}", id))?,
+            None => self
+                .last_fired
+                .lock()
+                .unwrap()
+                .clone()
+                .context("No alarm fired yet")?,
+        };
+        let minutes = request.minutes.unwrap_or(DEFAULT_SNOOZE_MINUTES);
+        let at = minutes
+            .checked_mul(60)
+            .and_then(|seconds| checked_from_now(Duration::from_secs(seconds)))
+            .with_context(|| format!("Snoozing for {} minutes is out of range", minutes))?;
+        let alarm = Alarm {
+            id: format!("{}-snooze", original.id),
+            label: original.label.clone(),
+            schedule: AlarmSchedule::Timer { at },
+            action: original.action,
+        };
+        info!("Snoozing alarm {:?} for {} minutes", original.id, minutes);
+        self.insert(alarm.clone())?;
+        Ok(alarm)
+    }
+
+    fn insert(&self, alarm: Alarm) -> Result<()> {
+        {
+            let mut alarms = self.alarms.lock().unwrap();
+            alarms.retain(|scheduled| scheduled.alarm.id != alarm.id);
+            alarms.push(ScheduledAlarm {
+                next_fire: alarm.schedule.next_after(Local::now()),
+                alarm,
+            });
+            save(&self.save_file_path, &alarms)?;
+        }
+        self.notify_subscribers();
+        Ok(())
+    }
+
+    /// Due alarms are sent as speech requests so that quiet hours and do not disturb apply
+    pub fn start(&self, requests: UnboundedSender<SpeechRequest>) {
+        let scheduler = self.clone();
+        let mut changes = self.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let sleep = sleep_duration(scheduler.next_fire(), Local::now());
+                tokio::select! {
+                    _ = tokio::time::sleep(sleep) => scheduler.fire_due_alarms(&requests),
+                    changed = changes.changed() => if changed.is_err() {
+                        break;
+                    },
+                }
+            }
+        });
+    }
+
+    fn next_fire(&self) -> Option<DateTime<Local>> {
+        self.alarms
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|scheduled| scheduled.next_fire)
+            .min()
+    }
+
+    fn fire_due_alarms(&self, requests: &UnboundedSender<SpeechRequest>) {
+        let now = Local::now();
+        let mut due = vec![];
+        let timers_done = {
+            let mut alarms = self.alarms.lock().unwrap();
+            let count = alarms.len();
+            for scheduled in alarms.iter_mut() {
+                if scheduled.next_fire.map(|at| at <= now).unwrap_or(false) {
+                    due.push(scheduled.alarm.clone());
+                    scheduled.next_fire = scheduled.alarm.schedule.next_after(now);
+                }
+            }
+            // timers are done once they fire
+            alarms.retain(|scheduled| scheduled.next_fire.is_some());
+            if alarms.len() != count {
+                if let Err(e) = save(&self.save_file_path, &alarms) {
+                    error!("Failed to save alarms {:?}", e);
+                }
+            }
+            alarms.len() != count
+        };
+        for alarm in due {
+            self.fire(alarm, requests);
+        }
+        if timers_done {
+            self.notify_subscribers();
+        }
+    }
+
+    fn fire(&self, alarm: Alarm, requests: &UnboundedSender<SpeechRequest>) {
+        info!("Firing alarm {:?} {:?}", alarm.id, alarm.label);
+        let expires_at = alarm
+            .action
+            .ttl_seconds
+            .and_then(|ttl| checked_from_now(Duration::from_secs(ttl)));
+        // one request so that the sound and the text can't be separated by other messages
+        let request = SpeechRequest {
+            topic: format!("alarms/{}", alarm.id),
+            priority: alarm.action.priority,
+            expires_at,
+            steps: alarm.action.steps(),
+        };
+        if requests.send(request).is_err() {
+            error!("Alarm request channel closed");
+        }
+        self.last_fired.lock().unwrap().replace(alarm);
+    }
+}
+
+fn save(path: &Path, alarms: &[ScheduledAlarm]) -> Result<()> {
+    let alarms: Vec<&Alarm> = alarms.iter().map(|scheduled| &scheduled.alarm).collect();
+    write_json_atomically(path, &alarms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, LocalResult, NaiveDate, NaiveDateTime};
+
+    /// Central European time with the 2024 daylight saving changes
+    #[derive(Debug, Clone, Copy)]
+    struct TestZone;
+
+    impl TestZone {
+        fn standard() -> FixedOffset {
+            FixedOffset::east_opt(3600).unwrap()
+        }
+
+        fn summer() -> FixedOffset {
+            FixedOffset::east_opt(2 * 3600).unwrap()
+        }
+    }
+
+    impl TimeZone for TestZone {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            TestZone
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let valid: Vec<_> = [Self::summer(), Self::standard()]
+                .into_iter()
+                .filter(|offset| {
+                    let utc = *local - chrono::Duration::seconds(offset.local_minus_utc() as i64);
+                    self.offset_from_naive_datetime(&utc) == *offset
+                })
+                .collect();
+            match valid[..] {
+                [] => LocalResult::None,
+                [offset] => LocalResult::Single(offset),
+                [earliest, latest] => LocalResult::Ambiguous(earliest, latest),
+                _ => unreachable!(),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_naive_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_naive_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            let summer_start = naive_datetime(2024, 3, 31, 1, 0);
+            let summer_end = naive_datetime(2024, 10, 27, 1, 0);
+            if (summer_start..summer_end).contains(utc) {
+                Self::summer()
+            } else {
+                Self::standard()
+            }
+        }
+    }
+
+    fn naive_datetime(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<TestZone> {
+        TestZone
+            .from_local_datetime(&naive_datetime(year, month, day, hour, minute))
+            .earliest()
+            .unwrap()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn daily_fires_later_today_or_tomorrow() {
+        let after = local(2024, 1, 10, 6, 0);
+        assert_eq!(
+            next_time_of_day(time(7, 0), after, None),
+            Some(local(2024, 1, 10, 7, 0))
+        );
+        assert_eq!(
+            next_time_of_day(time(5, 0), after, None),
+            Some(local(2024, 1, 11, 5, 0))
+        );
+        // firing time itself isn't "after"
+        assert_eq!(
+            next_time_of_day(time(6, 0), after, None),
+            Some(local(2024, 1, 11, 6, 0))
+        );
+    }
+
+    #[test]
+    fn skipped_time_fires_the_next_day() {
+        // 02:30 doesn't exist on the 31st of March
+        let after = local(2024, 3, 30, 12, 0);
+        assert_eq!(
+            next_time_of_day(time(2, 30), after, None),
+            Some(local(2024, 4, 1, 2, 30))
+        );
+    }
+
+    #[test]
+    fn repeated_time_fires_once() {
+        let after = local(2024, 10, 26, 12, 0);
+        let first = next_time_of_day(time(2, 30), after, None).unwrap();
+        assert_eq!(first.offset(), &TestZone::summer());
+        assert_eq!(first.naive_local(), naive_datetime(2024, 10, 27, 2, 30));
+        assert_eq!(
+            next_time_of_day(time(2, 30), first, None),
+            Some(local(2024, 10, 28, 2, 30))
+        );
+    }
+
+    #[test]
+    fn keeps_wall_clock_time_across_daylight_saving() {
+        let before = next_time_of_day(time(7, 0), local(2024, 3, 29, 12, 0), None).unwrap();
+        let after = next_time_of_day(time(7, 0), before, None).unwrap();
+        assert_eq!(after.naive_local(), naive_datetime(2024, 3, 31, 7, 0));
+        assert_eq!(after.signed_duration_since(before).num_hours(), 23);
+    }
+
+    #[test]
+    fn weekly_fires_on_listed_weekdays() {
+        let weekdays = [Weekday::Mon, Weekday::Fri];
+        // the 8th of January 2024 is a Monday
+        let monday_morning = local(2024, 1, 8, 6, 0);
+        assert_eq!(
+            next_time_of_day(time(7, 0), monday_morning, Some(&weekdays)),
+            Some(local(2024, 1, 8, 7, 0))
+        );
+        let monday_evening = local(2024, 1, 8, 20, 0);
+        assert_eq!(
+            next_time_of_day(time(7, 0), monday_evening, Some(&weekdays)),
+            Some(local(2024, 1, 12, 7, 0))
+        );
+        let friday_evening = local(2024, 1, 12, 20, 0);
+        assert_eq!(
+            next_time_of_day(time(7, 0), friday_evening, Some(&[Weekday::Fri])),
+            Some(local(2024, 1, 19, 7, 0))
+        );
+        assert_eq!(
+            next_time_of_day(time(7, 0), monday_morning, Some(&[])),
+            None
+        );
+    }
+
+    #[test]
+    fn five_field_cron_gets_seconds() {
+        let after = Utc.from_naive_datetime(&naive_datetime(2024, 1, 8, 0, 0));
+        let schedule = parse_cron("30 7 * * Mon-Fri").unwrap();
+        assert_eq!(
+            schedule.after(&after).next(),
+            Some(Utc.from_naive_datetime(&naive_datetime(2024, 1, 8, 7, 30)))
+        );
+    }
+
+    #[test]
+    fn six_and_seven_field_cron_are_kept() {
+        let after = Utc.from_naive_datetime(&naive_datetime(2024, 1, 8, 0, 0));
+        let schedule = parse_cron("15 30 7 * * *").unwrap();
+        let next = schedule.after(&after).next().unwrap();
+        assert_eq!(
+            next.naive_utc(),
+            naive_datetime(2024, 1, 8, 7, 30) + chrono::Duration::seconds(15)
+        );
+        let schedule = parse_cron("0 0 12 1 1 * 2030").unwrap();
+        assert_eq!(
+            schedule.after(&after).next(),
+            Some(Utc.from_naive_datetime(&naive_datetime(2030, 1, 1, 12, 0)))
+        );
+    }
+
+    #[test]
+    fn invalid_cron_is_rejected() {
+        assert!(parse_cron("every morning").is_err());
+        assert!(parse_cron("61 * * * *").is_err());
+        assert!(parse_cron("").is_err());
+    }
+
+    fn scheduler(dir: &tempfile::TempDir) -> AlarmScheduler {
+        AlarmScheduler::load(dir.path().join("alarms.json"))
+    }
+
+    fn create_alarm(json: serde_json::Value) -> CreateAlarm {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn out_of_range_durations_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let scheduler = scheduler(&dir);
+        for in_seconds in [u64::MAX, 1 << 50] {
+            let request = create_alarm(serde_json::json!({"in_seconds": in_seconds, "text": "hi"}));
+            assert!(scheduler.create(request).is_err());
+        }
+        let request = create_alarm(
+            serde_json::json!({"in_seconds": 60, "text": "hi", "ttl_seconds": u64::MAX}),
+        );
+        assert!(scheduler.create(request).is_err());
+
+        let alarm = scheduler
+            .create(create_alarm(
+                serde_json::json!({"in_seconds": 60, "text": "hi"}),
+            ))
+            .unwrap();
+        let snooze = SnoozeAlarm {
+            id: Some(alarm.id),
+            minutes: Some(u64::MAX),
+        };
+        assert!(scheduler.snooze(snooze).is_err());
+        assert_eq!(scheduler.alarms().len(), 1);
+    }
+
+    #[test]
+    fn alarms_survive_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let alarm = scheduler(&dir)
+            .create(create_alarm(serde_json::json!({
+                "schedule": {"type": "daily", "time": "07:00"},
+                "text": "Wake up",
+                "priority": "high",
+            })))
+            .unwrap();
+        let alarms = scheduler(&dir).alarms();
+        assert_eq!(alarms.len(), 1);
+        assert_eq!(alarms[0].id, alarm.id);
+        assert_eq!(alarms[0].action.priority, Priority::High);
+    }
+
+    #[test]
+    fn sound_and_text_fire_as_one_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let scheduler = scheduler(&dir);
+        let alarm = scheduler
+            .create(create_alarm(serde_json::json!({
+                "in_seconds": 60,
+                "sound": "chimes/ding.mp3",
+                "text": "Tea is ready",
+            })))
+            .unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        scheduler.fire(alarm, &sender);
+
+        let request = receiver.try_recv().unwrap();
+        assert!(matches!(
+            request.steps.as_slice(),
+            [SequenceStep::PlayFile { .. }, SequenceStep::Say { .. }]
+        ));
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -1,17 +1,32 @@
+use crate::audio_processing::{trim_silence, SILENCE_TRIMMING_VERSION};
+use crate::configuration::SilenceTrimmingConfig;
 use crate::error::{HomeSpeakError, Result};
-use crate::speech_service::Playable;
+use crate::loudness::{measure_encoded, Loudness};
+use crate::speech_service::{MeasuredPlayable, Playable};
 use crate::AUDIO_FILE_EXTENSION;
+use std::ffi::OsString;
 use std::fs::{self, File};
-use std::io::prelude::*;
-use std::path::Path;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::*;
+
+const LOUDNESS_FILE_EXTENSION: &str = "loudness.json";
+const PROCESSED_FILE_EXTENSION: &str = "wav";
+
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Debug, Clone)]
 pub struct AudioCache {
     cache_dir_path: Option<String>,
+    silence_trimming: SilenceTrimmingConfig,
 }
 
 impl AudioCache {
-    pub fn new(cache_dir_path: String) -> Result<AudioCache> {
+    pub fn new(
+        cache_dir_path: String,
+        silence_trimming: SilenceTrimmingConfig,
+    ) -> Result<AudioCache> {
         let path = Path::new(&cache_dir_path);
         fs::create_dir_all(path)?;
         if !path.exists() {
@@ -19,39 +34,193 @@ impl AudioCache {
         }
         Ok(AudioCache {
             cache_dir_path: Some(cache_dir_path),
+            silence_trimming,
         })
     }
 
-    pub fn new_without_cache() -> AudioCache {
+    pub fn new_without_cache(silence_trimming: SilenceTrimmingConfig) -> AudioCache {
         AudioCache {
             cache_dir_path: None,
+            silence_trimming,
+        }
+    }
+
+    /// Processing settings are part of the name so that changing them invalidates old entries
+    fn entry_name(&self, key: &str) -> String {
+        if self.silence_trimming.enabled {
+            format!(
+                "{}-trim{}_{}db_{}ms",
+                key,
+                SILENCE_TRIMMING_VERSION,
+                self.silence_trimming.threshold_db,
+                self.silence_trimming.padding_ms
+            )
+        } else {
+            key.to_owned()
+        }
+    }
+
+    fn audio_extension(&self) -> &'static str {
+        if self.silence_trimming.enabled {
+            PROCESSED_FILE_EXTENSION
+        } else {
+            AUDIO_FILE_EXTENSION
         }
     }
 
-    pub fn get(&self, key: &str) -> Option<Box<dyn Playable>> {
+    fn file_path(cache_dir_path: &str, entry_name: &str, extension: &str) -> PathBuf {
+        Path::new(cache_dir_path).join(format!("{}.{}", entry_name, extension))
+    }
+
+    /// Entries from before loudness was recorded get decoded and measured
+    /// so this runs on the blocking thread pool
+    pub async fn get(&self, key: &str) -> Option<Box<dyn Playable>> {
+        let cache = self.clone();
+        let key = key.to_owned();
+        tokio::task::spawn_blocking(move || cache.get_blocking(&key))
+            .await
+            .map_err(|e| error!("Audio cache lookup panicked {:?}", e))
+            .ok()?
+    }
+
+    fn get_blocking(&self, key: &str) -> Option<Box<dyn Playable>> {
         let cache_dir_path = match &self.cache_dir_path {
             Some(path) => path,
             None => return None,
         };
-        let path = Path::new(cache_dir_path);
-        let file_path = path.join(format!("{}.{}", key, AUDIO_FILE_EXTENSION));
-        if let Ok(file) = File::open(file_path) {
-            Some(Box::new(file))
+        let entry_name = self.entry_name(key);
+        let file_path = Self::file_path(cache_dir_path, &entry_name, self.audio_extension());
+        let mut file = File::open(file_path).ok()?;
+
+        let loudness_path = Self::file_path(cache_dir_path, &entry_name, LOUDNESS_FILE_EXTENSION);
+        let loudness = match read_loudness(&loudness_path) {
+            Some(loudness) => Some(loudness),
+            None => {
+                // entry from before loudness was recorded
+                let loudness = file.as_bytes().ok().and_then(measure_encoded);
+                if let Some(loudness) = &loudness {
+                    if let Err(e) = write_loudness(&loudness_path, loudness) {
+                        warn!("Failed to store loudness for {} {:?}", key, e);
+                    }
+                }
+                loudness
+            }
+        };
+        Some(MeasuredPlayable::boxed(file, loudness))
+    }
+
+    /// Process and store audio and return the result ready for playback
+    ///
+    /// Trimming and loudness measurement decode the whole clip
+    /// so this runs on the blocking thread pool.
+    pub async fn set(&self, key: &str, contents: Vec<u8>) -> Result<Box<dyn Playable>> {
+        let cache = self.clone();
+        let key = key.to_owned();
+        tokio::task::spawn_blocking(move || cache.set_blocking(&key, contents)).await?
+    }
+
+    fn set_blocking(&self, key: &str, contents: Vec<u8>) -> Result<Box<dyn Playable>> {
+        let contents = if self.silence_trimming.enabled {
+            trim_silence(contents.clone(), &self.silence_trimming).unwrap_or_else(|| {
+                warn!("Failed to trim silence of {}. Storing it as is", key);
+                contents
+            })
         } else {
-            None
+            contents
+        };
+        let loudness = measure_encoded(contents.clone());
+        if let Some(cache_dir_path) = &self.cache_dir_path {
+            let entry_name = self.entry_name(key);
+            // loudness first so that the audio never shows up without it
+            if let Some(loudness) = &loudness {
+                let loudness_path =
+                    Self::file_path(cache_dir_path, &entry_name, LOUDNESS_FILE_EXTENSION);
+                write_loudness(&loudness_path, loudness)?;
+            }
+            let file_path = Self::file_path(cache_dir_path, &entry_name, self.audio_extension());
+            write_atomically(&file_path, &contents)?;
         }
+        Ok(MeasuredPlayable::boxed(Cursor::new(contents), loudness))
     }
+}
 
-    pub fn set(&self, key: &str, contents: Vec<u8>) -> Result<()> {
-        let cache_dir_path = match &self.cache_dir_path {
-            Some(path) => path,
-            None => return Ok(()),
+fn read_loudness(path: &Path) -> Option<Loudness> {
+    let file = File::open(path).ok()?;
+    serde_json::from_reader(file).ok()
+}
+
+fn write_loudness(path: &Path, loudness: &Loudness) -> Result<()> {
+    write_atomically(path, &serde_json::to_vec(loudness)?)
+}
+
+/// Write to a temporary file next to the entry and rename it into place
+///
+/// Lookups never see half written entries. Temporary names are unique
+/// so that concurrent writes of the same entry don't mix.
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp_path = path.with_file_name(temp_name);
+    if let Err(error) = fs::write(&temp_path, contents).and_then(|_| fs::rename(&temp_path, path)) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(error.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(dir: &tempfile::TempDir) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn writes_replace_entries_without_leftovers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.mp3");
+        write_atomically(&path, b"first").unwrap();
+        write_atomically(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert_eq!(entries(&dir), ["key.mp3"]);
+    }
+
+    #[test]
+    fn concurrent_writes_of_one_entry_stay_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.mp3");
+        let contents: Vec<Vec<u8>> = (0..8_u8).map(|i| vec![i; 64 * 1024]).collect();
+        std::thread::scope(|scope| {
+            for data in &contents {
+                let path = &path;
+                scope.spawn(move || write_atomically(path, data).unwrap());
+            }
+        });
+        assert!(contents.contains(&fs::read(&path).unwrap()));
+        assert_eq!(entries(&dir), ["key.mp3"]);
+    }
+
+    #[test]
+    fn loudness_sidecar_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.loudness.json");
+        let loudness = Loudness {
+            integrated_lufs: -20.5,
+            sample_peak: 0.75,
         };
-        let path = Path::new(cache_dir_path);
-        let file_path = path.join(format!("{}.{}", key, AUDIO_FILE_EXTENSION));
-        let mut file = File::create(file_path)?;
-        file.write_all(&contents)?;
-        file.flush()?;
-        Ok(())
+        write_loudness(&path, &loudness).unwrap();
+        assert_eq!(read_loudness(&path), Some(loudness));
+        assert_eq!(entries(&dir), ["key.loudness.json"]);
     }
 }
@@ -0,0 +1,138 @@
+use crate::configuration::SilenceTrimmingConfig;
+use rodio::Source;
+use std::io::Cursor;
+
+/// Bump when trimming changes so that old cache entries get replaced
+pub const SILENCE_TRIMMING_VERSION: u32 = 1;
+
+/// 16 bit PCM wav
+pub fn encode_wav(samples: &[i16], channels: u16, sample_rate: u32) -> Vec<u8> {
+    let block_align = channels * 2;
+    let data_length = (samples.len() * 2) as u32;
+    let mut wav = Vec::with_capacity(44 + data_length as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_length).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16_u32.to_le_bytes());
+    // PCM
+    wav.extend_from_slice(&1_u16.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    // byte rate
+    wav.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    // bits per sample
+    wav.extend_from_slice(&16_u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_length.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+/// Decode audio, cut quiet parts from both ends and encode the rest as wav
+///
+/// Returns None if the audio can't be decoded or is silent.
+pub fn trim_silence(data: Vec<u8>, config: &SilenceTrimmingConfig) -> Option<Vec<u8>> {
+    let decoder = rodio::Decoder::new(Cursor::new(data)).ok()?;
+    let channels = decoder.channels().max(1);
+    let sample_rate = decoder.sample_rate();
+    let samples: Vec<i16> = decoder.collect();
+
+    let threshold = 10_f64.powf(config.threshold_db / 20.0) * i16::MAX as f64;
+    let is_loud = |frame: &[i16]| {
+        frame
+            .iter()
+            .any(|sample| (*sample as f64).abs() > threshold)
+    };
+    let frame_length = channels as usize;
+    let frames: Vec<&[i16]> = samples.chunks(frame_length).collect();
+    let first_loud = frames.iter().position(|frame| is_loud(frame))?;
+    let last_loud = frames.iter().rposition(|frame| is_loud(frame))?;
+
+    let padding_frames = (config.padding_ms * sample_rate as u64 / 1000) as usize;
+    let start = first_loud.saturating_sub(padding_frames);
+    let end = (last_loud + 1 + padding_frames).min(frames.len());
+
+    let trimmed = &samples[start * frame_length..(end * frame_length).min(samples.len())];
+    Some(encode_wav(trimmed, channels, sample_rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 1000;
+    const WAV_HEADER_LENGTH: usize = 44;
+
+    fn config(threshold_db: f64, padding_ms: u64) -> SilenceTrimmingConfig {
+        SilenceTrimmingConfig {
+            enabled: true,
+            threshold_db,
+            padding_ms,
+        }
+    }
+
+    /// Silence, a loud part and silence again
+    fn padded_tone(leading: usize, loud: usize, trailing: usize, level: i16) -> Vec<i16> {
+        let mut samples = vec![0; leading];
+        samples.extend((0..loud).map(|i| if i % 2 == 0 { level } else { -level }));
+        samples.extend(vec![0; trailing]);
+        samples
+    }
+
+    fn trimmed_samples(samples: &[i16], channels: u16, config: &SilenceTrimmingConfig) -> usize {
+        let trimmed = trim_silence(encode_wav(samples, channels, SAMPLE_RATE), config).unwrap();
+        (trimmed.len() - WAV_HEADER_LENGTH) / 2
+    }
+
+    #[test]
+    fn trims_both_ends() {
+        let samples = padded_tone(1000, 500, 2000, 10_000);
+        assert_eq!(trimmed_samples(&samples, 1, &config(-40.0, 0)), 500);
+    }
+
+    #[test]
+    fn keeps_padding_around_audio() {
+        let samples = padded_tone(1000, 500, 2000, 10_000);
+        // 10ms at 1kHz is 10 samples on each side
+        assert_eq!(trimmed_samples(&samples, 1, &config(-40.0, 10)), 520);
+    }
+
+    #[test]
+    fn padding_is_limited_by_the_clip() {
+        let samples = padded_tone(5, 500, 5, 10_000);
+        assert_eq!(trimmed_samples(&samples, 1, &config(-40.0, 100)), 510);
+    }
+
+    #[test]
+    fn samples_below_threshold_count_as_silence() {
+        // -40 dB is about 328
+        let mut samples = padded_tone(100, 100, 0, 200);
+        samples.extend(padded_tone(0, 100, 100, 10_000));
+        assert_eq!(trimmed_samples(&samples, 1, &config(-40.0, 0)), 100);
+        // -50 dB is about 104
+        assert_eq!(trimmed_samples(&samples, 1, &config(-50.0, 0)), 200);
+    }
+
+    #[test]
+    fn trims_whole_frames() {
+        // loud sample only in the right channel
+        let samples = [0, 0, 0, 0, 0, 10_000, 10_000, 0, 0, 0];
+        assert_eq!(trimmed_samples(&samples, 2, &config(-40.0, 0)), 4);
+    }
+
+    #[test]
+    fn silence_returns_none() {
+        let silence = encode_wav(&[0; 1000], 1, SAMPLE_RATE);
+        assert!(trim_silence(silence, &config(-40.0, 10)).is_none());
+        let quiet = encode_wav(&padded_tone(0, 1000, 0, 100), 1, SAMPLE_RATE);
+        assert!(trim_silence(quiet, &config(-40.0, 10)).is_none());
+    }
+
+    #[test]
+    fn invalid_audio_returns_none() {
+        assert!(trim_silence(b"not audio".to_vec(), &config(-40.0, 10)).is_none());
+    }
+}
@@ -8,8 +8,10 @@ use home_speak::{
     logging::{set_global_tracing_zenoh_subscriber, setup_tracing},
     mqtt::start_mqtt_service,
     speech_service::{
-        AudioMessage, AudioRepository, AudioService, ElevenSpeechService, SpeechService, TtsService,
+        AudioMessage, AudioRepository, AudioService, ElevenSpeechService, SequencePlayer,
+        SpeechService,
     },
+    startup::start_announcement,
     template_messages::TemplateEngine,
 };
 use rumqttc::AsyncClient;
@@ -36,7 +38,7 @@ async fn main() -> anyhow::Result<()> {
     let opts = Opts::parse();
     setup_tracing(opts.verbose, "home-speak");
 
-    let app_config = get_configuration(opts.config)?;
+    let app_config = get_configuration(opts.config.clone())?;
 
     // zenoh
     let zenoh_config = app_config.zenoh.get_zenoh_config()?;
@@ -53,48 +55,69 @@ async fn main() -> anyhow::Result<()> {
     let (audio_sender, mut audio_receiver) = unbounded_channel();
 
     let audio_cache = if let Some(cache_dir_path) = &app_config.tts_service_config.cache_dir_path {
-        audio_cache::AudioCache::new(cache_dir_path.clone())?
+        audio_cache::AudioCache::new(
+            cache_dir_path.clone(),
+            app_config.tts_service_config.silence_trimming.clone(),
+        )?
     } else {
-        audio_cache::AudioCache::new_without_cache()
+        audio_cache::AudioCache::new_without_cache(
+            app_config.tts_service_config.silence_trimming.clone(),
+        )
     };
 
-    let audio_service = AudioService::new(Some(audio_sender))?;
+    let audio_service = AudioService::new(Some(audio_sender), app_config.loudness.clone())?;
 
-    let mut speech_service = SpeechService::new_with_mqtt(
-        app_config.tts_service_config.google_api_key.clone(),
-        app_config.tts_service_config.azure_api_key.clone(),
+    let speech_service = SpeechService::new(
+        &app_config.tts_service_config,
         audio_cache.clone(),
         audio_service.clone(),
     )?;
 
     let eleven_speech_service = ElevenSpeechService::new(
-        app_config.tts_service_config.eleven_labs_api_key.clone(),
+        &app_config.tts_service_config,
         audio_cache,
         audio_service.clone(),
     )
     .await?;
 
-    let audio_repository_service =
-        AudioRepository::new(&app_config.audio_repository_path, audio_service.clone())?;
+    let audio_repository_service = AudioRepository::new(
+        &app_config.audio_repository_path,
+        &app_config.audio_library,
+        audio_service.clone(),
+    )?;
+    let _audio_repository_watcher = audio_repository_service.watch_for_changes()?;
+
+    let template_engine = TemplateEngine::new(
+        app_config.assistant_config.clone(),
+        app_config.templates_path.clone(),
+    );
+    let _template_watcher = template_engine.watch_for_changes()?;
 
-    let template_engine = TemplateEngine::new(app_config.assistant_config.clone());
+    let speech_service = Arc::new(speech_service);
 
     if !app_config.skip_intro {
-        let startup_message = template_engine.startup_message();
-        for message_part in startup_message {
-            speech_service.say(&message_part, TtsService::Azure).await?;
-        }
+        let sequence_player = SequencePlayer::new(
+            speech_service.clone(),
+            eleven_speech_service.clone(),
+            audio_repository_service.clone(),
+            audio_service.clone(),
+        );
+        start_announcement(
+            app_config.startup.clone(),
+            &template_engine,
+            sequence_player,
+        );
     }
 
-    let speech_service = Arc::new(tokio::sync::Mutex::new(speech_service));
-
     // TODO: I can't pass the client to the speech service since the speech service needs to be passed here....
     let client = start_mqtt_service(
         app_config.clone(),
+        opts.config,
         speech_service.clone(),
         eleven_speech_service,
         audio_service,
         audio_repository_service,
+        template_engine,
     )?;
 
     let audio_worker_task = tokio::spawn(async move {
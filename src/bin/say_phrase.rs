@@ -25,22 +25,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let app_config = get_configuration(opts.config)?;
 
     let audio_cache = if let Some(cache_dir_path) = &app_config.tts_service_config.cache_dir_path {
-        audio_cache::AudioCache::new(cache_dir_path.clone())?
+        audio_cache::AudioCache::new(
+            cache_dir_path.clone(),
+            app_config.tts_service_config.silence_trimming.clone(),
+        )?
     } else {
-        audio_cache::AudioCache::new_without_cache()
+        audio_cache::AudioCache::new_without_cache(
+            app_config.tts_service_config.silence_trimming.clone(),
+        )
     };
 
-    let audio_service = AudioService::new(None)?;
+    let audio_service = AudioService::new(None, app_config.loudness.clone())?;
 
     let speech_service = SpeechService::new(
-        app_config.tts_service_config.google_api_key,
-        app_config.tts_service_config.azure_api_key,
+        &app_config.tts_service_config,
         audio_cache,
-        audio_service,
+        audio_service.clone(),
     )?;
 
-    let speech_service_handle =
-        start_speech_service_worker(speech_service, app_config.tts_service_config.tts_service);
+    let speech_service_handle = start_speech_service_worker(
+        speech_service,
+        audio_service,
+        app_config.tts_service_config.tts_service,
+    );
 
     let phrases = opts.phrases.split(',');
     for phrase in phrases.into_iter().filter(|text| !text.is_empty()) {
@@ -66,14 +73,16 @@ impl SpeechServiceHandle {
 }
 
 fn start_speech_service_worker(
-    mut speech_service: SpeechService,
+    speech_service: SpeechService,
+    audio_service: AudioService,
     tts_service: TtsService,
 ) -> SpeechServiceHandle {
     let (sender, r) = unbounded::<String>();
 
     tokio::spawn(async move {
         for msg in r {
-            if let Err(e) = speech_service.say(&msg, tts_service).await {
+            let slot = audio_service.reserve_slot();
+            if let Err(e) = speech_service.say(&msg, tts_service, &slot).await {
                 error!("Speech service error {}", e);
             }
         }
@@ -1,7 +1,13 @@
-use crate::{error::HomeSpeakError, speech_service::TtsService};
+use crate::{
+    error::HomeSpeakError,
+    localization::{ClockFormat, Humanizer, Language},
+    quiet_hours::Priority,
+    speech_service::{AzureVoiceStyle, SequenceStep, SpeechProvider, TtsService},
+};
+use chrono::NaiveTime;
 use secrecy::Secret;
-use serde::Deserialize;
-use std::{path::PathBuf, str};
+use serde::{Deserialize, Deserializer, Serializer};
+use std::{collections::HashMap, path::PathBuf, str, time::Duration};
 use tracing::*;
 
 /// Use default config if no path is provided
@@ -36,6 +42,37 @@ pub struct AppConfig {
     pub mqtt: MqttConfig,
     pub audio_repository_path: PathBuf,
     #[serde(default)]
+    pub audio_library: AudioLibraryConfig,
+    #[serde(default)]
+    pub pre_roll: PreRollConfig,
+    #[serde(default)]
+    pub loudness: LoudnessConfig,
+    /// Named sequences that can be triggered over MQTT
+    #[serde(default)]
+    pub sequences: HashMap<String, Vec<SequenceStep>>,
+    #[serde(default)]
+    pub quiet_hours: QuietHoursConfig,
+    #[serde(default)]
+    pub dnd: DndConfig,
+    #[serde(default)]
+    pub message_filter: MessageFilterConfig,
+    #[serde(default)]
+    pub alarm_config: AlarmConfig,
+    /// Announcements fired automatically, keyed by name
+    ///
+    /// Reloaded over MQTT without a restart.
+    #[serde(default)]
+    pub schedules: HashMap<String, ScheduleConfig>,
+    /// Remembers when schedules last fired so that restarts don't fire them twice
+    #[serde(default = "default_schedule_state_path")]
+    pub schedule_state_path: PathBuf,
+    /// Directory of named message templates, one YAML file per template
+    #[serde(default)]
+    pub templates_path: Option<PathBuf>,
+    /// Announcement made on startup unless `skip_intro` is set
+    #[serde(default)]
+    pub startup: StartupConfig,
+    #[serde(default)]
     pub zenoh: HomeSpeakZenohConfig,
 }
 
@@ -45,13 +82,657 @@ pub struct TtsServiceConfig {
     pub azure_api_key: Secret<String>,
     pub eleven_labs_api_key: Secret<String>,
     pub cache_dir_path: Option<String>,
+    #[serde(default)]
+    pub silence_trimming: SilenceTrimmingConfig,
     pub tts_service: TtsService,
+    #[serde(default)]
+    pub eleven_labs: ElevenLabsConfig,
+    /// The Azure and Google clients don't expose their HTTP client
+    /// so only the total request time can be limited
+    #[serde(default)]
+    pub azure: TtsRequestConfig,
+    #[serde(default)]
+    pub google: TtsRequestConfig,
+    #[serde(default)]
+    pub text_chunking: TextChunkingConfig,
+    /// Number of say requests that can be synthesized at the same time
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
 }
 
-#[derive(Deserialize, Debug, Clone, Default)]
+const DEFAULT_ELEVEN_LABS_BASE_URL: &str = "https://api.elevenlabs.io";
+const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 5_000;
+const DEFAULT_READ_TIMEOUT_MS: u64 = 10_000;
+const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 20_000;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_INITIAL_BACKOFF_MS: u64 = 500;
+const DEFAULT_MAX_BACKOFF_MS: u64 = 8_000;
+const DEFAULT_RETRY_DEADLINE_MS: u64 = 60_000;
+const DEFAULT_MAX_CHUNK_LENGTH: usize = 400;
+const DEFAULT_MAX_CONCURRENT_CHUNKS: usize = 3;
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 4;
+
+fn default_eleven_labs_base_url() -> String {
+    DEFAULT_ELEVEN_LABS_BASE_URL.to_owned()
+}
+
+const fn default_connect_timeout_ms() -> u64 {
+    DEFAULT_CONNECT_TIMEOUT_MS
+}
+
+const fn default_read_timeout_ms() -> u64 {
+    DEFAULT_READ_TIMEOUT_MS
+}
+
+const fn default_request_timeout_ms() -> u64 {
+    DEFAULT_REQUEST_TIMEOUT_MS
+}
+
+const fn default_max_retries() -> u32 {
+    DEFAULT_MAX_RETRIES
+}
+
+const fn default_initial_backoff_ms() -> u64 {
+    DEFAULT_INITIAL_BACKOFF_MS
+}
+
+const fn default_max_backoff_ms() -> u64 {
+    DEFAULT_MAX_BACKOFF_MS
+}
+
+const fn default_retry_deadline_ms() -> u64 {
+    DEFAULT_RETRY_DEADLINE_MS
+}
+
+const fn default_max_chunk_length() -> usize {
+    DEFAULT_MAX_CHUNK_LENGTH
+}
+
+const fn default_max_concurrent_chunks() -> usize {
+    DEFAULT_MAX_CONCURRENT_CHUNKS
+}
+
+const fn default_max_concurrent_requests() -> usize {
+    DEFAULT_MAX_CONCURRENT_REQUESTS
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ElevenLabsConfig {
+    /// Can be pointed at a local mock or a proxy
+    #[serde(default = "default_eleven_labs_base_url")]
+    pub base_url: String,
+    #[serde(default = "default_connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+    /// Maximum time to wait for the response headers or the next chunk of data
+    #[serde(default = "default_read_timeout_ms")]
+    pub read_timeout_ms: u64,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+impl Default for ElevenLabsConfig {
+    fn default() -> Self {
+        Self {
+            base_url: default_eleven_labs_base_url(),
+            connect_timeout_ms: DEFAULT_CONNECT_TIMEOUT_MS,
+            read_timeout_ms: DEFAULT_READ_TIMEOUT_MS,
+            retry: RetryConfig::default(),
+        }
+    }
+}
+
+impl ElevenLabsConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout_ms)
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_millis(self.read_timeout_ms)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TtsRequestConfig {
+    #[serde(default = "default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+impl Default for TtsRequestConfig {
+    fn default() -> Self {
+        Self {
+            request_timeout_ms: DEFAULT_REQUEST_TIMEOUT_MS,
+            retry: RetryConfig::default(),
+        }
+    }
+}
+
+impl TtsRequestConfig {
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_millis(self.request_timeout_ms)
+    }
+}
+
+/// Retry policy for transient failures such as HTTP 429 or 5xx
+#[derive(Deserialize, Debug, Clone)]
+pub struct RetryConfig {
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Total time budget for all attempts including backoff
+    #[serde(default = "default_retry_deadline_ms")]
+    pub deadline_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff_ms: DEFAULT_INITIAL_BACKOFF_MS,
+            max_backoff_ms: DEFAULT_MAX_BACKOFF_MS,
+            deadline_ms: DEFAULT_RETRY_DEADLINE_MS,
+        }
+    }
+}
+
+/// Long messages are split into sentences which are synthesized concurrently
+/// and played in order as soon as the first one is ready
+#[derive(Deserialize, Debug, Clone)]
+pub struct TextChunkingConfig {
+    /// Sentences longer than this many characters are split between words
+    #[serde(default = "default_max_chunk_length")]
+    pub max_chunk_length: usize,
+    #[serde(default = "default_max_concurrent_chunks")]
+    pub max_concurrent_chunks: usize,
+}
+
+impl Default for TextChunkingConfig {
+    fn default() -> Self {
+        Self {
+            max_chunk_length: DEFAULT_MAX_CHUNK_LENGTH,
+            max_concurrent_chunks: DEFAULT_MAX_CONCURRENT_CHUNKS,
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn deadline(&self) -> Duration {
+        Duration::from_millis(self.deadline_ms)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct AssistantConfig {
     pub name: String,
     pub primary_user_name: String,
+    /// Language of dates, times and the startup message
+    #[serde(default)]
+    pub language: Language,
+    #[serde(default)]
+    pub clock: ClockFormat,
+    /// Say "quarter past nine" instead of "9:15"
+    #[serde(default = "default_spoken_time")]
+    pub spoken_time: bool,
+}
+
+const DEFAULT_SPOKEN_TIME: bool = true;
+
+const fn default_spoken_time() -> bool {
+    DEFAULT_SPOKEN_TIME
+}
+
+impl Default for AssistantConfig {
+    fn default() -> Self {
+        Self {
+            name: String::default(),
+            primary_user_name: String::default(),
+            language: Language::default(),
+            clock: ClockFormat::default(),
+            spoken_time: DEFAULT_SPOKEN_TIME,
+        }
+    }
+}
+
+impl AssistantConfig {
+    pub fn humanizer(&self) -> Humanizer {
+        Humanizer::new(self.language, self.clock, self.spoken_time)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AudioLibraryConfig {
+    /// Sounds whose path contains any of these are never picked at random
+    #[serde(default)]
+    pub random_exclude: Vec<String>,
+    /// Allow uploading and deleting sounds over MQTT
+    #[serde(default)]
+    pub allow_remote_changes: bool,
+}
+
+const DEFAULT_SILENCE_THRESHOLD_DB: f64 = -50.0;
+const DEFAULT_SILENCE_PADDING_MS: u64 = 50;
+
+const fn default_silence_threshold_db() -> f64 {
+    DEFAULT_SILENCE_THRESHOLD_DB
+}
+
+const fn default_silence_padding_ms() -> u64 {
+    DEFAULT_SILENCE_PADDING_MS
+}
+
+/// Remove leading and trailing silence from synthesized speech before caching it
+#[derive(Deserialize, Debug, Clone)]
+pub struct SilenceTrimmingConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Samples quieter than this count as silence
+    #[serde(default = "default_silence_threshold_db")]
+    pub threshold_db: f64,
+    /// Silence kept around the audio so that soft sounds at the edges don't get cut
+    #[serde(default = "default_silence_padding_ms")]
+    pub padding_ms: u64,
+}
+
+impl Default for SilenceTrimmingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold_db: DEFAULT_SILENCE_THRESHOLD_DB,
+            padding_ms: DEFAULT_SILENCE_PADDING_MS,
+        }
+    }
+}
+
+const DEFAULT_LOUDNESS_TARGET_LUFS: f64 = -18.0;
+const DEFAULT_LOUDNESS_MAX_GAIN_DB: f64 = 12.0;
+const DEFAULT_LOUDNESS_PEAK_CEILING_DB: f64 = -1.0;
+
+const fn default_true() -> bool {
+    true
+}
+
+const fn default_loudness_target_lufs() -> f64 {
+    DEFAULT_LOUDNESS_TARGET_LUFS
+}
+
+const fn default_loudness_max_gain_db() -> f64 {
+    DEFAULT_LOUDNESS_MAX_GAIN_DB
+}
+
+const fn default_loudness_peak_ceiling_db() -> f64 {
+    DEFAULT_LOUDNESS_PEAK_CEILING_DB
+}
+
+/// Play everything at the same perceived loudness
+#[derive(Deserialize, Debug, Clone)]
+pub struct LoudnessConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_loudness_target_lufs")]
+    pub target_lufs: f64,
+    /// Limits how much quiet clips get boosted
+    #[serde(default = "default_loudness_max_gain_db")]
+    pub max_gain_db: f64,
+    /// Gain is reduced so that the loudest sample stays below this
+    #[serde(default = "default_loudness_peak_ceiling_db")]
+    pub peak_ceiling_db: f64,
+}
+
+impl Default for LoudnessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            target_lufs: DEFAULT_LOUDNESS_TARGET_LUFS,
+            max_gain_db: DEFAULT_LOUDNESS_MAX_GAIN_DB,
+            peak_ceiling_db: DEFAULT_LOUDNESS_PEAK_CEILING_DB,
+        }
+    }
+}
+
+const DEFAULT_PRE_ROLL_COOLDOWN_MS: u64 = 30_000;
+
+const fn default_pre_roll_cooldown_ms() -> u64 {
+    DEFAULT_PRE_ROLL_COOLDOWN_MS
+}
+
+/// Earcon played from the audio repository before speech
+#[derive(Deserialize, Debug, Clone)]
+pub struct PreRollConfig {
+    #[serde(default)]
+    pub sound: Option<String>,
+    /// Overrides keyed by route relative to the base route such as `say/eleven`
+    ///
+    /// The most specific route wins. An empty sound disables the pre-roll for that route.
+    #[serde(default)]
+    pub routes: HashMap<String, String>,
+    /// No pre-roll if the previous speech request came in less than this long ago
+    #[serde(default = "default_pre_roll_cooldown_ms")]
+    pub cooldown_ms: u64,
+}
+
+impl Default for PreRollConfig {
+    fn default() -> Self {
+        Self {
+            sound: None,
+            routes: HashMap::new(),
+            cooldown_ms: DEFAULT_PRE_ROLL_COOLDOWN_MS,
+        }
+    }
+}
+
+impl PreRollConfig {
+    pub fn cooldown(&self) -> Duration {
+        Duration::from_millis(self.cooldown_ms)
+    }
+}
+
+/// Times of day are written as `HH:MM` with optional seconds
+pub fn deserialize_time_of_day<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<NaiveTime, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&text, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(&text, "%H:%M:%S"))
+        .map_err(serde::de::Error::custom)
+}
+
+pub fn serialize_time_of_day<S: Serializer>(
+    time: &NaiveTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&time.format("%H:%M:%S").to_string())
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct VolumeScheduleEntry {
+    /// Volume applies from this time until the start of the next entry
+    #[serde(deserialize_with = "deserialize_time_of_day")]
+    pub from: NaiveTime,
+    pub volume: f32,
+}
+
+/// What happens to messages that arrive during quiet hours
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QuietMode {
+    #[default]
+    Drop,
+    /// Hold messages until quiet hours end
+    Delay,
+    MinVolume,
+}
+
+const DEFAULT_QUIET_MIN_VOLUME: f32 = 0.1;
+
+const fn default_quiet_min_volume() -> f32 {
+    DEFAULT_QUIET_MIN_VOLUME
+}
+
+const fn default_quiet_bypass_priority() -> Priority {
+    Priority::Urgent
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct QuietWindowConfig {
+    #[serde(deserialize_with = "deserialize_time_of_day")]
+    pub start: NaiveTime,
+    /// Can be earlier than start for windows that span midnight
+    #[serde(deserialize_with = "deserialize_time_of_day")]
+    pub end: NaiveTime,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct QuietHoursConfig {
+    /// Entries can be in any order. The last entry of the day carries over past midnight
+    #[serde(default)]
+    pub volume_schedule: Vec<VolumeScheduleEntry>,
+    #[serde(default)]
+    pub window: Option<QuietWindowConfig>,
+    #[serde(default)]
+    pub mode: QuietMode,
+    /// Volume of messages played during quiet hours in [`QuietMode::MinVolume`]
+    #[serde(default = "default_quiet_min_volume")]
+    pub min_volume: f32,
+    /// Messages with at least this priority ignore quiet hours
+    #[serde(default = "default_quiet_bypass_priority")]
+    pub bypass_priority: Priority,
+}
+
+impl Default for QuietHoursConfig {
+    fn default() -> Self {
+        Self {
+            volume_schedule: vec![],
+            window: None,
+            mode: QuietMode::default(),
+            min_volume: DEFAULT_QUIET_MIN_VOLUME,
+            bypass_priority: Priority::Urgent,
+        }
+    }
+}
+
+const DEFAULT_INBOX_PATH: &str = "/var/lib/home_speak/inbox.json";
+const DEFAULT_DND_STATE_PATH: &str = "/var/lib/home_speak/dnd.json";
+
+fn default_inbox_path() -> PathBuf {
+    PathBuf::from(DEFAULT_INBOX_PATH)
+}
+
+fn default_dnd_state_path() -> PathBuf {
+    PathBuf::from(DEFAULT_DND_STATE_PATH)
+}
+
+const fn default_dnd_bypass_priority() -> Priority {
+    Priority::Urgent
+}
+
+/// While do not disturb is on messages are kept in an inbox
+#[derive(Deserialize, Debug, Clone)]
+pub struct DndConfig {
+    #[serde(default = "default_inbox_path")]
+    pub inbox_path: PathBuf,
+    /// Remembers the do not disturb override across restarts
+    #[serde(default = "default_dnd_state_path")]
+    pub state_path: PathBuf,
+    /// Messages with at least this priority still play
+    #[serde(default = "default_dnd_bypass_priority")]
+    pub bypass_priority: Priority,
+}
+
+impl Default for DndConfig {
+    fn default() -> Self {
+        Self {
+            inbox_path: default_inbox_path(),
+            state_path: default_dnd_state_path(),
+            bypass_priority: Priority::Urgent,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateMode {
+    /// Duplicates within the window of the first message are dropped
+    #[default]
+    Drop,
+    /// Every duplicate restarts the window so a flapping source speaks once until it settles
+    Coalesce,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    pub max_messages: usize,
+    pub period_ms: u64,
+}
+
+impl RateLimitConfig {
+    pub fn period(&self) -> Duration {
+        Duration::from_millis(self.period_ms)
+    }
+}
+
+fn default_filtered_routes() -> Vec<String> {
+    vec!["say".to_owned(), "sequence".to_owned()]
+}
+
+/// Protects against flapping sensors and chatty automations
+#[derive(Deserialize, Debug, Clone)]
+pub struct MessageFilterConfig {
+    /// Routes relative to the base route that are filtered including their subroutes
+    #[serde(default = "default_filtered_routes")]
+    pub routes: Vec<String>,
+    /// Messages with the same normalized text within this window are duplicates. 0 disables it
+    #[serde(default)]
+    pub duplicate_window_ms: u64,
+    #[serde(default)]
+    pub duplicate_mode: DuplicateMode,
+    /// Limit per source if the message names one and per topic otherwise
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Overrides keyed by route relative to the base route. The most specific route wins
+    #[serde(default)]
+    pub route_rate_limits: HashMap<String, RateLimitConfig>,
+}
+
+impl Default for MessageFilterConfig {
+    fn default() -> Self {
+        Self {
+            routes: default_filtered_routes(),
+            duplicate_window_ms: 0,
+            duplicate_mode: DuplicateMode::default(),
+            rate_limit: None,
+            route_rate_limits: HashMap::new(),
+        }
+    }
+}
+
+impl MessageFilterConfig {
+    pub fn duplicate_window(&self) -> Duration {
+        Duration::from_millis(self.duplicate_window_ms)
+    }
+}
+
+const DEFAULT_ALARM_SAVE_FILE_PATH: &str = "/var/lib/home_speak/alarms.json";
+
+fn default_alarm_save_file_path() -> PathBuf {
+    PathBuf::from(DEFAULT_ALARM_SAVE_FILE_PATH)
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AlarmConfig {
+    #[serde(default = "default_alarm_save_file_path")]
+    pub save_file_path: PathBuf,
+}
+
+impl Default for AlarmConfig {
+    fn default() -> Self {
+        Self {
+            save_file_path: default_alarm_save_file_path(),
+        }
+    }
+}
+
+const DEFAULT_SCHEDULE_STATE_PATH: &str = "/var/lib/home_speak/schedules.json";
+
+fn default_schedule_state_path() -> PathBuf {
+    PathBuf::from(DEFAULT_SCHEDULE_STATE_PATH)
+}
+
+/// ```yaml
+/// good_morning:
+///   cron: "0 7 * * mon-fri"
+///   text: "Good morning {{ user_name }}, it's {{ date }}"
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+pub struct ScheduleConfig {
+    /// Five fields or six and seven with seconds and years
+    pub cron: String,
+    /// Template rendered when the schedule fires
+    pub text: String,
+    #[serde(default)]
+    pub provider: SpeechProvider,
+    /// Only used by Azure
+    #[serde(default)]
+    pub style: AzureVoiceStyle,
+    /// ElevenLabs voice name
+    #[serde(default)]
+    pub voice: Option<String>,
+    #[serde(default)]
+    pub priority: Priority,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupSection {
+    Greeting,
+    Date,
+    Interfaces,
+    Hostname,
+}
+
+const DEFAULT_STARTUP_SECTIONS: [StartupSection; 4] = [
+    StartupSection::Greeting,
+    StartupSection::Date,
+    StartupSection::Interfaces,
+    StartupSection::Hostname,
+];
+
+fn default_startup_sections() -> Vec<StartupSection> {
+    DEFAULT_STARTUP_SECTIONS.to_vec()
+}
+
+const DEFAULT_STARTUP_STATE_PATH: &str = "/var/lib/home_speak/startup.json";
+
+fn default_startup_state_path() -> PathBuf {
+    PathBuf::from(DEFAULT_STARTUP_STATE_PATH)
+}
+
+/// ```yaml
+/// sections: [greeting, interfaces]
+/// provider: eleven
+/// only_on_ip_change: true
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+pub struct StartupConfig {
+    /// Parts of the built in announcement in the order they are said
+    #[serde(default = "default_startup_sections")]
+    pub sections: Vec<StartupSection>,
+    /// Template said instead of the sections
+    ///
+    /// `context.ip_changed` and `context.previous_ip_addresses` describe the last boot.
+    #[serde(default)]
+    pub template: Option<String>,
+    #[serde(default)]
+    pub provider: SpeechProvider,
+    /// Only used by Azure
+    #[serde(default)]
+    pub style: AzureVoiceStyle,
+    /// ElevenLabs voice name
+    #[serde(default)]
+    pub voice: Option<String>,
+    /// Stay quiet if the IP addresses are the same as on the last boot
+    #[serde(default)]
+    pub only_on_ip_change: bool,
+    /// Remembers the IP addresses of the last announcement
+    #[serde(default = "default_startup_state_path")]
+    pub state_path: PathBuf,
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        Self {
+            sections: default_startup_sections(),
+            template: None,
+            provider: SpeechProvider::default(),
+            style: AzureVoiceStyle::default(),
+            voice: None,
+            only_on_ip_change: false,
+            state_path: default_startup_state_path(),
+        }
+    }
 }
 
 // weird serde default thing
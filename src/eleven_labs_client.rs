@@ -2,7 +2,12 @@ use anyhow::Context;
 use anyhow::Result;
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, future::Future, time::Duration};
+
+use crate::{
+    configuration::{ElevenLabsConfig, RetryConfig},
+    retry::{retry_decision, with_retry},
+};
 
 pub const DEFAULT_MODEL: &str = "eleven_multilingual_v2";
 
@@ -112,18 +117,71 @@ pub struct Invoice {
     next_payment_attempt_unix: i64,
 }
 
+/// Failed HTTP request
+///
+/// Unlike [`reqwest::Error`] this keeps the `Retry-After` header.
+#[derive(Debug, thiserror::Error)]
+#[error("request failed with status {status}")]
+pub struct HttpStatusError {
+    pub status: reqwest::StatusCode,
+    pub retry_after: Option<Duration>,
+}
+
+fn check_status(response: &reqwest::Response) -> std::result::Result<(), HttpStatusError> {
+    let status = response.status();
+    if status.is_client_error() || status.is_server_error() {
+        // Retry-After can also be an HTTP date but APIs tend to send seconds
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        Err(HttpStatusError {
+            status,
+            retry_after,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+async fn with_read_timeout<T, F>(read_timeout: Duration, future: F) -> Result<T>
+where
+    F: Future<Output = reqwest::Result<T>>,
+{
+    let result = tokio::time::timeout(read_timeout, future)
+        .await
+        .context("Request timed out")?;
+    Ok(result?)
+}
+
 #[derive(Debug, Clone)]
 pub struct ElevenLabsTtsClient {
     client: reqwest::Client,
     api_key: String,
+    base_url: String,
+    read_timeout: Duration,
+    retry: RetryConfig,
 }
 
 impl ElevenLabsTtsClient {
     pub fn new(api_key: String) -> Self {
-        ElevenLabsTtsClient {
-            client: reqwest::Client::new(),
+        Self::with_config(api_key, &ElevenLabsConfig::default())
+            .expect("Failed to create default http client")
+    }
+
+    pub fn with_config(api_key: String, config: &ElevenLabsConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(config.connect_timeout())
+            .build()?;
+        Ok(ElevenLabsTtsClient {
+            client,
             api_key,
-        }
+            base_url: config.base_url.trim_end_matches('/').to_owned(),
+            read_timeout: config.read_timeout(),
+            retry: config.retry.clone(),
+        })
     }
 
     pub async fn tts(
@@ -133,56 +191,217 @@ impl ElevenLabsTtsClient {
         voice_settings: Option<VoiceSettings>,
         model: &str,
     ) -> Result<Bytes> {
-        let url = format!("https://api.elevenlabs.io/v1/text-to-speech/{}", voice_id);
+        let url = format!("{}/v1/text-to-speech/{}", self.base_url, voice_id);
+        let url = url.as_str();
+        let voice_settings = &voice_settings;
+        with_retry(
+            "eleven_labs_tts",
+            &self.retry,
+            |error: &anyhow::Error| retry_decision(error.as_ref()),
+            |remaining| async move {
+                let read_timeout = self.read_timeout.min(remaining);
+                let resp = self
+                    .post_tts(url, text, voice_settings.clone(), model, read_timeout)
+                    .await?;
+                with_read_timeout(read_timeout, resp.bytes()).await
+            },
+        )
+        .await
+    }
 
+    /// Synthesize using the streaming endpoint
+    ///
+    /// Returns as soon as the response headers arrive so that the caller can
+    /// start playing the audio while the rest of it is still being generated.
+    /// Only the initial request is retried since the caller may already be playing the stream.
+    pub async fn tts_stream(
+        &self,
+        text: &str,
+        voice_id: &str,
+        voice_settings: Option<VoiceSettings>,
+        model: &str,
+    ) -> Result<TtsStream> {
+        let url = format!("{}/v1/text-to-speech/{}/stream", self.base_url, voice_id);
+        let url = url.as_str();
+        let voice_settings = &voice_settings;
+        let response = with_retry(
+            "eleven_labs_tts_stream",
+            &self.retry,
+            |error: &anyhow::Error| retry_decision(error.as_ref()),
+            |remaining| async move {
+                let read_timeout = self.read_timeout.min(remaining);
+                self.post_tts(url, text, voice_settings.clone(), model, read_timeout)
+                    .await
+            },
+        )
+        .await?;
+        Ok(TtsStream {
+            response,
+            read_timeout: self.read_timeout,
+        })
+    }
+
+    async fn post_tts(
+        &self,
+        url: &str,
+        text: &str,
+        voice_settings: Option<VoiceSettings>,
+        model: &str,
+        read_timeout: Duration,
+    ) -> Result<reqwest::Response> {
         let body = TtsRequest {
             text: text.to_owned(),
             model_id: Some(model.to_owned()),
             voice_settings,
         };
 
-        let resp = self
+        let request = self
             .client
             .post(url)
             .header("xi-api-key", self.api_key.clone())
             .header("accept", "audio/mpeg")
             .header("Content-Type", "application/json")
             .json(&body)
-            .send()
-            .await?;
-        resp.error_for_status_ref()
-            .context("Request failed with status")?;
-        let data = resp.bytes().await?;
-        Ok(data)
+            .send();
+        let resp = with_read_timeout(read_timeout, request).await?;
+        check_status(&resp).context("Request failed with status")?;
+        Ok(resp)
     }
 
     pub async fn voices(&self) -> Result<Voices> {
-        let resp = self
+        let request = self
             .client
-            .get("https://api.elevenlabs.io/v1/voices")
+            .get(format!("{}/v1/voices", self.base_url))
             .header("xi-api-key", self.api_key.clone())
             .header("accept", "application/json")
-            .send()
-            .await?;
+            .send();
+        let resp = with_read_timeout(self.read_timeout, request).await?;
         resp.error_for_status_ref()
             .context("Request failed with status")?;
-        let data = resp.json::<Voices>().await?;
+        let data = with_read_timeout(self.read_timeout, resp.json::<Voices>()).await?;
 
         Ok(data)
     }
 
     pub async fn get_subscription_info(&self) -> Result<Subscription> {
-        let resp = self
+        let request = self
             .client
-            .get("https://api.elevenlabs.io/v1/user/subscription")
+            .get(format!("{}/v1/user/subscription", self.base_url))
             .header("xi-api-key", self.api_key.clone())
             .header("accept", "application/json")
-            .send()
-            .await?;
+            .send();
+        let resp = with_read_timeout(self.read_timeout, request).await?;
         resp.error_for_status_ref()
             .context("Request failed with status")?;
-        let data = resp.json::<Subscription>().await?;
+        let data = with_read_timeout(self.read_timeout, resp.json::<Subscription>()).await?;
 
         Ok(data)
     }
 }
+
+/// Audio data of a streaming TTS request
+#[derive(Debug)]
+pub struct TtsStream {
+    response: reqwest::Response,
+    read_timeout: Duration,
+}
+
+impl TtsStream {
+    /// Returns the next chunk of audio data or `None` once the stream is done
+    pub async fn next_chunk(&mut self) -> Result<Option<Bytes>> {
+        with_read_timeout(self.read_timeout, self.response.chunk()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockResponse, MockServer};
+
+    fn client(server: &MockServer) -> ElevenLabsTtsClient {
+        let config = ElevenLabsConfig {
+            base_url: server.url().to_owned(),
+            read_timeout_ms: 1_000,
+            retry: RetryConfig {
+                max_retries: 2,
+                initial_backoff_ms: 1,
+                max_backoff_ms: 10,
+                deadline_ms: 5_000,
+            },
+            ..Default::default()
+        };
+        ElevenLabsTtsClient::with_config("key".to_owned(), &config).unwrap()
+    }
+
+    async fn read_all(stream: &mut TtsStream) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        while let Some(chunk) = stream.next_chunk().await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn stream_returns_whole_body() {
+        let server = MockServer::start(vec![MockResponse::new(200, b"audio data")]);
+        let mut stream = client(&server)
+            .tts_stream("hello", "voice", None, DEFAULT_MODEL)
+            .await
+            .unwrap();
+        assert_eq!(read_all(&mut stream).await.unwrap(), b"audio data");
+        assert_eq!(
+            server.requests(),
+            vec!["POST /v1/text-to-speech/voice/stream HTTP/1.1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn truncated_stream_fails() {
+        let server = MockServer::start(vec![MockResponse::new(200, b"audio data").truncate_at(5)]);
+        let mut stream = client(&server)
+            .tts_stream("hello", "voice", None, DEFAULT_MODEL)
+            .await
+            .unwrap();
+        assert!(read_all(&mut stream).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let server = MockServer::start(vec![
+            MockResponse::new(401, b"unauthorized"),
+            MockResponse::new(200, b"audio data"),
+        ]);
+        let error = client(&server)
+            .tts_stream("hello", "voice", None, DEFAULT_MODEL)
+            .await
+            .unwrap_err();
+        let status = error.downcast_ref::<HttpStatusError>().unwrap().status;
+        assert_eq!(status, reqwest::StatusCode::UNAUTHORIZED);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let server = MockServer::start(vec![
+            MockResponse::new(503, b"busy"),
+            MockResponse::new(429, b"slow down").header("Retry-After", "0"),
+            MockResponse::new(200, b"audio data"),
+        ]);
+        let data = client(&server)
+            .tts("hello", "voice", None, DEFAULT_MODEL)
+            .await
+            .unwrap();
+        assert_eq!(data.as_ref(), b"audio data");
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let server = MockServer::start(vec![MockResponse::new(500, b"error"); 4]);
+        assert!(client(&server)
+            .tts("hello", "voice", None, DEFAULT_MODEL)
+            .await
+            .is_err());
+        assert_eq!(server.requests().len(), 3);
+    }
+}
@@ -16,11 +16,17 @@ pub enum HomeSpeakError {
     FailedToCreateASink,
     #[error("failed to create an output stream")]
     FailedToCreateAnOutputStream,
-    // TODO: Propagate errors from google_tts
     #[error("google tts failed to synthesize")]
-    GoogleTtsError,
+    GoogleTtsError(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("tts request timed out")]
+    TtsRequestTimeout,
     #[error("azure tts error")]
-    AzureTtsError(#[from] azure_tts::TtsError),
+    AzureTtsError {
+        /// Set if Azure rejected the request
+        status: Option<reqwest::StatusCode>,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
     #[error("serialisation error")]
     SerializationError(#[from] serde_json::Error),
     #[error("time format parse error")]
@@ -33,6 +39,83 @@ pub enum HomeSpeakError {
     ReqwestError(#[from] reqwest::Error),
     #[error("Audio cache dir error")]
     AudioCacheDirError,
+    #[error("blocking task failed")]
+    BlockingTaskError(#[from] tokio::task::JoinError),
     #[error("Zenoh error {0:?}")]
     ZenohError(#[from] zenoh::Error),
 }
+
+impl HomeSpeakError {
+    /// Keeps the cause so that retries can tell transport errors from rejected requests
+    pub(crate) fn google_tts<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> Self {
+        HomeSpeakError::GoogleTtsError(error.into())
+    }
+
+    /// Records the HTTP status so that retries don't depend on how Azure nests its errors
+    pub(crate) fn azure_tts<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> Self {
+        let source = error.into();
+        HomeSpeakError::AzureTtsError {
+            status: crate::retry::http_status(source.as_ref()),
+            source,
+        }
+    }
+}
+
+/// Reasons for rejecting a sound lookup in the audio repository
+#[derive(Error, Debug)]
+pub enum AudioRepositoryError {
+    #[error("absolute paths are not allowed")]
+    AbsolutePath,
+    #[error("path leaves the audio repository")]
+    PathOutsideRepository,
+    #[error("sound not found")]
+    NotFound,
+    #[error("path is not a file")]
+    NotAFile,
+    #[error("sound names have to be a single file name with a supported extension")]
+    InvalidName,
+    #[error("failed to decode audio")]
+    InvalidAudio,
+}
+
+impl AudioRepositoryError {
+    /// Stable identifier used in error reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AudioRepositoryError::AbsolutePath => "absolute_path",
+            AudioRepositoryError::PathOutsideRepository => "path_outside_repository",
+            AudioRepositoryError::NotFound => "not_found",
+            AudioRepositoryError::NotAFile => "not_a_file",
+            AudioRepositoryError::InvalidName => "invalid_name",
+            AudioRepositoryError::InvalidAudio => "invalid_audio",
+        }
+    }
+}
+
+/// Reasons a message template can't be rendered
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum TemplateError {
+    #[error("unknown variable {0:?}")]
+    UnknownVariable(String),
+    #[error("unknown filter {0:?}")]
+    UnknownFilter(String),
+    #[error("filter {filter:?} can't be applied to {value:?}")]
+    InvalidFilterInput { filter: String, value: String },
+    #[error("template syntax error: {0}")]
+    Syntax(String),
+    #[error("unknown template {0:?}")]
+    UnknownTemplate(String),
+}
+
+impl TemplateError {
+    /// Stable identifier used in error reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TemplateError::UnknownVariable(_) => "unknown_variable",
+            TemplateError::UnknownFilter(_) => "unknown_filter",
+            TemplateError::InvalidFilterInput { .. } => "invalid_filter_input",
+            TemplateError::Syntax(_) => "template_syntax",
+            TemplateError::UnknownTemplate(_) => "unknown_template",
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{future::Future, path::Path, time::Duration};
+use tokio::sync::mpsc::unbounded_channel;
+use tracing::*;
+
+/// Editors and rsync produce a burst of events so wait for things to settle
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Run `on_change` once changes to `path` settle down
+///
+/// Watching stops when the returned watcher is dropped.
+pub fn watch_debounced<F, Fut>(
+    path: &Path,
+    recursive_mode: RecursiveMode,
+    mut on_change: F,
+) -> notify::Result<RecommendedWatcher>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (event_sender, mut event_receiver) = unbounded_channel();
+    let watched_path = path.to_owned();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if !event.kind.is_access() => {
+                let _ = event_sender.send(());
+            }
+            Ok(_) => (),
+            Err(error) => error!("Watch error for {:?} {:?}", watched_path, error),
+        })?;
+    watcher.watch(path, recursive_mode)?;
+    info!("Watching {:?} for changes", path);
+
+    tokio::spawn(async move {
+        while event_receiver.recv().await.is_some() {
+            while let Ok(Some(_)) =
+                tokio::time::timeout(WATCH_DEBOUNCE, event_receiver.recv()).await
+            {}
+            on_change().await;
+        }
+    });
+    Ok(watcher)
+}
@@ -0,0 +1,153 @@
+use crate::{
+    quiet_hours::Priority,
+    speech_service::SequenceStep,
+    state_file::{read_json_or_move_aside, write_json_atomically},
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tracing::*;
+
+/// Say request as it gets delayed or kept in the inbox
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpeechRequest {
+    pub topic: String,
+    pub priority: Priority,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Played back to back in one playback slot
+    pub steps: Vec<SequenceStep>,
+}
+
+impl SpeechRequest {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at <= Utc::now())
+            .unwrap_or(false)
+    }
+
+    /// Deadline for the player if the request expires
+    pub fn deadline(&self) -> Option<Instant> {
+        self.expires_at.map(|expires_at| {
+            let remaining = (expires_at - Utc::now()).to_std().unwrap_or_default();
+            Instant::now() + remaining
+        })
+    }
+
+    /// Text of the first spoken step
+    pub fn text(&self) -> Option<&str> {
+        self.steps.iter().find_map(|step| match step {
+            SequenceStep::Say { text, .. } => Some(text.as_str()),
+            _ => None,
+        })
+    }
+}
+
+/// Messages held back while do not disturb is on
+///
+/// Every change is written to disk so that messages survive restarts.
+#[derive(Debug, Clone)]
+pub struct Inbox {
+    path: Arc<PathBuf>,
+    messages: Arc<Mutex<Vec<SpeechRequest>>>,
+}
+
+impl Inbox {
+    /// Starts empty if the file doesn't exist or can't be parsed
+    pub fn load(path: PathBuf) -> Self {
+        let messages: Vec<SpeechRequest> = read_json_or_move_aside(&path).unwrap_or_default();
+        info!("Loaded inbox with {} messages", messages.len());
+        Self {
+            path: Arc::new(path),
+            messages: Arc::new(Mutex::new(messages)),
+        }
+    }
+
+    pub fn push(&self, message: SpeechRequest) -> Result<()> {
+        let mut messages = self.messages.lock().unwrap();
+        messages.push(message);
+        write_json_atomically(&self.path, &*messages)
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hand all messages to `queue` in arrival order and empty the inbox
+    ///
+    /// Messages are only removed once `queue` returns
+    /// so that a crash before they are queued doesn't lose them.
+    pub fn take_all<F: FnOnce(Vec<SpeechRequest>)>(&self, queue: F) -> Result<()> {
+        let mut messages = self.messages.lock().unwrap();
+        queue(messages.clone());
+        messages.clear();
+        write_json_atomically(&self.path, &*messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(topic: &str) -> SpeechRequest {
+        SpeechRequest {
+            topic: topic.to_owned(),
+            priority: Priority::Normal,
+            expires_at: None,
+            steps: vec![SequenceStep::Silence { duration_ms: 100 }],
+        }
+    }
+
+    #[test]
+    fn messages_are_removed_after_they_are_queued() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("inbox.json");
+        let inbox = Inbox::load(path.clone());
+        inbox.push(message("first")).unwrap();
+        inbox.push(message("second")).unwrap();
+
+        let mut queued = vec![];
+        inbox
+            .take_all(|messages| {
+                // still on disk in case we crash while queueing
+                assert_eq!(Inbox::load(path.clone()).len(), 2);
+                queued = messages;
+            })
+            .unwrap();
+
+        let topics: Vec<_> = queued
+            .iter()
+            .map(|message| message.topic.as_str())
+            .collect();
+        assert_eq!(topics, vec!["first", "second"]);
+        assert!(inbox.is_empty());
+        assert!(Inbox::load(path).is_empty());
+    }
+
+    #[test]
+    fn unreadable_inbox_is_not_overwritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("inbox.json");
+        std::fs::write(&path, b"[{\"topic\":").unwrap();
+        let inbox = Inbox::load(path.clone());
+        assert!(inbox.is_empty());
+        inbox.push(message("new")).unwrap();
+
+        let kept = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|entry| *entry != path)
+            .unwrap();
+        assert_eq!(std::fs::read(kept).unwrap(), b"[{\"topic\":");
+        assert_eq!(Inbox::load(path).len(), 1);
+    }
+}
@@ -1,10 +1,25 @@
+pub mod alarms;
 pub mod audio_cache;
+pub mod audio_processing;
 pub mod configuration;
 pub mod eleven_labs_client;
 pub mod error;
+pub mod file_watcher;
+pub mod inbox;
+pub mod localization;
 pub mod logging;
+pub mod loudness;
+#[cfg(test)]
+mod mock_server;
 pub mod mqtt;
+pub mod quiet_hours;
+pub mod retry;
+pub mod schedules;
 pub mod speech_service;
+pub mod startup;
+pub mod state_file;
 pub mod template_messages;
+pub mod templating;
+pub mod text_chunking;
 
 const AUDIO_FILE_EXTENSION: &str = "mp3";
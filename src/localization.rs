@@ -0,0 +1,791 @@
+//! Spoken dates, times and durations in the language of the assistant
+
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Timelike};
+use ordinal::Ordinal;
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Language {
+    #[default]
+    En,
+    De,
+    Cs,
+    Sk,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClockFormat {
+    #[default]
+    #[serde(rename = "24h")]
+    H24,
+    #[serde(rename = "12h")]
+    H12,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Unit {
+    Minute,
+    Hour,
+    Day,
+}
+
+/// Phrasings for one, few (2 to 4) and many of a unit
+///
+/// `{}` is replaced with the count.
+struct UnitForms {
+    future: [&'static str; 3],
+    past: [&'static str; 3],
+    count: [&'static str; 3],
+}
+
+/// Phrases of the startup announcement
+pub struct StartupPhrases {
+    pub greeting: &'static str,
+    pub date: &'static str,
+    pub no_interfaces: &'static str,
+    pub interfaces: &'static str,
+    pub interface: &'static str,
+    pub interfaces_failed: &'static str,
+    pub hostname: &'static str,
+    pub no_hostname: &'static str,
+}
+
+impl Language {
+    fn weekdays(&self) -> [&'static str; 7] {
+        match self {
+            Language::En => [
+                "Monday",
+                "Tuesday",
+                "Wednesday",
+                "Thursday",
+                "Friday",
+                "Saturday",
+                "Sunday",
+            ],
+            Language::De => [
+                "Montag",
+                "Dienstag",
+                "Mittwoch",
+                "Donnerstag",
+                "Freitag",
+                "Samstag",
+                "Sonntag",
+            ],
+            Language::Cs => [
+                "pondělí",
+                "úterý",
+                "středa",
+                "čtvrtek",
+                "pátek",
+                "sobota",
+                "neděle",
+            ],
+            Language::Sk => [
+                "pondelok", "utorok", "streda", "štvrtok", "piatok", "sobota", "nedeľa",
+            ],
+        }
+    }
+
+    /// Czech and Slovak months are in genitive as in "3. ledna"
+    fn months(&self) -> [&'static str; 12] {
+        match self {
+            Language::En => [
+                "January",
+                "February",
+                "March",
+                "April",
+                "May",
+                "June",
+                "July",
+                "August",
+                "September",
+                "October",
+                "November",
+                "December",
+            ],
+            Language::De => [
+                "Januar",
+                "Februar",
+                "März",
+                "April",
+                "Mai",
+                "Juni",
+                "Juli",
+                "August",
+                "September",
+                "Oktober",
+                "November",
+                "Dezember",
+            ],
+            Language::Cs => [
+                "ledna",
+                "února",
+                "března",
+                "dubna",
+                "května",
+                "června",
+                "července",
+                "srpna",
+                "září",
+                "října",
+                "listopadu",
+                "prosince",
+            ],
+            Language::Sk => [
+                "januára",
+                "februára",
+                "marca",
+                "apríla",
+                "mája",
+                "júna",
+                "júla",
+                "augusta",
+                "septembra",
+                "októbra",
+                "novembra",
+                "decembra",
+            ],
+        }
+    }
+
+    /// Morning, afternoon, evening and night
+    fn day_periods(&self) -> [&'static str; 4] {
+        match self {
+            Language::En => [
+                "in the morning",
+                "in the afternoon",
+                "in the evening",
+                "at night",
+            ],
+            Language::De => ["morgens", "nachmittags", "abends", "nachts"],
+            Language::Cs => ["ráno", "odpoledne", "večer", "v noci"],
+            Language::Sk => ["ráno", "popoludní", "večer", "v noci"],
+        }
+    }
+
+    fn plural_form(&self, count: u64) -> usize {
+        match (self, count) {
+            (_, 1) => 0,
+            (Language::Cs | Language::Sk, 2..=4) => 1,
+            _ => 2,
+        }
+    }
+
+    fn unit_forms(&self, unit: Unit) -> UnitForms {
+        match (self, unit) {
+            (Language::En, Unit::Minute) => UnitForms {
+                future: ["in a minute", "in {} minutes", "in {} minutes"],
+                past: ["a minute ago", "{} minutes ago", "{} minutes ago"],
+                count: ["1 minute", "{} minutes", "{} minutes"],
+            },
+            (Language::En, Unit::Hour) => UnitForms {
+                future: ["in an hour", "in {} hours", "in {} hours"],
+                past: ["an hour ago", "{} hours ago", "{} hours ago"],
+                count: ["1 hour", "{} hours", "{} hours"],
+            },
+            (Language::En, Unit::Day) => UnitForms {
+                future: ["in a day", "in {} days", "in {} days"],
+                past: ["a day ago", "{} days ago", "{} days ago"],
+                count: ["1 day", "{} days", "{} days"],
+            },
+            (Language::De, Unit::Minute) => UnitForms {
+                future: ["in einer Minute", "in {} Minuten", "in {} Minuten"],
+                past: ["vor einer Minute", "vor {} Minuten", "vor {} Minuten"],
+                count: ["eine Minute", "{} Minuten", "{} Minuten"],
+            },
+            (Language::De, Unit::Hour) => UnitForms {
+                future: ["in einer Stunde", "in {} Stunden", "in {} Stunden"],
+                past: ["vor einer Stunde", "vor {} Stunden", "vor {} Stunden"],
+                count: ["eine Stunde", "{} Stunden", "{} Stunden"],
+            },
+            (Language::De, Unit::Day) => UnitForms {
+                future: ["in einem Tag", "in {} Tagen", "in {} Tagen"],
+                past: ["vor einem Tag", "vor {} Tagen", "vor {} Tagen"],
+                count: ["ein Tag", "{} Tage", "{} Tage"],
+            },
+            (Language::Cs, Unit::Minute) => UnitForms {
+                future: ["za minutu", "za {} minuty", "za {} minut"],
+                past: ["před minutou", "před {} minutami", "před {} minutami"],
+                count: ["jedna minuta", "{} minuty", "{} minut"],
+            },
+            (Language::Cs, Unit::Hour) => UnitForms {
+                future: ["za hodinu", "za {} hodiny", "za {} hodin"],
+                past: ["před hodinou", "před {} hodinami", "před {} hodinami"],
+                count: ["jedna hodina", "{} hodiny", "{} hodin"],
+            },
+            (Language::Cs, Unit::Day) => UnitForms {
+                future: ["za den", "za {} dny", "za {} dní"],
+                past: ["před dnem", "před {} dny", "před {} dny"],
+                count: ["jeden den", "{} dny", "{} dní"],
+            },
+            (Language::Sk, Unit::Minute) => UnitForms {
+                future: ["o minútu", "o {} minúty", "o {} minút"],
+                past: ["pred minútou", "pred {} minútami", "pred {} minútami"],
+                count: ["jedna minúta", "{} minúty", "{} minút"],
+            },
+            (Language::Sk, Unit::Hour) => UnitForms {
+                future: ["o hodinu", "o {} hodiny", "o {} hodín"],
+                past: ["pred hodinou", "pred {} hodinami", "pred {} hodinami"],
+                count: ["jedna hodina", "{} hodiny", "{} hodín"],
+            },
+            (Language::Sk, Unit::Day) => UnitForms {
+                future: ["o deň", "o {} dni", "o {} dní"],
+                past: ["pred dňom", "pred {} dňami", "pred {} dňami"],
+                count: ["jeden deň", "{} dni", "{} dní"],
+            },
+        }
+    }
+
+    /// `{}` is replaced with the name, date, interface and address or hostname
+    pub fn startup_phrases(&self) -> StartupPhrases {
+        match self {
+            Language::En => StartupPhrases {
+                greeting: "Good morning, my name is {}!",
+                date: "It's {}.",
+                no_interfaces: "Huh, It looks like this device has no network interfaces?",
+                interfaces: "My network interfaces are {}.",
+                interface: "{} at {}",
+                interfaces_failed: "I can't tell you how to reach me because it looks like I failed to query the local interfaces for some reason.",
+                hostname: "My hostname is {}.",
+                no_hostname: "I can't detect my hostname. Maybe this platform isn't supported?",
+            },
+            Language::De => StartupPhrases {
+                greeting: "Guten Morgen, ich heiße {}!",
+                date: "Es ist {}.",
+                no_interfaces: "Dieses Gerät scheint keine Netzwerkschnittstellen zu haben.",
+                interfaces: "Meine Netzwerkschnittstellen sind {}.",
+                interface: "{} mit der Adresse {}",
+                interfaces_failed: "Ich kann dir nicht sagen, wie du mich erreichst, weil ich die Netzwerkschnittstellen nicht abfragen konnte.",
+                hostname: "Mein Hostname ist {}.",
+                no_hostname: "Ich kann meinen Hostnamen nicht ermitteln. Vielleicht wird diese Plattform nicht unterstützt?",
+            },
+            Language::Cs => StartupPhrases {
+                greeting: "Dobré ráno, jmenuji se {}!",
+                date: "Je {}.",
+                no_interfaces: "Zdá se, že toto zařízení nemá žádná síťová rozhraní.",
+                interfaces: "Moje síťová rozhraní jsou {}.",
+                interface: "{} s adresou {}",
+                interfaces_failed: "Nemohu ti říct, jak se ke mně dostat, protože se mi nepodařilo zjistit síťová rozhraní.",
+                hostname: "Můj hostname je {}.",
+                no_hostname: "Nemohu zjistit svůj hostname. Možná tato platforma není podporována?",
+            },
+            Language::Sk => StartupPhrases {
+                greeting: "Dobré ráno, volám sa {}!",
+                date: "Je {}.",
+                no_interfaces: "Zdá sa, že toto zariadenie nemá žiadne sieťové rozhrania.",
+                interfaces: "Moje sieťové rozhrania sú {}.",
+                interface: "{} s adresou {}",
+                interfaces_failed: "Nemôžem ti povedať, ako sa ku mne dostať, pretože sa mi nepodarilo zistiť sieťové rozhrania.",
+                hostname: "Môj hostname je {}.",
+                no_hostname: "Neviem zistiť svoj hostname. Možno táto platforma nie je podporovaná?",
+            },
+        }
+    }
+}
+
+/// Fill `{}` placeholders in order
+pub fn fill(phrase: &str, values: &[&str]) -> String {
+    let mut parts = phrase.split("{}");
+    let mut output = parts.next().unwrap_or_default().to_owned();
+    for (part, value) in parts.zip(values.iter().chain(std::iter::repeat(&""))) {
+        output.push_str(value);
+        output.push_str(part);
+    }
+    output
+}
+
+/// Turns timestamps and durations into text meant to be spoken
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Humanizer {
+    language: Language,
+    clock: ClockFormat,
+    /// "quarter past nine" instead of "9:15"
+    spoken_time: bool,
+}
+
+impl Humanizer {
+    pub fn new(language: Language, clock: ClockFormat, spoken_time: bool) -> Self {
+        Self {
+            language,
+            clock,
+            spoken_time,
+        }
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// Time of day such as "9:05" or "quarter past nine"
+    pub fn time(&self, time: NaiveTime) -> String {
+        match self.spoken_time.then(|| self.spoken(time, false)).flatten() {
+            Some(text) => self.with_day_period(text, time),
+            None => self.digital(time),
+        }
+    }
+
+    /// Full date with the time of day
+    pub fn date_time(&self, date_time: NaiveDateTime) -> String {
+        let weekday = self.language.weekdays()[date_time.weekday().num_days_from_monday() as usize];
+        let month = self.language.months()[date_time.month0() as usize];
+        let day = date_time.day();
+        let year = date_time.year();
+        let at = self.at_time(date_time.time());
+        match self.language {
+            Language::En => format!(
+                "{}, {} of {}, {} {}",
+                weekday,
+                Ordinal(day),
+                month,
+                year,
+                at
+            ),
+            Language::De => format!("{}, {}. {} {} {}", weekday, day, month, year, at),
+            Language::Cs | Language::Sk => {
+                format!("{} {}. {} {} {}", weekday, day, month, year, at)
+            }
+        }
+    }
+
+    /// Distance of a timestamp from now such as "in 5 minutes" or "tomorrow at 7:00"
+    pub fn relative(&self, date_time: NaiveDateTime, now: NaiveDateTime) -> String {
+        let minutes = (date_time - now).num_minutes();
+        let days = (date_time.date() - now.date()).num_days();
+        match days {
+            _ if minutes == 0 => String::from(match self.language {
+                Language::En => "now",
+                Language::De => "jetzt",
+                Language::Cs => "teď",
+                Language::Sk => "teraz",
+            }),
+            _ if minutes.abs() < 60 => self.relative_units(minutes, Unit::Minute),
+            0 => self.relative_units(minutes / 60, Unit::Hour),
+            1 | -1 => {
+                let word = match (self.language, days) {
+                    (Language::En, 1) => "tomorrow",
+                    (Language::En, _) => "yesterday",
+                    (Language::De, 1) => "morgen",
+                    (Language::De, _) => "gestern",
+                    (Language::Cs, 1) => "zítra",
+                    (Language::Cs, _) => "včera",
+                    (Language::Sk, 1) => "zajtra",
+                    (Language::Sk, _) => "včera",
+                };
+                format!("{} {}", word, self.at_time(date_time.time()))
+            }
+            days => self.relative_units(days, Unit::Day),
+        }
+    }
+
+    /// Length of time such as "2 hours and 5 minutes"
+    pub fn duration(&self, duration: Duration) -> String {
+        let minutes = duration.as_secs() / 60;
+        let mut parts: Vec<String> = [
+            (minutes / (60 * 24), Unit::Day),
+            (minutes / 60 % 24, Unit::Hour),
+            (minutes % 60, Unit::Minute),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, unit)| self.units(count, unit, |forms| forms.count))
+        .collect();
+        let and = match self.language {
+            Language::En => "and",
+            Language::De => "und",
+            Language::Cs | Language::Sk => "a",
+        };
+        match parts.pop() {
+            None => String::from(match self.language {
+                Language::En => "less than a minute",
+                Language::De => "weniger als eine Minute",
+                Language::Cs => "méně než minuta",
+                Language::Sk => "menej ako minúta",
+            }),
+            Some(last) if parts.is_empty() => last,
+            Some(last) => format!("{} {} {}", parts.join(", "), and, last),
+        }
+    }
+
+    fn units(
+        &self,
+        count: u64,
+        unit: Unit,
+        form: impl Fn(UnitForms) -> [&'static str; 3],
+    ) -> String {
+        let phrase = form(self.language.unit_forms(unit))[self.language.plural_form(count)];
+        fill(phrase, &[&count.to_string()])
+    }
+
+    fn relative_units(&self, count: i64, unit: Unit) -> String {
+        if count > 0 {
+            self.units(count.unsigned_abs(), unit, |forms| forms.future)
+        } else {
+            self.units(count.unsigned_abs(), unit, |forms| forms.past)
+        }
+    }
+
+    /// Time with the preposition used for "at"
+    fn at_time(&self, time: NaiveTime) -> String {
+        if let Some(text) = self.spoken_time.then(|| self.spoken(time, true)).flatten() {
+            return self.with_day_period(text, time);
+        }
+        let text = self.digital(time);
+        match self.language {
+            Language::En => format!("at {}", text),
+            Language::De => format!("um {}", text),
+            Language::Cs => format!("{} {}", czech_at(&text), text),
+            Language::Sk => format!("o {}", text),
+        }
+    }
+
+    fn digital(&self, time: NaiveTime) -> String {
+        match self.clock {
+            ClockFormat::H24 => format!("{}:{:02}", time.hour(), time.minute()),
+            ClockFormat::H12 => {
+                let text = format!("{}:{:02}", twelve_hour(time.hour()), time.minute());
+                if self.language == Language::En {
+                    let suffix = if time.hour() < 12 { "AM" } else { "PM" };
+                    format!("{} {}", text, suffix)
+                } else {
+                    self.with_day_period(text, time)
+                }
+            }
+        }
+    }
+
+    /// Morning or evening for the 12 hour clock
+    fn with_day_period(&self, text: String, time: NaiveTime) -> String {
+        let is_noon_or_midnight = time.minute() == 0 && matches!(time.hour(), 0 | 12);
+        if self.clock != ClockFormat::H12 || (self.language == Language::En && is_noon_or_midnight)
+        {
+            return text;
+        }
+        let period = match time.hour() {
+            5..=11 => 0,
+            12..=17 => 1,
+            18..=21 => 2,
+            _ => 3,
+        };
+        format!("{} {}", text, self.language.day_periods()[period])
+    }
+
+    /// Full, quarter and half hours the way people say them
+    ///
+    /// With `at` the result includes the preposition for "at" since some languages
+    /// change the form of the hour after it.
+    fn spoken(&self, time: NaiveTime, at: bool) -> Option<String> {
+        // quarter to and half past are said in terms of the next hour in some languages
+        let hour = twelve_hour(time.hour()) as usize - 1;
+        let next = hour_index(time.hour() + 1);
+        let text = match (self.language, time.minute()) {
+            (Language::En, 0) if time.hour() == 12 => String::from("noon"),
+            (Language::En, 0) if time.hour() == 0 => String::from("midnight"),
+            (Language::En, 0) => format!("{} o'clock", EN_HOURS[hour]),
+            (Language::En, 15) => format!("quarter past {}", EN_HOURS[hour]),
+            (Language::En, 30) => format!("half past {}", EN_HOURS[hour]),
+            (Language::En, 45) => format!("quarter to {}", EN_HOURS[next]),
+            (Language::De, 0) if hour == 0 => String::from("ein Uhr"),
+            (Language::De, 0) => format!("{} Uhr", DE_HOURS[hour]),
+            (Language::De, 15) => format!("Viertel nach {}", DE_HOURS[hour]),
+            (Language::De, 30) => format!("halb {}", DE_HOURS[next]),
+            (Language::De, 45) => format!("Viertel vor {}", DE_HOURS[next]),
+            (Language::Cs, 0) if at && hour == 0 => return Some(String::from("v jednu hodinu")),
+            (Language::Cs, 0) => String::from(CS_FULL_HOURS[hour]),
+            (Language::Cs, 15) => format!("čtvrt na {}", CS_HOURS_ACCUSATIVE[next]),
+            (Language::Cs, 30) => format!("půl {}", CS_HOURS_GENITIVE[next]),
+            (Language::Cs, 45) => format!("tři čtvrtě na {}", CS_HOURS_ACCUSATIVE[next]),
+            // "o deviatej" rather than "o deväť hodín"
+            (Language::Sk, 0) if at => return Some(format!("o {}", SK_HOURS_LOCATIVE[hour])),
+            (Language::Sk, 0) => String::from(SK_FULL_HOURS[hour]),
+            (Language::Sk, 15) => format!("štvrť na {}", SK_HOURS_ACCUSATIVE[next]),
+            (Language::Sk, 30) => format!("pol {}", SK_HOURS_LOCATIVE[next]),
+            (Language::Sk, 45) => format!("tri štvrte na {}", SK_HOURS_ACCUSATIVE[next]),
+            _ => return None,
+        };
+        if !at {
+            return Some(text);
+        }
+        Some(match self.language {
+            Language::En => format!("at {}", text),
+            Language::De => format!("um {}", text),
+            Language::Cs => format!("{} {}", czech_at(&text), text),
+            Language::Sk => format!("o {}", text),
+        })
+    }
+}
+
+fn twelve_hour(hour: u32) -> u32 {
+    (hour + 11) % 12 + 1
+}
+
+/// Index into the hour tables which start at one o'clock
+fn hour_index(hour: u32) -> usize {
+    twelve_hour(hour % 24) as usize - 1
+}
+
+/// Czech "v" turns into "ve" before words that would be hard to pronounce after it
+fn czech_at(text: &str) -> &'static str {
+    const VE_PREFIXES: [&str; 6] = ["č", "š", "tř", "dv", "v", "f"];
+    let hour = text.split(':').next().unwrap_or_default();
+    let ve = match hour.parse::<u32>() {
+        Ok(hour) => matches!(hour, 2..=4 | 12..=14 | 20..=23),
+        Err(_) => VE_PREFIXES.iter().any(|prefix| text.starts_with(prefix)),
+    };
+    if ve {
+        "ve"
+    } else {
+        "v"
+    }
+}
+
+const EN_HOURS: [&str; 12] = [
+    "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten", "eleven",
+    "twelve",
+];
+
+const DE_HOURS: [&str; 12] = [
+    "eins", "zwei", "drei", "vier", "fünf", "sechs", "sieben", "acht", "neun", "zehn", "elf",
+    "zwölf",
+];
+
+const CS_FULL_HOURS: [&str; 12] = [
+    "jedna hodina",
+    "dvě hodiny",
+    "tři hodiny",
+    "čtyři hodiny",
+    "pět hodin",
+    "šest hodin",
+    "sedm hodin",
+    "osm hodin",
+    "devět hodin",
+    "deset hodin",
+    "jedenáct hodin",
+    "dvanáct hodin",
+];
+
+const CS_HOURS_ACCUSATIVE: [&str; 12] = [
+    "jednu",
+    "dvě",
+    "tři",
+    "čtyři",
+    "pět",
+    "šest",
+    "sedm",
+    "osm",
+    "devět",
+    "deset",
+    "jedenáct",
+    "dvanáct",
+];
+
+const CS_HOURS_GENITIVE: [&str; 12] = [
+    "jedné",
+    "druhé",
+    "třetí",
+    "čtvrté",
+    "páté",
+    "šesté",
+    "sedmé",
+    "osmé",
+    "deváté",
+    "desáté",
+    "jedenácté",
+    "dvanácté",
+];
+
+const SK_FULL_HOURS: [&str; 12] = [
+    "jedna hodina",
+    "dve hodiny",
+    "tri hodiny",
+    "štyri hodiny",
+    "päť hodín",
+    "šesť hodín",
+    "sedem hodín",
+    "osem hodín",
+    "deväť hodín",
+    "desať hodín",
+    "jedenásť hodín",
+    "dvanásť hodín",
+];
+
+const SK_HOURS_ACCUSATIVE: [&str; 12] = [
+    "jednu",
+    "dve",
+    "tri",
+    "štyri",
+    "päť",
+    "šesť",
+    "sedem",
+    "osem",
+    "deväť",
+    "desať",
+    "jedenásť",
+    "dvanásť",
+];
+
+const SK_HOURS_LOCATIVE: [&str; 12] = [
+    "jednej",
+    "druhej",
+    "tretej",
+    "štvrtej",
+    "piatej",
+    "šiestej",
+    "siedmej",
+    "ôsmej",
+    "deviatej",
+    "desiatej",
+    "jedenástej",
+    "dvanástej",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        // a Monday
+        NaiveDate::from_ymd_opt(2022, 1, 3)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn humanizer(language: Language) -> Humanizer {
+        Humanizer::new(language, ClockFormat::H24, true)
+    }
+
+    #[test]
+    fn english_spoken_times() {
+        let humanizer = humanizer(Language::En);
+        assert_eq!(humanizer.time(at(9, 15).time()), "quarter past nine");
+        assert_eq!(humanizer.time(at(9, 30).time()), "half past nine");
+        assert_eq!(humanizer.time(at(9, 45).time()), "quarter to ten");
+        assert_eq!(humanizer.time(at(21, 0).time()), "nine o'clock");
+        assert_eq!(humanizer.time(at(12, 0).time()), "noon");
+        assert_eq!(humanizer.time(at(23, 45).time()), "quarter to twelve");
+        assert_eq!(humanizer.time(at(9, 5).time()), "9:05");
+    }
+
+    #[test]
+    fn twelve_hour_clock() {
+        let humanizer = Humanizer::new(Language::En, ClockFormat::H12, true);
+        assert_eq!(humanizer.time(at(21, 5).time()), "9:05 PM");
+        assert_eq!(humanizer.time(at(0, 5).time()), "12:05 AM");
+        assert_eq!(
+            humanizer.time(at(21, 15).time()),
+            "quarter past nine in the evening"
+        );
+        let humanizer = Humanizer::new(Language::De, ClockFormat::H12, false);
+        assert_eq!(humanizer.time(at(21, 15).time()), "9:15 abends");
+    }
+
+    #[test]
+    fn digital_times() {
+        let humanizer = Humanizer::new(Language::En, ClockFormat::H24, false);
+        assert_eq!(humanizer.time(at(9, 15).time()), "9:15");
+        assert_eq!(humanizer.time(at(21, 0).time()), "21:00");
+    }
+
+    #[test]
+    fn german_spoken_times() {
+        let humanizer = humanizer(Language::De);
+        assert_eq!(humanizer.time(at(9, 15).time()), "Viertel nach neun");
+        assert_eq!(humanizer.time(at(9, 30).time()), "halb zehn");
+        assert_eq!(humanizer.time(at(9, 45).time()), "Viertel vor zehn");
+        assert_eq!(humanizer.time(at(13, 0).time()), "ein Uhr");
+        assert_eq!(humanizer.time(at(12, 30).time()), "halb eins");
+    }
+
+    #[test]
+    fn czech_spoken_times() {
+        let humanizer = humanizer(Language::Cs);
+        assert_eq!(humanizer.time(at(9, 15).time()), "čtvrt na deset");
+        assert_eq!(humanizer.time(at(9, 30).time()), "půl desáté");
+        assert_eq!(humanizer.time(at(9, 45).time()), "tři čtvrtě na deset");
+        assert_eq!(humanizer.time(at(12, 30).time()), "půl jedné");
+        assert_eq!(humanizer.time(at(14, 0).time()), "dvě hodiny");
+    }
+
+    #[test]
+    fn slovak_spoken_times() {
+        let humanizer = humanizer(Language::Sk);
+        assert_eq!(humanizer.time(at(9, 15).time()), "štvrť na desať");
+        assert_eq!(humanizer.time(at(9, 30).time()), "pol desiatej");
+        assert_eq!(humanizer.time(at(9, 45).time()), "tri štvrte na desať");
+        assert_eq!(humanizer.time(at(9, 0).time()), "deväť hodín");
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(
+            humanizer(Language::En).date_time(at(9, 15)),
+            "Monday, 3rd of January, 2022 at quarter past nine"
+        );
+        assert_eq!(
+            humanizer(Language::De).date_time(at(9, 30)),
+            "Montag, 3. Januar 2022 um halb zehn"
+        );
+        assert_eq!(
+            humanizer(Language::Cs).date_time(at(9, 15)),
+            "pondělí 3. ledna 2022 ve čtvrt na deset"
+        );
+        assert_eq!(
+            humanizer(Language::Cs).date_time(at(9, 5)),
+            "pondělí 3. ledna 2022 v 9:05"
+        );
+        assert_eq!(
+            humanizer(Language::Sk).date_time(at(9, 0)),
+            "pondelok 3. januára 2022 o deviatej"
+        );
+    }
+
+    #[test]
+    fn relative_times() {
+        let now = at(9, 0);
+        let en = humanizer(Language::En);
+        assert_eq!(en.relative(at(9, 0), now), "now");
+        assert_eq!(en.relative(at(9, 5), now), "in 5 minutes");
+        assert_eq!(en.relative(at(8, 59), now), "a minute ago");
+        assert_eq!(en.relative(at(11, 0), now), "in 2 hours");
+        assert_eq!(
+            en.relative(at(7, 30) + chrono::Duration::days(1), now),
+            "tomorrow at half past seven"
+        );
+        assert_eq!(
+            en.relative(at(9, 0) + chrono::Duration::days(3), now),
+            "in 3 days"
+        );
+        let cs = humanizer(Language::Cs);
+        assert_eq!(cs.relative(at(9, 2), now), "za 2 minuty");
+        assert_eq!(cs.relative(at(9, 5), now), "za 5 minut");
+        assert_eq!(cs.relative(at(8, 55), now), "před 5 minutami");
+        let sk = humanizer(Language::Sk);
+        assert_eq!(sk.relative(at(9, 1), now), "o minútu");
+    }
+
+    #[test]
+    fn durations() {
+        let en = humanizer(Language::En);
+        assert_eq!(
+            en.duration(Duration::from_secs(26 * 3600 + 5 * 60)),
+            "1 day, 2 hours and 5 minutes"
+        );
+        assert_eq!(en.duration(Duration::from_secs(30)), "less than a minute");
+        let de = humanizer(Language::De);
+        assert_eq!(de.duration(Duration::from_secs(3 * 3600)), "3 Stunden");
+        let cs = humanizer(Language::Cs);
+        assert_eq!(
+            cs.duration(Duration::from_secs(2 * 3600 + 60)),
+            "2 hodiny a jedna minuta"
+        );
+    }
+
+    #[test]
+    fn fills_placeholders() {
+        assert_eq!(fill("{} at {}", &["eth0", "10.0.0.2"]), "eth0 at 10.0.0.2");
+        assert_eq!(fill("It's {}.", &["late"]), "It's late.");
+    }
+}
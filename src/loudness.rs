@@ -0,0 +1,341 @@
+//! Integrated loudness measurement based on EBU R128 / ITU-R BS.1770
+//!
+//! Uses K-weighting, 400ms blocks with 75% overlap and the absolute and relative gates.
+//! Sample peak is measured instead of true peak.
+
+use crate::configuration::LoudnessConfig;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::{f64::consts::PI, io::Cursor};
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Loudness {
+    pub integrated_lufs: f64,
+    pub sample_peak: f32,
+}
+
+impl Loudness {
+    /// Linear gain that brings the clip to the target loudness without clipping
+    pub fn gain(&self, config: &LoudnessConfig) -> f32 {
+        if !config.enabled {
+            return 1.0;
+        }
+        let gain_db = (config.target_lufs - self.integrated_lufs).min(config.max_gain_db);
+        let mut gain = db_to_linear(gain_db);
+        if self.sample_peak > 0.0 {
+            let peak_limited_gain = db_to_linear(config.peak_ceiling_db) / self.sample_peak as f64;
+            gain = gain.min(peak_limited_gain);
+        }
+        gain as f32
+    }
+}
+
+fn db_to_linear(db: f64) -> f64 {
+    10_f64.powf(db / 20.0)
+}
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0,
+            b1,
+            b2,
+            a1,
+            a2,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    /// Pre-filter modelling the acoustic effect of the head
+    fn high_shelf(sample_rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / sample_rate).tan();
+        let vh = 10_f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Self::new(
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        )
+    }
+
+    /// RLB weighting curve
+    fn high_pass(sample_rate: f64) -> Self {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Self::new(
+            1.0,
+            -2.0,
+            1.0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        )
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+}
+
+/// Accumulates interleaved samples
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<(Biquad, Biquad)>,
+    frames_per_sub_block: usize,
+    /// Sum of squares of 100ms sub blocks
+    sub_blocks: Vec<f64>,
+    current_energy: f64,
+    current_frames: usize,
+    channel: usize,
+    sample_peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        let channels = channels.max(1) as usize;
+        let filter = (
+            Biquad::high_shelf(sample_rate as f64),
+            Biquad::high_pass(sample_rate as f64),
+        );
+        Self {
+            channels,
+            filters: vec![filter; channels],
+            frames_per_sub_block: (sample_rate as usize / 10).max(1),
+            sub_blocks: vec![],
+            current_energy: 0.0,
+            current_frames: 0,
+            channel: 0,
+            sample_peak: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, sample: f32) {
+        self.sample_peak = self.sample_peak.max(sample.abs());
+        let (shelf, high_pass) = &mut self.filters[self.channel];
+        let filtered = high_pass.process(shelf.process(sample as f64));
+        self.current_energy += filtered * filtered;
+
+        self.channel += 1;
+        if self.channel == self.channels {
+            self.channel = 0;
+            self.current_frames += 1;
+            if self.current_frames == self.frames_per_sub_block {
+                self.sub_blocks.push(self.current_energy);
+                self.current_energy = 0.0;
+                self.current_frames = 0;
+            }
+        }
+    }
+
+    /// None for silence
+    pub fn finish(self) -> Option<Loudness> {
+        let block_energies: Vec<f64> = if self.sub_blocks.len() < SUB_BLOCKS_PER_BLOCK {
+            // clips shorter than a block are measured as a single block
+            let frames = self.sub_blocks.len() * self.frames_per_sub_block + self.current_frames;
+            if frames == 0 {
+                return None;
+            }
+            let energy: f64 = self.sub_blocks.iter().sum::<f64>() + self.current_energy;
+            vec![energy / frames as f64]
+        } else {
+            let block_frames = (SUB_BLOCKS_PER_BLOCK * self.frames_per_sub_block) as f64;
+            self.sub_blocks
+                .windows(SUB_BLOCKS_PER_BLOCK)
+                .map(|window| window.iter().sum::<f64>() / block_frames)
+                .collect()
+        };
+
+        let above_absolute_gate: Vec<f64> = block_energies
+            .into_iter()
+            .filter(|energy| energy_to_lufs(*energy) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        if above_absolute_gate.is_empty() {
+            return None;
+        }
+        let relative_gate = energy_to_lufs(mean(&above_absolute_gate)) + RELATIVE_GATE_LU;
+        let gated: Vec<f64> = above_absolute_gate
+            .into_iter()
+            .filter(|energy| energy_to_lufs(*energy) > relative_gate)
+            .collect();
+        if gated.is_empty() {
+            return None;
+        }
+        Some(Loudness {
+            integrated_lufs: energy_to_lufs(mean(&gated)),
+            sample_peak: self.sample_peak,
+        })
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Decode any format supported by rodio and measure it
+pub fn measure_encoded(data: Vec<u8>) -> Option<Loudness> {
+    let decoder = rodio::Decoder::new(Cursor::new(data)).ok()?;
+    let mut meter = LoudnessMeter::new(decoder.channels(), decoder.sample_rate());
+    for sample in decoder.convert_samples::<f32>() {
+        meter.push(sample);
+    }
+    meter.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+    const TOLERANCE_LU: f64 = 0.1;
+
+    fn sine(amplitude: f32, seconds: f32) -> impl Iterator<Item = f32> {
+        let frames = (seconds * SAMPLE_RATE as f32) as usize;
+        (0..frames).map(move |frame| {
+            let time = frame as f32 / SAMPLE_RATE as f32;
+            amplitude * (2.0 * std::f32::consts::PI * 1000.0 * time).sin()
+        })
+    }
+
+    fn measure_mono(samples: impl Iterator<Item = f32>) -> Option<Loudness> {
+        let mut meter = LoudnessMeter::new(1, SAMPLE_RATE);
+        samples.for_each(|sample| meter.push(sample));
+        meter.finish()
+    }
+
+    fn assert_lufs(loudness: Option<Loudness>, expected: f64) {
+        let measured = loudness.unwrap().integrated_lufs;
+        assert!(
+            (measured - expected).abs() < TOLERANCE_LU,
+            "measured {} LUFS, expected {}",
+            measured,
+            expected
+        );
+    }
+
+    #[test]
+    fn full_scale_sine_reads_minus_three() {
+        // BS.1770 reference: a 0 dBFS 1 kHz sine in one channel is -3.01 LKFS
+        assert_lufs(measure_mono(sine(1.0, 3.0)), -3.01);
+    }
+
+    #[test]
+    fn level_follows_amplitude() {
+        assert_lufs(measure_mono(sine(0.1, 3.0)), -23.01);
+    }
+
+    #[test]
+    fn channels_are_summed() {
+        let mut meter = LoudnessMeter::new(2, SAMPLE_RATE);
+        for sample in sine(0.1, 3.0) {
+            meter.push(sample);
+            meter.push(sample);
+        }
+        assert_lufs(meter.finish(), -20.0);
+    }
+
+    #[test]
+    fn clips_shorter_than_a_block_are_measured() {
+        assert_lufs(measure_mono(sine(0.1, 0.2)), -23.01);
+    }
+
+    #[test]
+    fn silence_is_gated() {
+        let samples = sine(0.1, 3.0).chain(std::iter::repeat(0.0).take(SAMPLE_RATE as usize * 3));
+        let measured = measure_mono(samples).unwrap().integrated_lufs;
+        // blocks straddling the end of the tone still count so it reads slightly lower
+        // averaging in the silence would give about -26
+        assert!((-23.5..-23.0).contains(&measured), "measured {}", measured);
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        assert!(measure_mono(std::iter::repeat(0.0).take(SAMPLE_RATE as usize)).is_none());
+        assert!(measure_mono(std::iter::empty()).is_none());
+    }
+
+    #[test]
+    fn peak_is_recorded() {
+        let loudness = measure_mono(sine(0.5, 1.0)).unwrap();
+        assert!((loudness.sample_peak - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn gain_reaches_target() {
+        let loudness = Loudness {
+            integrated_lufs: -24.0,
+            sample_peak: 0.1,
+        };
+        let config = LoudnessConfig {
+            enabled: true,
+            target_lufs: -18.0,
+            max_gain_db: 12.0,
+            peak_ceiling_db: -1.0,
+        };
+        assert!((loudness.gain(&config) as f64 - db_to_linear(6.0)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn gain_is_limited_by_max_gain_and_peak() {
+        let config = LoudnessConfig {
+            enabled: true,
+            target_lufs: -18.0,
+            max_gain_db: 6.0,
+            peak_ceiling_db: -1.0,
+        };
+        let quiet = Loudness {
+            integrated_lufs: -40.0,
+            sample_peak: 0.01,
+        };
+        assert!((quiet.gain(&config) as f64 - db_to_linear(6.0)).abs() < 1e-4);
+
+        let peaky = Loudness {
+            integrated_lufs: -30.0,
+            sample_peak: 0.8,
+        };
+        let expected = db_to_linear(-1.0) / 0.8;
+        assert!((peaky.gain(&config) as f64 - expected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn disabled_normalization_has_unity_gain() {
+        let config = LoudnessConfig {
+            enabled: false,
+            ..Default::default()
+        };
+        let loudness = Loudness {
+            integrated_lufs: -40.0,
+            sample_peak: 0.01,
+        };
+        assert_eq!(loudness.gain(&config), 1.0);
+    }
+}
@@ -0,0 +1,129 @@
+//! Minimal HTTP server for testing clients against canned responses
+
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    truncate_at: Option<usize>,
+}
+
+impl MockResponse {
+    pub fn new(status: u16, body: &[u8]) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: body.to_vec(),
+            truncate_at: None,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Close the connection after sending this many bytes of the body
+    ///
+    /// The advertised content length stays the same so clients see a broken stream.
+    pub fn truncate_at(mut self, length: usize) -> Self {
+        self.truncate_at = Some(length);
+        self
+    }
+}
+
+/// Answers each connection with the next queued response
+///
+/// Once the queue runs out every request gets a 404.
+pub struct MockServer {
+    url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockServer {
+    pub fn start(responses: Vec<MockResponse>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let responses = Arc::new(Mutex::new(VecDeque::from(responses)));
+        thread::spawn({
+            let requests = requests.clone();
+            move || {
+                for stream in listener.incoming().flatten() {
+                    let response = responses
+                        .lock()
+                        .unwrap()
+                        .pop_front()
+                        .unwrap_or_else(|| MockResponse::new(404, b""));
+                    let mut reader = BufReader::new(stream);
+                    if let Some(request_line) = read_request(&mut reader) {
+                        requests.lock().unwrap().push(request_line);
+                        write_response(reader.into_inner(), &response);
+                    }
+                }
+            }
+        });
+        Self { url, requests }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Request lines such as `POST /v1/voices HTTP/1.1` in the order they arrived
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Consumes the request and returns its request line
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<String> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+    Some(request_line.trim_end().to_owned())
+}
+
+fn write_response(mut stream: TcpStream, response: &MockResponse) {
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let body_length = response
+        .truncate_at
+        .unwrap_or(response.body.len())
+        .min(response.body.len());
+    // the client notices broken connections itself
+    let _ = stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(&response.body[..body_length]))
+        .and_then(|_| stream.flush());
+}
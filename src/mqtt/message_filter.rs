@@ -0,0 +1,360 @@
+use crate::{
+    configuration::{DuplicateMode, MessageFilterConfig, RateLimitConfig},
+    speech_service::is_route_prefix,
+};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+use tracing::*;
+
+/// Drops duplicate and excessive messages before they reach the router
+///
+/// JSON requests are compared by their `content` field and can name a `source`
+/// which is rate limited instead of the topic.
+/// The same text on different routes is not a duplicate since routes pick the voice.
+pub struct MessageFilter {
+    config: MessageFilterConfig,
+    base_topic: String,
+    /// Route and normalized text and the start of their duplicate window
+    recent_messages: HashMap<(String, String), Instant>,
+    /// Accepted messages per source or topic within the rate limit period
+    rate_windows: HashMap<String, VecDeque<Instant>>,
+    dropped_duplicates: u64,
+    dropped_rate_limited: u64,
+}
+
+impl MessageFilter {
+    pub fn new(config: MessageFilterConfig, base_topic: &str) -> Self {
+        Self {
+            config,
+            base_topic: base_topic.to_owned(),
+            recent_messages: HashMap::new(),
+            rate_windows: HashMap::new(),
+            dropped_duplicates: 0,
+            dropped_rate_limited: 0,
+        }
+    }
+
+    /// Returns false for messages that should be dropped
+    pub fn admit(&mut self, topic: &str, payload: &[u8]) -> bool {
+        self.admit_at(topic, payload, Instant::now())
+    }
+
+    fn admit_at(&mut self, topic: &str, payload: &[u8], now: Instant) -> bool {
+        let route = topic
+            .strip_prefix(&self.base_topic)
+            .unwrap_or(topic)
+            .trim_start_matches('/')
+            .to_owned();
+        if !self
+            .config
+            .routes
+            .iter()
+            .any(|prefix| is_route_prefix(prefix, &route))
+        {
+            return true;
+        }
+
+        let (text, source) = parse_request(payload);
+        let duplicate_key = (route.clone(), text);
+        if self.is_duplicate(&duplicate_key, now) {
+            self.dropped_duplicates += 1;
+            warn!(
+                "Dropping duplicate message {:?} on {}. Dropped {} duplicates so far",
+                duplicate_key.1, topic, self.dropped_duplicates
+            );
+            return false;
+        }
+        if self.is_rate_limited(&route, topic, source.as_deref(), now) {
+            self.dropped_rate_limited += 1;
+            warn!(
+                "Dropping rate limited message {:?} on {} from {:?}. Dropped {} rate limited messages so far",
+                duplicate_key.1, topic, source, self.dropped_rate_limited
+            );
+            return false;
+        }
+        if !self.config.duplicate_window().is_zero() {
+            self.recent_messages.insert(duplicate_key, now);
+        }
+        true
+    }
+
+    fn is_duplicate(&mut self, key: &(String, String), now: Instant) -> bool {
+        let window = self.config.duplicate_window();
+        if window.is_zero() {
+            return false;
+        }
+        self.recent_messages
+            .retain(|_, seen| now.duration_since(*seen) < window);
+        match self.recent_messages.get_mut(key) {
+            Some(seen) => {
+                if self.config.duplicate_mode == DuplicateMode::Coalesce {
+                    *seen = now;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Counts the message against the limit if it's accepted
+    fn is_rate_limited(
+        &mut self,
+        route: &str,
+        topic: &str,
+        source: Option<&str>,
+        now: Instant,
+    ) -> bool {
+        self.prune_rate_windows(now);
+        let Some(limit) = self.rate_limit_for_route(route) else {
+            return false;
+        };
+        let key = match source {
+            Some(source) => format!("source:{}", source),
+            None => format!("topic:{}", topic),
+        };
+        let accepted = self.rate_windows.entry(key).or_default();
+        while accepted
+            .front()
+            .map(|first| now.duration_since(*first) >= limit.period())
+            .unwrap_or(false)
+        {
+            accepted.pop_front();
+        }
+        if accepted.len() >= limit.max_messages {
+            return true;
+        }
+        accepted.push_back(now);
+        false
+    }
+
+    /// Forget sources and topics that haven't sent anything within the longest period
+    ///
+    /// Sources are chosen by clients so they would otherwise grow without bound.
+    fn prune_rate_windows(&mut self, now: Instant) {
+        let longest_period = self
+            .config
+            .route_rate_limits
+            .values()
+            .chain(self.config.rate_limit.iter())
+            .map(RateLimitConfig::period)
+            .max()
+            .unwrap_or(Duration::ZERO);
+        self.rate_windows.retain(|_, accepted| {
+            accepted
+                .back()
+                .map(|last| now.duration_since(*last) < longest_period)
+                .unwrap_or(false)
+        });
+    }
+
+    fn rate_limit_for_route(&self, route: &str) -> Option<RateLimitConfig> {
+        self.config
+            .route_rate_limits
+            .iter()
+            .filter(|(prefix, _)| is_route_prefix(prefix, route))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, limit)| *limit)
+            .or(self.config.rate_limit)
+    }
+}
+
+/// Normalized text and the optional source of a request
+fn parse_request(payload: &[u8]) -> (String, Option<String>) {
+    let payload = String::from_utf8_lossy(payload);
+    match serde_json::from_str::<Value>(&payload) {
+        Ok(Value::Object(fields)) => {
+            let text = fields
+                .get("content")
+                .and_then(Value::as_str)
+                .unwrap_or(&payload);
+            let source = fields
+                .get("source")
+                .and_then(Value::as_str)
+                .map(str::to_owned);
+            (normalize(text), source)
+        }
+        _ => (normalize(&payload), None),
+    }
+}
+
+/// Case, punctuation and spacing don't make a message different
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE_TOPIC: &str = "home_speak";
+
+    fn filter(config: MessageFilterConfig) -> MessageFilter {
+        MessageFilter::new(config, BASE_TOPIC)
+    }
+
+    fn duplicate_filter(mode: DuplicateMode) -> MessageFilter {
+        filter(MessageFilterConfig {
+            duplicate_window_ms: 1_000,
+            duplicate_mode: mode,
+            ..Default::default()
+        })
+    }
+
+    fn rate_limit(max_messages: usize, period_ms: u64) -> RateLimitConfig {
+        RateLimitConfig {
+            max_messages,
+            period_ms,
+        }
+    }
+
+    fn ms(milliseconds: u64) -> Duration {
+        Duration::from_millis(milliseconds)
+    }
+
+    #[test]
+    fn normalization_ignores_case_punctuation_and_spacing() {
+        assert_eq!(normalize("  Hello,   World! "), "hello world");
+        assert_eq!(normalize("Door is OPEN..."), "door is open");
+        assert_eq!(normalize("- ? !"), "");
+        assert_eq!(normalize("Příliš ŽLUŤOUČKÝ"), "příliš žluťoučký");
+    }
+
+    #[test]
+    fn json_requests_are_compared_by_content() {
+        let (text, source) = parse_request(br#"{"content": "Hello there!", "source": "door"}"#);
+        assert_eq!(text, "hello there");
+        assert_eq!(source.as_deref(), Some("door"));
+        let (text, source) = parse_request(b"Hello THERE");
+        assert_eq!(text, "hello there");
+        assert_eq!(source, None);
+    }
+
+    #[test]
+    fn drop_mode_window_starts_at_first_message() {
+        let mut filter = duplicate_filter(DuplicateMode::Drop);
+        let start = Instant::now();
+        assert!(filter.admit_at("home_speak/say", b"Hello", start));
+        assert!(!filter.admit_at("home_speak/say", b"hello!", start + ms(600)));
+        assert!(filter.admit_at("home_speak/say", b"hello", start + ms(1_100)));
+    }
+
+    #[test]
+    fn coalesce_mode_extends_window_with_every_duplicate() {
+        let mut filter = duplicate_filter(DuplicateMode::Coalesce);
+        let start = Instant::now();
+        assert!(filter.admit_at("home_speak/say", b"Hello", start));
+        assert!(!filter.admit_at("home_speak/say", b"hello", start + ms(600)));
+        assert!(!filter.admit_at("home_speak/say", b"hello", start + ms(1_200)));
+        assert!(filter.admit_at("home_speak/say", b"hello", start + ms(2_300)));
+    }
+
+    #[test]
+    fn same_text_on_other_routes_is_not_a_duplicate() {
+        let mut filter = duplicate_filter(DuplicateMode::Drop);
+        let start = Instant::now();
+        assert!(filter.admit_at("home_speak/say/eleven", b"Hello", start));
+        assert!(filter.admit_at("home_speak/say/plain", b"Hello", start));
+        assert!(!filter.admit_at("home_speak/say/plain", b"Hello", start));
+    }
+
+    #[test]
+    fn unfiltered_routes_pass() {
+        let mut filter = duplicate_filter(DuplicateMode::Drop);
+        let start = Instant::now();
+        assert!(filter.admit_at("home_speak/volume", b"0.5", start));
+        assert!(filter.admit_at("home_speak/volume", b"0.5", start));
+    }
+
+    #[test]
+    fn rate_limit_is_per_source_or_topic() {
+        let mut filter = filter(MessageFilterConfig {
+            rate_limit: Some(rate_limit(1, 1_000)),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        assert!(filter.admit_at("home_speak/say", b"one", start));
+        assert!(!filter.admit_at("home_speak/say", b"two", start));
+        assert!(filter.admit_at("home_speak/say/eleven", b"three", start));
+        assert!(filter.admit_at(
+            "home_speak/say",
+            br#"{"content": "four", "source": "a"}"#,
+            start
+        ));
+        assert!(!filter.admit_at(
+            "home_speak/say/eleven",
+            br#"{"content": "five", "source": "a"}"#,
+            start
+        ));
+        assert!(filter.admit_at("home_speak/say", b"six", start + ms(1_000)));
+    }
+
+    #[test]
+    fn most_specific_route_override_wins() {
+        let mut filter = filter(MessageFilterConfig {
+            rate_limit: Some(rate_limit(1, 1_000)),
+            route_rate_limits: HashMap::from([
+                ("say".to_owned(), rate_limit(2, 1_000)),
+                ("say/eleven".to_owned(), rate_limit(3, 1_000)),
+            ]),
+            ..Default::default()
+        });
+        assert_eq!(
+            filter
+                .rate_limit_for_route("say/eleven/x")
+                .unwrap()
+                .max_messages,
+            3
+        );
+        assert_eq!(
+            filter
+                .rate_limit_for_route("say/plain")
+                .unwrap()
+                .max_messages,
+            2
+        );
+        assert_eq!(
+            filter
+                .rate_limit_for_route("sequence")
+                .unwrap()
+                .max_messages,
+            1
+        );
+        // prefixes only match whole route segments
+        assert_eq!(
+            filter.rate_limit_for_route("sayings").unwrap().max_messages,
+            1
+        );
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(filter.admit_at("home_speak/say/eleven", b"hi", start));
+        }
+        assert!(!filter.admit_at("home_speak/say/eleven", b"hi", start));
+    }
+
+    #[test]
+    fn idle_sources_are_forgotten() {
+        let mut filter = filter(MessageFilterConfig {
+            rate_limit: Some(rate_limit(5, 1_000)),
+            ..Default::default()
+        });
+        let start = Instant::now();
+        for source in ["a", "b", "c"] {
+            let payload = format!(r#"{{"content": "hi", "source": "{}"}}"#, source);
+            assert!(filter.admit_at("home_speak/say", payload.as_bytes(), start));
+        }
+        assert_eq!(filter.rate_windows.len(), 3);
+        assert!(filter.admit_at("home_speak/say", b"hi", start + ms(1_000)));
+        assert_eq!(filter.rate_windows.len(), 1);
+    }
+}
@@ -1,3 +1,4 @@
+mod message_filter;
 mod mqtt_server;
 mod routes;
 
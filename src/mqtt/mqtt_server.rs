@@ -1,18 +1,30 @@
-use super::routes::{SayHandler, SayMoodHandler};
+use super::message_filter::MessageFilter;
+use super::routes::{
+    AlarmCommand, AlarmHandler, DndHandler, ErrorReporter, EventReporter, PlayInboxHandler,
+    ReloadSchedulesHandler, SayHandler, SayMoodHandler, SequenceHandler, SpeechTaskSpawner,
+};
 use crate::{
+    alarms::AlarmScheduler,
     configuration::AppConfig,
+    inbox::Inbox,
     mqtt::routes::{
-        Mp3AudioPlayerHandler, PlayAudioFileHandler, RestartRequestHandler,
+        LibraryChange, LibraryChangeHandler, LibraryPlayHandler, LibraryPlayMode,
+        Mp3AudioPlayerHandler, PlayAudioFileHandler, PlaybackMode, RestartRequestHandler,
         SayElevenCustomVoiceHandler, SayElevenDefaultHandler, SkipOneRequestHandler,
+        StopBackgroundRequestHandler,
     },
+    quiet_hours::QuietHours,
+    schedules::{ScheduleRunner, SystemClock},
     speech_service::{
-        AudioRepository, AudioService, AzureVoiceStyle, ElevenSpeechService, SpeechService,
+        AudioRepository, AudioService, AzureVoiceStyle, ElevenSpeechService, PreRoll,
+        SequencePlayer, SpeechService,
     },
+    template_messages::TemplateEngine,
 };
 use mqtt_router::Router;
 use rumqttc::{AsyncClient, ConnAck, Event, Incoming, MqttOptions, Publish, QoS, SubscribeFilter};
-use std::{sync::Arc, time::Duration};
-use tokio::sync::{mpsc::unbounded_channel, Mutex};
+use std::{path::PathBuf, sync::Arc, time::Duration};
+use tokio::sync::mpsc::unbounded_channel;
 use tracing::*;
 
 enum MqttUpdate {
@@ -22,12 +34,15 @@ enum MqttUpdate {
 
 const MQTT_MAX_PACKET_SIZE: usize = 268435455;
 
+/// The config path is used to reload schedules
 pub fn start_mqtt_service(
     app_config: AppConfig,
-    speech_service: Arc<Mutex<SpeechService>>,
+    config_path: Option<PathBuf>,
+    speech_service: Arc<SpeechService>,
     eleven_speech_service: ElevenSpeechService,
     audio_service: AudioService,
     audio_repository: AudioRepository,
+    template_engine: TemplateEngine,
 ) -> anyhow::Result<AsyncClient> {
     let mut mqttoptions = MqttOptions::new(
         &app_config.mqtt.client_id,
@@ -42,9 +57,64 @@ pub fn start_mqtt_service(
     let client_clone = client.clone();
 
     let base_topic = app_config.mqtt.base_route;
+    let pre_roll = PreRoll::new(app_config.pre_roll, &base_topic, audio_repository.clone());
+    let quiet_hours = QuietHours::new(app_config.quiet_hours, app_config.dnd.bypass_priority)
+        .with_dnd_state(app_config.dnd.state_path);
+    quiet_hours.start_volume_schedule(audio_service.clone());
+    let sequence_player = SequencePlayer::new(
+        speech_service,
+        eleven_speech_service,
+        audio_repository.clone(),
+        audio_service.clone(),
+    );
+    let spawner = SpeechTaskSpawner::new(
+        audio_service.clone(),
+        pre_roll,
+        quiet_hours.clone(),
+        sequence_player,
+        Inbox::load(app_config.dnd.inbox_path),
+        EventReporter::new(client.clone(), format!("{}/events", base_topic)),
+        app_config.tts_service_config.max_concurrent_requests,
+    );
+    let error_reporter = ErrorReporter::new(client.clone(), format!("{}/error", base_topic));
+    // alarms and schedules go through the spawner so that quiet hours apply to them
+    let (timed_sender, mut timed_receiver) = unbounded_channel();
+    let alarm_scheduler = AlarmScheduler::load(app_config.alarm_config.save_file_path);
+    alarm_scheduler.start(timed_sender.clone());
+    let schedule_runner = ScheduleRunner::new(
+        app_config.schedules,
+        app_config.schedule_state_path,
+        template_engine.clone(),
+        Arc::new(SystemClock),
+    );
+    schedule_runner.start(timed_sender);
+    let timed_spawner = spawner.clone();
+    tokio::spawn(async move {
+        while let Some(request) = timed_receiver.recv().await {
+            timed_spawner.spawn(request);
+        }
+    });
 
     info!("MQTT base topic {}", base_topic);
 
+    start_catalog_publisher(
+        client.clone(),
+        format!("{}/library", base_topic),
+        audio_repository.clone(),
+    );
+    start_dnd_status_publisher(
+        client.clone(),
+        format!("{}/dnd/status", base_topic),
+        quiet_hours.clone(),
+    );
+    start_alarm_list_publisher(
+        client.clone(),
+        format!("{}/alarms", base_topic),
+        alarm_scheduler.clone(),
+    );
+
+    let mut message_filter = MessageFilter::new(app_config.message_filter, &base_topic);
+
     let (message_sender, mut message_receiver) = unbounded_channel();
 
     tokio::spawn(async move {
@@ -77,49 +147,53 @@ pub fn start_mqtt_service(
         router
             .add_handler(
                 &format!("{}/say", base_topic),
-                SayHandler::new(speech_service.clone()),
+                SayHandler::new(
+                    spawner.clone(),
+                    template_engine.clone(),
+                    error_reporter.clone(),
+                ),
             )
             .unwrap();
 
         router
             .add_handler(
                 &format!("{}/say/cheerful", base_topic),
-                SayMoodHandler::new(speech_service.clone(), AzureVoiceStyle::Cheerful),
+                SayMoodHandler::new(spawner.clone(), AzureVoiceStyle::Cheerful),
             )
             .unwrap();
 
         router
             .add_handler(
                 &format!("{}/say/angry", base_topic),
-                SayMoodHandler::new(speech_service.clone(), AzureVoiceStyle::Angry),
+                SayMoodHandler::new(spawner.clone(), AzureVoiceStyle::Angry),
             )
             .unwrap();
 
         router
             .add_handler(
                 &format!("{}/say/sad", base_topic),
-                SayMoodHandler::new(speech_service.clone(), AzureVoiceStyle::Sad),
+                SayMoodHandler::new(spawner.clone(), AzureVoiceStyle::Sad),
             )
             .unwrap();
 
         router
             .add_handler(
                 &format!("{}/say/plain", base_topic),
-                SayMoodHandler::new(speech_service.clone(), AzureVoiceStyle::Plain),
+                SayMoodHandler::new(spawner.clone(), AzureVoiceStyle::Plain),
             )
             .unwrap();
 
         router
             .add_handler(
                 &format!("{}/say/eleven/simple", base_topic),
-                SayElevenDefaultHandler::new(eleven_speech_service.clone()),
+                SayElevenDefaultHandler::new(spawner.clone()),
             )
             .unwrap();
 
         router
             .add_handler(
                 &format!("{}/say/eleven/voice/+", base_topic),
-                SayElevenCustomVoiceHandler::new(eleven_speech_service.clone()),
+                SayElevenCustomVoiceHandler::new(spawner.clone()),
             )
             .unwrap();
 
@@ -130,6 +204,27 @@ pub fn start_mqtt_service(
             )
             .unwrap();
 
+        router
+            .add_handler(
+                &format!("{}/play/urgent", base_topic),
+                Mp3AudioPlayerHandler::with_mode(audio_service.clone(), PlaybackMode::Urgent),
+            )
+            .unwrap();
+
+        router
+            .add_handler(
+                &format!("{}/play/background", base_topic),
+                Mp3AudioPlayerHandler::with_mode(audio_service.clone(), PlaybackMode::Background),
+            )
+            .unwrap();
+
+        router
+            .add_handler(
+                &format!("{}/background/stop", base_topic),
+                StopBackgroundRequestHandler::new(audio_service.clone()),
+            )
+            .unwrap();
+
         router
             .add_handler(
                 &format!("{}/restart", base_topic),
@@ -147,10 +242,167 @@ pub fn start_mqtt_service(
         router
             .add_handler(
                 &format!("{}/play_file", base_topic),
-                PlayAudioFileHandler::new(audio_repository.clone()),
+                PlayAudioFileHandler::new(audio_repository.clone(), error_reporter.clone()),
             )
             .unwrap();
 
+        router
+            .add_handler(
+                &format!("{}/library/play", base_topic),
+                LibraryPlayHandler::new(
+                    audio_repository.clone(),
+                    error_reporter.clone(),
+                    LibraryPlayMode::ByName,
+                ),
+            )
+            .unwrap();
+
+        router
+            .add_handler(
+                &format!("{}/library/play_tag", base_topic),
+                LibraryPlayHandler::new(
+                    audio_repository.clone(),
+                    error_reporter.clone(),
+                    LibraryPlayMode::Tag,
+                ),
+            )
+            .unwrap();
+
+        router
+            .add_handler(
+                &format!("{}/library/play_random_tag", base_topic),
+                LibraryPlayHandler::new(
+                    audio_repository.clone(),
+                    error_reporter.clone(),
+                    LibraryPlayMode::RandomWithTag,
+                ),
+            )
+            .unwrap();
+
+        router
+            .add_handler(
+                &format!("{}/play_random", base_topic),
+                LibraryPlayHandler::new(
+                    audio_repository.clone(),
+                    error_reporter.clone(),
+                    LibraryPlayMode::Random,
+                ),
+            )
+            .unwrap();
+
+        router
+            .add_handler(
+                &format!("{}/play_random_from_dir", base_topic),
+                LibraryPlayHandler::new(
+                    audio_repository.clone(),
+                    error_reporter.clone(),
+                    LibraryPlayMode::RandomFromDir,
+                ),
+            )
+            .unwrap();
+
+        router
+            .add_handler(
+                &format!("{}/play_random/+", base_topic),
+                LibraryPlayHandler::new(
+                    audio_repository.clone(),
+                    error_reporter.clone(),
+                    LibraryPlayMode::RandomFromTopicDir,
+                ),
+            )
+            .unwrap();
+
+        let named_sequences = Arc::new(app_config.sequences);
+
+        router
+            .add_handler(
+                &format!("{}/sequence", base_topic),
+                SequenceHandler::new(
+                    spawner.clone(),
+                    named_sequences.clone(),
+                    error_reporter.clone(),
+                    false,
+                ),
+            )
+            .unwrap();
+
+        router
+            .add_handler(
+                &format!("{}/sequence/+", base_topic),
+                SequenceHandler::new(
+                    spawner.clone(),
+                    named_sequences.clone(),
+                    error_reporter.clone(),
+                    true,
+                ),
+            )
+            .unwrap();
+
+        if app_config.audio_library.allow_remote_changes {
+            router
+                .add_handler(
+                    &format!("{}/library/upload/+", base_topic),
+                    LibraryChangeHandler::new(
+                        audio_repository.clone(),
+                        error_reporter.clone(),
+                        LibraryChange::Upload,
+                    ),
+                )
+                .unwrap();
+
+            router
+                .add_handler(
+                    &format!("{}/library/delete/+", base_topic),
+                    LibraryChangeHandler::new(
+                        audio_repository.clone(),
+                        error_reporter.clone(),
+                        LibraryChange::Delete,
+                    ),
+                )
+                .unwrap();
+        } else {
+            info!("Remote changes to the audio library are disabled");
+        }
+
+        router
+            .add_handler(
+                &format!("{}/dnd", base_topic),
+                DndHandler::new(quiet_hours.clone()),
+            )
+            .unwrap();
+
+        router
+            .add_handler(
+                &format!("{}/inbox/play", base_topic),
+                PlayInboxHandler::new(spawner.clone()),
+            )
+            .unwrap();
+
+        router
+            .add_handler(
+                &format!("{}/schedules/reload", base_topic),
+                ReloadSchedulesHandler::new(
+                    schedule_runner.clone(),
+                    config_path.clone(),
+                    error_reporter.clone(),
+                ),
+            )
+            .unwrap();
+
+        for command in [
+            AlarmCommand::Create,
+            AlarmCommand::Delete,
+            AlarmCommand::Snooze,
+            AlarmCommand::List,
+        ] {
+            router
+                .add_handler(
+                    &format!("{}/{}", base_topic, command.route_name()),
+                    AlarmHandler::new(alarm_scheduler.clone(), error_reporter.clone(), command),
+                )
+                .unwrap();
+        }
+
         let topics = router
             .topics_for_subscription()
             .map(|topic| SubscribeFilter {
@@ -163,6 +415,9 @@ pub fn start_mqtt_service(
             let update = message_receiver.recv().await.unwrap();
             match update {
                 MqttUpdate::Message(message) => {
+                    if !message_filter.admit(&message.topic, &message.payload) {
+                        continue;
+                    }
                     match router
                         .handle_message_ignore_errors(&message.topic, &message.payload)
                         .await
@@ -187,3 +442,68 @@ pub fn start_mqtt_service(
 
     Ok(client_clone)
 }
+
+/// Publish the sound catalog as a retained message every time the index changes
+fn start_catalog_publisher(client: AsyncClient, topic: String, audio_repository: AudioRepository) {
+    let mut index_updates = audio_repository.subscribe_index_updates();
+    tokio::spawn(async move {
+        loop {
+            match serde_json::to_vec(&audio_repository.catalog()) {
+                Ok(payload) => {
+                    if let Err(e) = client
+                        .publish(&topic, QoS::AtLeastOnce, true, payload)
+                        .await
+                    {
+                        error!("Failed to publish sound catalog {:?}", e);
+                    }
+                }
+                Err(e) => error!("Failed to serialize sound catalog {:?}", e),
+            }
+            if index_updates.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Publish the do not disturb state as a retained message every time it changes
+fn start_dnd_status_publisher(client: AsyncClient, topic: String, quiet_hours: QuietHours) {
+    let mut dnd_updates = quiet_hours.subscribe_dnd();
+    tokio::spawn(async move {
+        loop {
+            let dnd = quiet_hours.dnd();
+            if let Err(e) = client
+                .publish(&topic, QoS::AtLeastOnce, true, dnd.as_str())
+                .await
+            {
+                error!("Failed to publish do not disturb status {:?}", e);
+            }
+            if dnd_updates.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Publish all alarms as a retained message every time they change
+fn start_alarm_list_publisher(client: AsyncClient, topic: String, alarm_scheduler: AlarmScheduler) {
+    let mut changes = alarm_scheduler.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match serde_json::to_vec(&alarm_scheduler.alarms()) {
+                Ok(payload) => {
+                    if let Err(e) = client
+                        .publish(&topic, QoS::AtLeastOnce, true, payload)
+                        .await
+                    {
+                        error!("Failed to publish alarms {:?}", e);
+                    }
+                }
+                Err(e) => error!("Failed to serialize alarms {:?}", e),
+            }
+            if changes.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+}
@@ -1,24 +1,313 @@
 use crate::{
+    alarms::{AlarmScheduler, CreateAlarm, SnoozeAlarm},
+    configuration::get_configuration,
+    error::{AudioRepositoryError, TemplateError},
+    inbox::{Inbox, SpeechRequest},
+    quiet_hours::{Admission, DndOverride, Priority, QuietHours},
+    schedules::ScheduleRunner,
     speech_service::{
-        AudioRepository, AudioService, AzureVoiceStyle, ElevenSpeechService, SpeechService,
+        AudioRepository, AudioService, AzureVoiceStyle, Deadline, PreRoll, SequencePlayer,
+        SequenceStep, SpeechProvider,
     },
     template_messages::TemplateEngine,
 };
 use anyhow::Context;
 use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use mqtt_router::RouteHandler;
-use serde::Deserialize;
-use std::{io::Cursor, str::from_utf8, sync::Arc};
-use tokio::sync::Mutex;
+use rumqttc::{AsyncClient, QoS};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Cursor,
+    path::PathBuf,
+    str::from_utf8,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::Semaphore;
 use tracing::*;
 
+/// Say requests held back until quiet hours end, in arrival order
+type DelayedSpeech = Arc<Mutex<VecDeque<SpeechRequest>>>;
+
+/// How often delayed speech checks whether quiet hours ended
+const DELAYED_SPEECH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Route used for the pre-roll of inbox playback that wasn't requested over MQTT
+const INBOX_ROUTE: &str = "inbox";
+
+/// Runs speech requests in the background so that the router can keep handling messages
+///
+/// The playback slot is reserved before spawning so that messages still play in arrival order.
+#[derive(Debug, Clone)]
+pub struct SpeechTaskSpawner {
+    audio_service: AudioService,
+    pre_roll: PreRoll,
+    quiet_hours: QuietHours,
+    sequence_player: SequencePlayer,
+    inbox: Inbox,
+    event_reporter: EventReporter,
+    delayed: DelayedSpeech,
+    concurrency_limit: Arc<Semaphore>,
+}
+
+impl SpeechTaskSpawner {
+    pub fn new(
+        audio_service: AudioService,
+        pre_roll: PreRoll,
+        quiet_hours: QuietHours,
+        sequence_player: SequencePlayer,
+        inbox: Inbox,
+        event_reporter: EventReporter,
+        max_concurrent_requests: usize,
+    ) -> Self {
+        let spawner = Self {
+            audio_service,
+            pre_roll,
+            quiet_hours,
+            sequence_player,
+            inbox,
+            event_reporter,
+            delayed: DelayedSpeech::default(),
+            concurrency_limit: Arc::new(Semaphore::new(max_concurrent_requests.max(1))),
+        };
+        spawner.start_quiet_hours_release();
+        spawner
+    }
+
+    pub fn spawn(&self, request: SpeechRequest) {
+        let priority = request.priority;
+        match self.quiet_hours.admit(priority) {
+            Admission::Play { volume } => self.spawn_now(request, volume),
+            Admission::Drop => info!(
+                "Dropping {:?} priority message during quiet hours",
+                priority
+            ),
+            Admission::Delay => {
+                info!(
+                    "Delaying {:?} priority message until quiet hours end",
+                    priority
+                );
+                self.delayed.lock().unwrap().push_back(request);
+            }
+            Admission::Inbox => {
+                info!("Storing {:?} priority message in inbox", priority);
+                if let Err(e) = self.inbox.push(request) {
+                    error!("Failed to store message in inbox {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Expired requests are reported instead of played
+    ///
+    /// That includes requests that expire while waiting in the playback queue.
+    fn spawn_now(&self, request: SpeechRequest, volume: f32) {
+        if request.is_expired() {
+            self.report_expired(request);
+            return;
+        }
+        let (deadline, mut expired) = match request.deadline() {
+            Some(deadline) => {
+                let (deadline, expired) = Deadline::new(deadline);
+                (Some(deadline), Some(expired))
+            }
+            None => (None, None),
+        };
+        let slot = self.audio_service.reserve_slot_with(volume, deadline);
+        self.pre_roll.play_before_speech(&request.topic, &slot);
+        let sequence_player = self.sequence_player.clone();
+        let event_reporter = self.event_reporter.clone();
+        let concurrency_limit = self.concurrency_limit.clone();
+        tokio::spawn(async move {
+            let permit = concurrency_limit.acquire_owned().await;
+            if request.is_expired() {
+                info!("Request expired before it was synthesized");
+                event_reporter.report_expired(&request).await;
+                return;
+            }
+            if let Err(e) = sequence_player.play_into(&request.steps, &slot).await {
+                error!("Failed to call speech service {:?}", e);
+            }
+            drop(permit);
+            drop(slot);
+            // resolves with an error once all sounds are played without expiring
+            if let Some(expired) = &mut expired {
+                let discarded = expired.wait_for(|expired| *expired).await.is_ok();
+                if discarded {
+                    event_reporter.report_expired(&request).await;
+                }
+            }
+        });
+    }
+
+    fn report_expired(&self, request: SpeechRequest) {
+        info!("Discarding expired request from {}", request.topic);
+        let event_reporter = self.event_reporter.clone();
+        tokio::spawn(async move {
+            event_reporter.report_expired(&request).await;
+        });
+    }
+
+    /// Announce how many messages are waiting and play them
+    pub fn play_inbox(&self, topic: &str) -> anyhow::Result<()> {
+        self.inbox.take_all(|messages| {
+            let (expired, messages): (Vec<_>, Vec<_>) =
+                messages.into_iter().partition(SpeechRequest::is_expired);
+            for request in expired {
+                self.report_expired(request);
+            }
+            let announcement = match messages.len() {
+                0 => "You have no messages".to_owned(),
+                1 => "You have 1 message".to_owned(),
+                count => format!("You have {} messages", count),
+            };
+            info!("Playing inbox with {} messages", messages.len());
+            self.spawn_now(
+                SpeechRequest {
+                    topic: topic.to_owned(),
+                    priority: Priority::Normal,
+                    expires_at: None,
+                    steps: vec![say_step(announcement, AzureVoiceStyle::default())],
+                },
+                1.0,
+            );
+            for message in messages {
+                self.spawn_now(message, 1.0);
+            }
+        })
+    }
+
+    /// Plays delayed messages once quiet hours end and the inbox once do not disturb ends
+    ///
+    /// Messages left in the inbox by a previous run play right away unless do not disturb is on.
+    fn start_quiet_hours_release(&self) {
+        let spawner = self.clone();
+        let mut dnd_updates = self.quiet_hours.subscribe_dnd();
+        tokio::spawn(async move {
+            let mut dnd = spawner.quiet_hours.dnd();
+            if dnd != DndOverride::On && !spawner.inbox.is_empty() {
+                if let Err(e) = spawner.play_inbox(INBOX_ROUTE) {
+                    error!("Failed to play inbox {:?}", e);
+                }
+            }
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(DELAYED_SPEECH_CHECK_INTERVAL) => (),
+                    changed = dnd_updates.changed() => if changed.is_err() {
+                        break;
+                    },
+                }
+                let previous_dnd = dnd;
+                dnd = spawner.quiet_hours.dnd();
+                if previous_dnd == DndOverride::On
+                    && dnd != DndOverride::On
+                    && !spawner.inbox.is_empty()
+                {
+                    if let Err(e) = spawner.play_inbox(INBOX_ROUTE) {
+                        error!("Failed to play inbox {:?}", e);
+                    }
+                }
+
+                if spawner.quiet_hours.is_quiet() {
+                    continue;
+                }
+                let delayed: Vec<_> = spawner.delayed.lock().unwrap().drain(..).collect();
+                if !delayed.is_empty() {
+                    info!(
+                        "Quiet hours ended. Playing {} delayed messages",
+                        delayed.len()
+                    );
+                }
+                for request in delayed {
+                    spawner.spawn_now(request, 1.0);
+                }
+            }
+        });
+    }
+}
+
+fn say_step(text: String, style: AzureVoiceStyle) -> SequenceStep {
+    SequenceStep::Say {
+        text,
+        provider: SpeechProvider::Azure,
+        style,
+        voice: None,
+    }
+}
+
+/// Changes the do not disturb override of quiet hours
+///
+/// Accepts `on`, `off` or `auto` to follow the configured schedule again.
+pub struct DndHandler {
+    quiet_hours: QuietHours,
+}
+
+impl DndHandler {
+    pub fn new(quiet_hours: QuietHours) -> Box<Self> {
+        Box::new(Self { quiet_hours })
+    }
+}
+
+#[async_trait]
+impl RouteHandler for DndHandler {
+    #[instrument(skip(self, content))]
+    async fn call(
+        &mut self,
+        _topic: &str,
+        content: &[u8],
+    ) -> std::result::Result<(), anyhow::Error> {
+        let dnd: DndOverride = from_utf8(content)?.parse()?;
+        self.quiet_hours.set_dnd(dnd);
+        Ok(())
+    }
+}
+
+pub struct PlayInboxHandler {
+    spawner: SpeechTaskSpawner,
+}
+
+impl PlayInboxHandler {
+    pub fn new(spawner: SpeechTaskSpawner) -> Box<Self> {
+        Box::new(Self { spawner })
+    }
+}
+
+#[async_trait]
+impl RouteHandler for PlayInboxHandler {
+    #[instrument(skip(self, _content))]
+    async fn call(
+        &mut self,
+        topic: &str,
+        _content: &[u8],
+    ) -> std::result::Result<(), anyhow::Error> {
+        info!("Play inbox request");
+        if let Err(e) = self.spawner.play_inbox(topic) {
+            error!("Failed to play inbox {:?}", e);
+        }
+        Ok(())
+    }
+}
+
 pub struct SayHandler {
-    speech_service: Arc<Mutex<SpeechService>>,
+    spawner: SpeechTaskSpawner,
+    template_engine: TemplateEngine,
+    error_reporter: ErrorReporter,
 }
 
 impl SayHandler {
-    pub fn new(speech_service: Arc<Mutex<SpeechService>>) -> Box<Self> {
-        Box::new(Self { speech_service })
+    pub fn new(
+        spawner: SpeechTaskSpawner,
+        template_engine: TemplateEngine,
+        error_reporter: ErrorReporter,
+    ) -> Box<Self> {
+        Box::new(Self {
+            spawner,
+            template_engine,
+            error_reporter,
+        })
     }
 }
 
@@ -27,53 +316,117 @@ impl RouteHandler for SayHandler {
     #[instrument(skip(self, content))]
     async fn call(
         &mut self,
-        _topic: &str,
+        topic: &str,
         content: &[u8],
     ) -> std::result::Result<(), anyhow::Error> {
         info!("mqtt say command");
         let command: SayCommand = serde_json::from_slice(content)?;
+        let request = command.template_name.as_deref().unwrap_or(&command.content);
 
-        let message = if command.template {
-            TemplateEngine::template_substitute(&command.content)
-        } else {
-            command.content.clone()
+        let expires_at = match command.expires_at(Utc::now()) {
+            Ok(expires_at) => expires_at,
+            Err(e) => {
+                error!("Rejecting say request {:?}", e);
+                self.error_reporter.report("say", request, &e).await;
+                return Ok(());
+            }
         };
-
-        match self
-            .speech_service
-            .lock()
-            .await
-            .say_azure_with_style(&message, command.style)
-            .await
-        {
-            Ok(_) => (),
+        let step = match self.step(&command) {
+            Ok(step) => step,
             Err(e) => {
-                error!("Failed to call speech service {}", e);
+                error!("Failed to render template {:?}", e);
+                self.error_reporter.report("say", request, &e.into()).await;
+                return Ok(());
             }
-        }
+        };
+
+        self.spawner.spawn(SpeechRequest {
+            topic: topic.to_owned(),
+            priority: command.priority,
+            expires_at,
+            steps: vec![step],
+        });
         Ok(())
     }
 }
 
+impl SayHandler {
+    /// Style from the command takes precedence over the named template's default
+    fn step(&self, command: &SayCommand) -> Result<SequenceStep, TemplateError> {
+        if let Some(template_name) = &command.template_name {
+            let rendered = self
+                .template_engine
+                .render_named(template_name, &command.context)?;
+            return Ok(SequenceStep::Say {
+                text: rendered.text,
+                provider: rendered.provider.unwrap_or_default(),
+                style: command.style.or(rendered.style).unwrap_or_default(),
+                voice: rendered.voice,
+            });
+        }
+        let message = if command.template {
+            self.template_engine
+                .render(&command.content, &command.context)?
+        } else {
+            command.content.clone()
+        };
+        Ok(say_step(message, command.style.unwrap_or_default()))
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct SayCommand {
+    /// Not needed when `template_name` is set
+    #[serde(default)]
     content: String,
-    style: AzureVoiceStyle,
+    #[serde(default)]
+    style: Option<AzureVoiceStyle>,
     #[serde(default)]
     template: bool,
+    /// Named template to say instead of `content`
+    #[serde(default)]
+    template_name: Option<String>,
+    /// Available to the template as `context`
+    #[serde(default, alias = "parameters")]
+    context: serde_json::Value,
+    #[serde(default)]
+    priority: Priority,
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    ttl_seconds: Option<u64>,
+}
+
+impl SayCommand {
+    /// The earlier of the two if both are set
+    ///
+    /// Fails for a TTL that doesn't fit into a date.
+    fn expires_at(&self, now: DateTime<Utc>) -> anyhow::Result<Option<DateTime<Utc>>> {
+        let ttl_expiry = match self.ttl_seconds {
+            Some(ttl) => Some(
+                // from_std fails instead of panicking like Duration::seconds
+                chrono::Duration::from_std(Duration::from_secs(ttl))
+                    .ok()
+                    .and_then(|ttl| now.checked_add_signed(ttl))
+                    .with_context(|| format!("ttl_seconds {} is out of range", ttl))?,
+            ),
+            None => None,
+        };
+        Ok(match (self.expires_at, ttl_expiry) {
+            (Some(expires_at), Some(ttl_expiry)) => Some(expires_at.min(ttl_expiry)),
+            (expires_at, ttl_expiry) => expires_at.or(ttl_expiry),
+        })
+    }
 }
 
 pub struct SayMoodHandler {
-    speech_service: Arc<Mutex<SpeechService>>,
+    spawner: SpeechTaskSpawner,
     style: AzureVoiceStyle,
 }
 
 impl SayMoodHandler {
-    pub fn new(speech_service: Arc<Mutex<SpeechService>>, style: AzureVoiceStyle) -> Box<Self> {
-        Box::new(Self {
-            speech_service,
-            style,
-        })
+    pub fn new(spawner: SpeechTaskSpawner, style: AzureVoiceStyle) -> Box<Self> {
+        Box::new(Self { spawner, style })
     }
 }
 
@@ -82,35 +435,29 @@ impl RouteHandler for SayMoodHandler {
     #[instrument(skip(self, content))]
     async fn call(
         &mut self,
-        _topic: &str,
+        topic: &str,
         content: &[u8],
     ) -> std::result::Result<(), anyhow::Error> {
         info!("mqtt say cheerful command");
-        let message = from_utf8(content)?;
+        let message = from_utf8(content)?.to_owned();
 
-        match self
-            .speech_service
-            .lock()
-            .await
-            .say_azure_with_style(message, self.style)
-            .await
-        {
-            Ok(_) => (),
-            Err(e) => {
-                error!("Failed to call speech service {:?}", e);
-            }
-        }
+        self.spawner.spawn(SpeechRequest {
+            topic: topic.to_owned(),
+            priority: Priority::Normal,
+            expires_at: None,
+            steps: vec![say_step(message, self.style)],
+        });
         Ok(())
     }
 }
 
 pub struct SayElevenDefaultHandler {
-    speech_service: ElevenSpeechService,
+    spawner: SpeechTaskSpawner,
 }
 
 impl SayElevenDefaultHandler {
-    pub fn new(speech_service: ElevenSpeechService) -> Box<Self> {
-        Box::new(Self { speech_service })
+    pub fn new(spawner: SpeechTaskSpawner) -> Box<Self> {
+        Box::new(Self { spawner })
     }
 }
 
@@ -119,33 +466,35 @@ impl RouteHandler for SayElevenDefaultHandler {
     #[instrument(skip(self, content))]
     async fn call(
         &mut self,
-        _topic: &str,
+        topic: &str,
         content: &[u8],
     ) -> std::result::Result<(), anyhow::Error> {
         info!("mqtt say eleven command");
-        let message = from_utf8(content)?;
+        let message = from_utf8(content)?.to_owned();
 
-        match self
-            .speech_service
-            .say_eleven_with_default_voice(message)
-            .await
-        {
-            Ok(_) => (),
-            Err(e) => {
-                error!("Failed to call speech service {:?}", e);
-            }
-        }
+        let step = SequenceStep::Say {
+            text: message,
+            provider: SpeechProvider::Eleven,
+            style: AzureVoiceStyle::default(),
+            voice: None,
+        };
+        self.spawner.spawn(SpeechRequest {
+            topic: topic.to_owned(),
+            priority: Priority::Normal,
+            expires_at: None,
+            steps: vec![step],
+        });
         Ok(())
     }
 }
 
 pub struct SayElevenCustomVoiceHandler {
-    speech_service: ElevenSpeechService,
+    spawner: SpeechTaskSpawner,
 }
 
 impl SayElevenCustomVoiceHandler {
-    pub fn new(speech_service: ElevenSpeechService) -> Box<Self> {
-        Box::new(Self { speech_service })
+    pub fn new(spawner: SpeechTaskSpawner) -> Box<Self> {
+        Box::new(Self { spawner })
     }
 }
 
@@ -160,29 +509,53 @@ impl RouteHandler for SayElevenCustomVoiceHandler {
         let voice_name = topic
             .split('/')
             .last()
-            .context("Failed to extract voice name")?;
+            .context("Failed to extract voice name")?
+            .to_owned();
 
         info!("mqtt say eleven custom voice command: {}", voice_name);
 
-        let message = from_utf8(content)?;
+        let message = from_utf8(content)?.to_owned();
 
-        match self.speech_service.say_eleven(message, voice_name).await {
-            Ok(_) => (),
-            Err(e) => {
-                error!("Failed to call speech service {:?}", e);
-            }
-        }
+        let step = SequenceStep::Say {
+            text: message,
+            provider: SpeechProvider::Eleven,
+            style: AzureVoiceStyle::default(),
+            voice: Some(voice_name),
+        };
+        self.spawner.spawn(SpeechRequest {
+            topic: topic.to_owned(),
+            priority: Priority::Normal,
+            expires_at: None,
+            steps: vec![step],
+        });
         Ok(())
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum PlaybackMode {
+    Queued,
+    /// Pauses queued audio until done
+    Urgent,
+    /// Ducked while other audio plays
+    Background,
+}
+
 pub struct Mp3AudioPlayerHandler {
     audio_service: AudioService,
+    mode: PlaybackMode,
 }
 
 impl Mp3AudioPlayerHandler {
     pub fn new(audio_service: AudioService) -> Box<Self> {
-        Box::new(Self { audio_service })
+        Self::with_mode(audio_service, PlaybackMode::Queued)
+    }
+
+    pub fn with_mode(audio_service: AudioService, mode: PlaybackMode) -> Box<Self> {
+        Box::new(Self {
+            audio_service,
+            mode,
+        })
     }
 }
 
@@ -194,16 +567,46 @@ impl RouteHandler for Mp3AudioPlayerHandler {
         _topic: &str,
         content: &[u8],
     ) -> std::result::Result<(), anyhow::Error> {
-        info!("mqtt mp3 audio player");
+        info!("mqtt mp3 audio player {:?}", self.mode);
 
         let audio = Box::new(Cursor::new(content.to_vec()));
-        if let Err(e) = self.audio_service.play(audio) {
+        let result = match self.mode {
+            PlaybackMode::Queued => self.audio_service.play(audio),
+            PlaybackMode::Urgent => self.audio_service.preempt(audio),
+            PlaybackMode::Background => self.audio_service.play_background(audio),
+        };
+        if let Err(e) = result {
             error!("Failed to call audio service {:?}", e);
         }
         Ok(())
     }
 }
 
+pub struct StopBackgroundRequestHandler {
+    audio_service: AudioService,
+}
+
+impl StopBackgroundRequestHandler {
+    pub fn new(audio_service: AudioService) -> Box<Self> {
+        Box::new(Self { audio_service })
+    }
+}
+
+#[async_trait]
+impl RouteHandler for StopBackgroundRequestHandler {
+    #[instrument(skip(self, _content))]
+    async fn call(
+        &mut self,
+        _topic: &str,
+        _content: &[u8],
+    ) -> std::result::Result<(), anyhow::Error> {
+        info!("Stop background request");
+
+        self.audio_service.stop_background();
+        Ok(())
+    }
+}
+
 pub struct RestartRequestHandler {
     audio_service: AudioService,
 }
@@ -259,14 +662,113 @@ impl RouteHandler for SkipOneRequestHandler {
     }
 }
 
+/// Publishes failures of requests so that the caller can find out what went wrong
+#[derive(Debug, Clone)]
+pub struct ErrorReporter {
+    client: AsyncClient,
+    topic: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorReport<'a> {
+    route: &'a str,
+    request: &'a str,
+    kind: &'a str,
+    message: String,
+}
+
+impl ErrorReporter {
+    pub fn new(client: AsyncClient, topic: String) -> Self {
+        Self { client, topic }
+    }
+
+    async fn report(&self, route: &str, request: &str, error: &anyhow::Error) {
+        let kind = error
+            .downcast_ref::<AudioRepositoryError>()
+            .map(AudioRepositoryError::kind)
+            .or_else(|| {
+                error
+                    .downcast_ref::<TemplateError>()
+                    .map(TemplateError::kind)
+            })
+            .unwrap_or("internal");
+        let report = ErrorReport {
+            route,
+            request,
+            kind,
+            message: error.to_string(),
+        };
+        let payload = match serde_json::to_vec(&report) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize error report {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = self
+            .client
+            .publish(&self.topic, QoS::AtMostOnce, false, payload)
+            .await
+        {
+            error!("Failed to publish error report {:?}", e);
+        }
+    }
+}
+
+/// Publishes what happened to accepted requests
+#[derive(Debug, Clone)]
+pub struct EventReporter {
+    client: AsyncClient,
+    topic: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RequestEvent<'a> {
+    event: &'a str,
+    topic: &'a str,
+    text: Option<&'a str>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl EventReporter {
+    pub fn new(client: AsyncClient, topic: String) -> Self {
+        Self { client, topic }
+    }
+
+    async fn report_expired(&self, request: &SpeechRequest) {
+        let event = RequestEvent {
+            event: "expired",
+            topic: &request.topic,
+            text: request.text(),
+            expires_at: request.expires_at,
+        };
+        let payload = match serde_json::to_vec(&event) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize event {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = self
+            .client
+            .publish(&self.topic, QoS::AtMostOnce, false, payload)
+            .await
+        {
+            error!("Failed to publish event {:?}", e);
+        }
+    }
+}
+
 pub struct PlayAudioFileHandler {
     audio_repository: AudioRepository,
+    error_reporter: ErrorReporter,
 }
 
 impl PlayAudioFileHandler {
-    pub fn new(audio_service: AudioRepository) -> Box<Self> {
+    pub fn new(audio_service: AudioRepository, error_reporter: ErrorReporter) -> Box<Self> {
         Box::new(Self {
             audio_repository: audio_service,
+            error_reporter,
         })
     }
 }
@@ -285,8 +787,402 @@ impl RouteHandler for PlayAudioFileHandler {
             Ok(_) => (),
             Err(e) => {
                 error!("Failed to play audio file {:?}", e);
+                self.error_reporter.report("play_file", file_path, &e).await;
             }
         }
         Ok(())
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+pub enum LibraryPlayMode {
+    ByName,
+    Tag,
+    RandomWithTag,
+    RandomFromDir,
+    /// Subdirectory is the last segment of the topic
+    RandomFromTopicDir,
+    Random,
+}
+
+impl LibraryPlayMode {
+    fn route_name(&self) -> &'static str {
+        match self {
+            LibraryPlayMode::ByName => "library/play",
+            LibraryPlayMode::Tag => "library/play_tag",
+            LibraryPlayMode::RandomWithTag => "library/play_random_tag",
+            LibraryPlayMode::RandomFromDir => "play_random_from_dir",
+            LibraryPlayMode::RandomFromTopicDir => "play_random/+",
+            LibraryPlayMode::Random => "play_random",
+        }
+    }
+}
+
+pub struct LibraryPlayHandler {
+    audio_repository: AudioRepository,
+    error_reporter: ErrorReporter,
+    mode: LibraryPlayMode,
+}
+
+impl LibraryPlayHandler {
+    pub fn new(
+        audio_repository: AudioRepository,
+        error_reporter: ErrorReporter,
+        mode: LibraryPlayMode,
+    ) -> Box<Self> {
+        Box::new(Self {
+            audio_repository,
+            error_reporter,
+            mode,
+        })
+    }
+}
+
+#[async_trait]
+impl RouteHandler for LibraryPlayHandler {
+    #[instrument(skip(self, content))]
+    async fn call(
+        &mut self,
+        topic: &str,
+        content: &[u8],
+    ) -> std::result::Result<(), anyhow::Error> {
+        let request = match self.mode {
+            LibraryPlayMode::RandomFromTopicDir => topic
+                .split('/')
+                .last()
+                .context("Failed to extract directory")?,
+            _ => std::str::from_utf8(content)?.trim(),
+        };
+        info!("Library request {:?} {:?}", self.mode, request);
+        let result = match self.mode {
+            LibraryPlayMode::ByName => self.audio_repository.play_by_name(request),
+            LibraryPlayMode::Tag => self.audio_repository.play_tag(request),
+            LibraryPlayMode::RandomWithTag => self.audio_repository.play_random_with_tag(request),
+            LibraryPlayMode::RandomFromDir | LibraryPlayMode::RandomFromTopicDir => self
+                .audio_repository
+                .random_file_from_dir(request)
+                .and_then(|played| {
+                    if played {
+                        Ok(())
+                    } else {
+                        Err(AudioRepositoryError::NotFound.into())
+                    }
+                }),
+            LibraryPlayMode::Random => self.audio_repository.random_file_recursive(),
+        };
+        if let Err(e) = result {
+            error!("Failed to play from library {:?}", e);
+            self.error_reporter
+                .report(self.mode.route_name(), request, &e)
+                .await;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum LibraryChange {
+    Upload,
+    Delete,
+}
+
+/// Sound name is the last segment of the topic
+pub struct LibraryChangeHandler {
+    audio_repository: AudioRepository,
+    error_reporter: ErrorReporter,
+    change: LibraryChange,
+}
+
+impl LibraryChangeHandler {
+    pub fn new(
+        audio_repository: AudioRepository,
+        error_reporter: ErrorReporter,
+        change: LibraryChange,
+    ) -> Box<Self> {
+        Box::new(Self {
+            audio_repository,
+            error_reporter,
+            change,
+        })
+    }
+}
+
+#[async_trait]
+impl RouteHandler for LibraryChangeHandler {
+    #[instrument(skip(self, content))]
+    async fn call(
+        &mut self,
+        topic: &str,
+        content: &[u8],
+    ) -> std::result::Result<(), anyhow::Error> {
+        let name = topic
+            .split('/')
+            .last()
+            .context("Failed to extract sound name")?
+            .to_owned();
+        info!("Library {:?} of {:?}", self.change, name);
+
+        let audio_repository = self.audio_repository.clone();
+        let error_reporter = self.error_reporter.clone();
+        let change = self.change;
+        let data = Bytes::copy_from_slice(content);
+        // decoding large uploads takes a while so keep it off of the router
+        tokio::spawn(async move {
+            let task_name = name.clone();
+            let result = tokio::task::spawn_blocking(move || match change {
+                LibraryChange::Upload => audio_repository.add_sound(&task_name, data),
+                LibraryChange::Delete => audio_repository.delete_sound(&task_name),
+            })
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result);
+            if let Err(e) = result {
+                error!("Failed to {:?} sound {:?}", change, e);
+                let route = match change {
+                    LibraryChange::Upload => "library/upload",
+                    LibraryChange::Delete => "library/delete",
+                };
+                error_reporter.report(route, &name, &e).await;
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Plays a JSON list of steps or a named sequence from the config
+///
+/// Named sequences use the last segment of the topic as the name.
+/// Sequences go through the same quiet hours and queueing rules as say requests.
+pub struct SequenceHandler {
+    spawner: SpeechTaskSpawner,
+    named_sequences: Arc<HashMap<String, Vec<SequenceStep>>>,
+    error_reporter: ErrorReporter,
+    named: bool,
+}
+
+impl SequenceHandler {
+    pub fn new(
+        spawner: SpeechTaskSpawner,
+        named_sequences: Arc<HashMap<String, Vec<SequenceStep>>>,
+        error_reporter: ErrorReporter,
+        named: bool,
+    ) -> Box<Self> {
+        Box::new(Self {
+            spawner,
+            named_sequences,
+            error_reporter,
+            named,
+        })
+    }
+}
+
+#[async_trait]
+impl RouteHandler for SequenceHandler {
+    #[instrument(skip(self, content))]
+    async fn call(
+        &mut self,
+        topic: &str,
+        content: &[u8],
+    ) -> std::result::Result<(), anyhow::Error> {
+        let (route, request, steps) = if self.named {
+            let name = topic
+                .split('/')
+                .last()
+                .context("Failed to extract sequence name")?;
+            let steps = self
+                .named_sequences
+                .get(name)
+                .cloned()
+                .with_context(|| format!("Unknown sequence {:?}", name));
+            ("sequence/+", name.to_owned(), steps)
+        } else {
+            let request = from_utf8(content)?.to_owned();
+            let steps = serde_json::from_str::<Vec<SequenceStep>>(&request).map_err(Into::into);
+            ("sequence", request, steps)
+        };
+        let steps = match steps {
+            Ok(steps) => steps,
+            Err(e) => {
+                error!("Failed to load sequence {:?}", e);
+                self.error_reporter.report(route, &request, &e).await;
+                return Ok(());
+            }
+        };
+        info!("Playing sequence with {} steps", steps.len());
+
+        self.spawner.spawn(SpeechRequest {
+            topic: topic.to_owned(),
+            priority: Priority::Normal,
+            expires_at: None,
+            steps,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum AlarmCommand {
+    Create,
+    Delete,
+    Snooze,
+    /// Republish the retained alarm list
+    List,
+}
+
+impl AlarmCommand {
+    fn route_name(&self) -> &'static str {
+        match self {
+            AlarmCommand::Create => "alarms/create",
+            AlarmCommand::Delete => "alarms/delete",
+            AlarmCommand::Snooze => "alarms/snooze",
+            AlarmCommand::List => "alarms/list",
+        }
+    }
+}
+
+pub struct AlarmHandler {
+    alarm_scheduler: AlarmScheduler,
+    error_reporter: ErrorReporter,
+    command: AlarmCommand,
+}
+
+impl AlarmHandler {
+    pub fn new(
+        alarm_scheduler: AlarmScheduler,
+        error_reporter: ErrorReporter,
+        command: AlarmCommand,
+    ) -> Box<Self> {
+        Box::new(Self {
+            alarm_scheduler,
+            error_reporter,
+            command,
+        })
+    }
+
+    fn handle(&self, request: &str) -> anyhow::Result<()> {
+        match self.command {
+            AlarmCommand::Create => {
+                let request: CreateAlarm = serde_json::from_str(request)?;
+                self.alarm_scheduler.create(request)?;
+            }
+            AlarmCommand::Delete => self.alarm_scheduler.delete(request.trim())?,
+            AlarmCommand::Snooze => {
+                let request: SnoozeAlarm = if request.trim().is_empty() {
+                    SnoozeAlarm::default()
+                } else {
+                    serde_json::from_str(request)?
+                };
+                self.alarm_scheduler.snooze(request)?;
+            }
+            AlarmCommand::List => self.alarm_scheduler.notify_subscribers(),
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RouteHandler for AlarmHandler {
+    #[instrument(skip(self, content))]
+    async fn call(
+        &mut self,
+        _topic: &str,
+        content: &[u8],
+    ) -> std::result::Result<(), anyhow::Error> {
+        info!("Alarm request {:?}", self.command);
+        let request = from_utf8(content)?;
+        if let Err(e) = self.handle(request) {
+            error!("Failed to handle alarm request {:?}", e);
+            self.error_reporter
+                .report(self.command.route_name(), request, &e)
+                .await;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the schedules from the config file again
+pub struct ReloadSchedulesHandler {
+    schedule_runner: ScheduleRunner,
+    config_path: Option<PathBuf>,
+    error_reporter: ErrorReporter,
+}
+
+impl ReloadSchedulesHandler {
+    pub fn new(
+        schedule_runner: ScheduleRunner,
+        config_path: Option<PathBuf>,
+        error_reporter: ErrorReporter,
+    ) -> Box<Self> {
+        Box::new(Self {
+            schedule_runner,
+            config_path,
+            error_reporter,
+        })
+    }
+}
+
+#[async_trait]
+impl RouteHandler for ReloadSchedulesHandler {
+    #[instrument(skip(self, _content))]
+    async fn call(
+        &mut self,
+        _topic: &str,
+        _content: &[u8],
+    ) -> std::result::Result<(), anyhow::Error> {
+        info!("Reload schedules request");
+        match get_configuration(self.config_path.clone()) {
+            Ok(config) => self.schedule_runner.reload(config.schedules),
+            Err(e) => {
+                error!("Failed to reload schedules {:?}", e);
+                self.error_reporter.report("schedules/reload", "", &e).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn say_command(json: serde_json::Value) -> SayCommand {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn ttl_sets_expiry() {
+        let now = Utc::now();
+        let command = say_command(serde_json::json!({"content": "hi", "ttl_seconds": 60}));
+        assert_eq!(
+            command.expires_at(now).unwrap(),
+            Some(now + chrono::Duration::seconds(60))
+        );
+    }
+
+    #[test]
+    fn earlier_expiry_wins() {
+        let now = Utc::now();
+        let expires_at = now + chrono::Duration::seconds(10);
+        let command = say_command(serde_json::json!({
+            "content": "hi",
+            "ttl_seconds": 60,
+            "expires_at": expires_at,
+        }));
+        assert_eq!(command.expires_at(now).unwrap(), Some(expires_at));
+    }
+
+    #[test]
+    fn huge_ttl_is_rejected() {
+        let now = Utc::now();
+        for ttl in [u64::MAX, i64::MAX as u64, 1 << 50] {
+            let command = say_command(serde_json::json!({"content": "hi", "ttl_seconds": ttl}));
+            assert!(command.expires_at(now).is_err(), "{} was accepted", ttl);
+        }
+    }
+
+    #[test]
+    fn no_expiry_by_default() {
+        let command = say_command(serde_json::json!({"content": "hi"}));
+        assert_eq!(command.expires_at(Utc::now()).unwrap(), None);
+    }
+}
@@ -0,0 +1,352 @@
+use crate::{
+    configuration::{QuietHoursConfig, QuietMode},
+    speech_service::AudioService,
+    state_file::{read_json_or_move_aside, write_json_atomically},
+};
+use chrono::{Local, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, str::FromStr, sync::Arc, time::Duration};
+use tokio::sync::watch;
+use tracing::*;
+
+/// How often the volume schedule is checked
+const VOLUME_SCHEDULE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+/// Manual do-not-disturb override of the configured quiet hours
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DndOverride {
+    /// Follow the configured window
+    #[default]
+    Auto,
+    /// Messages below the do not disturb priority go to the inbox
+    On,
+    /// Never quiet
+    Off,
+}
+
+impl DndOverride {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DndOverride::Auto => "auto",
+            DndOverride::On => "on",
+            DndOverride::Off => "off",
+        }
+    }
+}
+
+impl FromStr for DndOverride {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.trim().to_lowercase().as_str() {
+            "auto" => Ok(DndOverride::Auto),
+            "on" | "true" => Ok(DndOverride::On),
+            "off" | "false" => Ok(DndOverride::Off),
+            other => anyhow::bail!("Unknown do not disturb state {:?}", other),
+        }
+    }
+}
+
+/// Saved so that do not disturb stays on across restarts
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct DndState {
+    dnd: DndOverride,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Admission {
+    Play {
+        volume: f32,
+    },
+    Drop,
+    /// Hold until [`QuietHours::is_quiet`] turns false
+    Delay,
+    /// Keep until do not disturb ends
+    Inbox,
+}
+
+/// Decides what happens to messages based on the time of day
+#[derive(Debug, Clone)]
+pub struct QuietHours {
+    config: Arc<QuietHoursConfig>,
+    dnd: Arc<watch::Sender<DndOverride>>,
+    dnd_bypass_priority: Priority,
+    dnd_state_path: Option<Arc<PathBuf>>,
+}
+
+impl QuietHours {
+    pub fn new(config: QuietHoursConfig, dnd_bypass_priority: Priority) -> Self {
+        let (dnd, _) = watch::channel(DndOverride::Auto);
+        Self {
+            config: Arc::new(config),
+            dnd: Arc::new(dnd),
+            dnd_bypass_priority,
+            dnd_state_path: None,
+        }
+    }
+
+    /// Restore the do not disturb override from `path` and save every change to it
+    pub fn with_dnd_state(mut self, path: PathBuf) -> Self {
+        let state: DndState = read_json_or_move_aside(&path).unwrap_or_default();
+        info!("Restored do not disturb {:?}", state.dnd);
+        self.dnd.send_replace(state.dnd);
+        self.dnd_state_path = Some(Arc::new(path));
+        self
+    }
+
+    pub fn dnd(&self) -> DndOverride {
+        *self.dnd.borrow()
+    }
+
+    pub fn set_dnd(&self, dnd: DndOverride) {
+        info!("Do not disturb set to {:?}", dnd);
+        self.dnd.send_replace(dnd);
+        if let Some(path) = &self.dnd_state_path {
+            if let Err(e) = write_json_atomically(path, &DndState { dnd }) {
+                error!("Failed to save do not disturb state {:?}", e);
+            }
+        }
+    }
+
+    /// Notified whenever the do not disturb override changes
+    pub fn subscribe_dnd(&self) -> watch::Receiver<DndOverride> {
+        self.dnd.subscribe()
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.is_quiet_at(Local::now().time())
+    }
+
+    pub fn is_quiet_at(&self, time: NaiveTime) -> bool {
+        match self.dnd() {
+            DndOverride::On => true,
+            DndOverride::Off => false,
+            DndOverride::Auto => match &self.config.window {
+                Some(window) if window.start <= window.end => {
+                    window.start <= time && time < window.end
+                }
+                Some(window) => time >= window.start || time < window.end,
+                None => false,
+            },
+        }
+    }
+
+    pub fn admit(&self, priority: Priority) -> Admission {
+        self.admit_at(priority, Local::now().time())
+    }
+
+    pub fn admit_at(&self, priority: Priority, time: NaiveTime) -> Admission {
+        if self.dnd() == DndOverride::On {
+            return if priority >= self.dnd_bypass_priority {
+                Admission::Play { volume: 1.0 }
+            } else {
+                Admission::Inbox
+            };
+        }
+        if priority >= self.config.bypass_priority || !self.is_quiet_at(time) {
+            return Admission::Play { volume: 1.0 };
+        }
+        match self.config.mode {
+            QuietMode::Drop => Admission::Drop,
+            QuietMode::Delay => Admission::Delay,
+            QuietMode::MinVolume => Admission::Play {
+                volume: self.config.min_volume,
+            },
+        }
+    }
+
+    /// Volume of the latest schedule entry that started before this time
+    pub fn volume_at(&self, time: NaiveTime) -> Option<f32> {
+        let schedule = &self.config.volume_schedule;
+        schedule
+            .iter()
+            .filter(|entry| entry.from <= time)
+            .max_by_key(|entry| entry.from)
+            // before the first entry of the day the previous day's last entry still applies
+            .or_else(|| schedule.iter().max_by_key(|entry| entry.from))
+            .map(|entry| entry.volume)
+    }
+
+    /// Keep the master volume in line with the volume schedule
+    ///
+    /// Only changes are sent since the player keeps its volume across restarts.
+    pub fn start_volume_schedule(&self, audio_service: AudioService) {
+        if self.config.volume_schedule.is_empty() {
+            return;
+        }
+        let quiet_hours = self.clone();
+        tokio::spawn(async move {
+            let mut current_volume = None;
+            let mut interval = tokio::time::interval(VOLUME_SCHEDULE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Some(volume) = quiet_hours.volume_at(Local::now().time()) {
+                    if current_volume != Some(volume) {
+                        info!("Scheduled volume change to {}", volume);
+                        audio_service.volume(volume);
+                        current_volume = Some(volume);
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::{QuietWindowConfig, VolumeScheduleEntry};
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn quiet_hours(start: NaiveTime, end: NaiveTime, mode: QuietMode) -> QuietHours {
+        let config = QuietHoursConfig {
+            window: Some(QuietWindowConfig { start, end }),
+            mode,
+            min_volume: 0.2,
+            bypass_priority: Priority::High,
+            ..Default::default()
+        };
+        QuietHours::new(config, Priority::Urgent)
+    }
+
+    fn volume_schedule(entries: &[(NaiveTime, f32)]) -> QuietHours {
+        let config = QuietHoursConfig {
+            volume_schedule: entries
+                .iter()
+                .map(|(from, volume)| VolumeScheduleEntry {
+                    from: *from,
+                    volume: *volume,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        QuietHours::new(config, Priority::Urgent)
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let quiet_hours = quiet_hours(time(13, 0), time(15, 0), QuietMode::Drop);
+        assert!(!quiet_hours.is_quiet_at(time(12, 59)));
+        assert!(quiet_hours.is_quiet_at(time(13, 0)));
+        assert!(quiet_hours.is_quiet_at(time(14, 30)));
+        assert!(!quiet_hours.is_quiet_at(time(15, 0)));
+    }
+
+    #[test]
+    fn window_wraps_past_midnight() {
+        let quiet_hours = quiet_hours(time(22, 0), time(7, 0), QuietMode::Drop);
+        assert!(!quiet_hours.is_quiet_at(time(21, 59)));
+        assert!(quiet_hours.is_quiet_at(time(22, 0)));
+        assert!(quiet_hours.is_quiet_at(time(0, 0)));
+        assert!(quiet_hours.is_quiet_at(time(6, 59)));
+        assert!(!quiet_hours.is_quiet_at(time(7, 0)));
+        assert!(!quiet_hours.is_quiet_at(time(12, 0)));
+    }
+
+    #[test]
+    fn no_window_is_never_quiet() {
+        let quiet_hours = QuietHours::new(QuietHoursConfig::default(), Priority::Urgent);
+        assert!(!quiet_hours.is_quiet_at(time(3, 0)));
+    }
+
+    #[test]
+    fn dnd_overrides_window() {
+        let quiet_hours = quiet_hours(time(22, 0), time(7, 0), QuietMode::Drop);
+        quiet_hours.set_dnd(DndOverride::On);
+        assert!(quiet_hours.is_quiet_at(time(12, 0)));
+        quiet_hours.set_dnd(DndOverride::Off);
+        assert!(!quiet_hours.is_quiet_at(time(23, 0)));
+    }
+
+    #[test]
+    fn dnd_survives_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dnd.json");
+        let config = QuietHoursConfig::default();
+        let quiet_hours =
+            QuietHours::new(config.clone(), Priority::Urgent).with_dnd_state(path.clone());
+        assert_eq!(quiet_hours.dnd(), DndOverride::Auto);
+        quiet_hours.set_dnd(DndOverride::On);
+
+        let restarted = QuietHours::new(config, Priority::Urgent).with_dnd_state(path);
+        assert_eq!(restarted.dnd(), DndOverride::On);
+    }
+
+    #[test]
+    fn admit_follows_quiet_mode() {
+        let night = time(23, 0);
+        let drop = quiet_hours(time(22, 0), time(7, 0), QuietMode::Drop);
+        assert_eq!(drop.admit_at(Priority::Normal, night), Admission::Drop);
+        let delay = quiet_hours(time(22, 0), time(7, 0), QuietMode::Delay);
+        assert_eq!(delay.admit_at(Priority::Normal, night), Admission::Delay);
+        let min_volume = quiet_hours(time(22, 0), time(7, 0), QuietMode::MinVolume);
+        assert_eq!(
+            min_volume.admit_at(Priority::Normal, night),
+            Admission::Play { volume: 0.2 }
+        );
+        assert_eq!(
+            drop.admit_at(Priority::Normal, time(12, 0)),
+            Admission::Play { volume: 1.0 }
+        );
+    }
+
+    #[test]
+    fn admit_lets_high_priority_through() {
+        let quiet_hours = quiet_hours(time(22, 0), time(7, 0), QuietMode::Drop);
+        assert_eq!(
+            quiet_hours.admit_at(Priority::High, time(23, 0)),
+            Admission::Play { volume: 1.0 }
+        );
+    }
+
+    #[test]
+    fn admit_during_dnd_uses_inbox() {
+        let quiet_hours = quiet_hours(time(22, 0), time(7, 0), QuietMode::Drop);
+        quiet_hours.set_dnd(DndOverride::On);
+        // the quiet hours bypass doesn't apply to do not disturb
+        assert_eq!(
+            quiet_hours.admit_at(Priority::High, time(12, 0)),
+            Admission::Inbox
+        );
+        assert_eq!(
+            quiet_hours.admit_at(Priority::Urgent, time(12, 0)),
+            Admission::Play { volume: 1.0 }
+        );
+    }
+
+    #[test]
+    fn volume_follows_schedule() {
+        let quiet_hours =
+            volume_schedule(&[(time(22, 0), 0.3), (time(7, 0), 1.0), (time(19, 0), 0.7)]);
+        assert_eq!(quiet_hours.volume_at(time(7, 0)), Some(1.0));
+        assert_eq!(quiet_hours.volume_at(time(18, 59)), Some(1.0));
+        assert_eq!(quiet_hours.volume_at(time(19, 0)), Some(0.7));
+        assert_eq!(quiet_hours.volume_at(time(23, 0)), Some(0.3));
+    }
+
+    #[test]
+    fn volume_carries_over_midnight() {
+        let quiet_hours = volume_schedule(&[(time(7, 0), 1.0), (time(22, 0), 0.3)]);
+        assert_eq!(quiet_hours.volume_at(time(0, 30)), Some(0.3));
+        assert_eq!(quiet_hours.volume_at(time(6, 59)), Some(0.3));
+    }
+
+    #[test]
+    fn empty_schedule_has_no_volume() {
+        assert_eq!(volume_schedule(&[]).volume_at(time(12, 0)), None);
+    }
+}
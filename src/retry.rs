@@ -0,0 +1,345 @@
+use crate::{
+    configuration::RetryConfig, eleven_labs_client::HttpStatusError, error::HomeSpeakError,
+};
+use rand::Rng;
+use reqwest::StatusCode;
+use std::{error::Error, fmt::Display, future::Future, time::Duration};
+use tokio::time::Instant;
+use tracing::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Retry after the server provided delay or after the default backoff
+    Retry {
+        after: Option<Duration>,
+    },
+    Fail,
+}
+
+impl RetryDecision {
+    const RETRY: RetryDecision = RetryDecision::Retry { after: None };
+}
+
+fn decision_for_status(status: StatusCode, retry_after: Option<Duration>) -> RetryDecision {
+    if status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status.is_server_error()
+    {
+        RetryDecision::Retry { after: retry_after }
+    } else {
+        RetryDecision::Fail
+    }
+}
+
+/// Status of the first rejected HTTP request in the error chain
+pub(crate) fn http_status(error: &(dyn Error + 'static)) -> Option<StatusCode> {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(error) = error.downcast_ref::<HttpStatusError>() {
+            return Some(error.status);
+        }
+        if let Some(status) = error
+            .downcast_ref::<reqwest::Error>()
+            .and_then(reqwest::Error::status)
+        {
+            return Some(status);
+        }
+        current = error.source();
+    }
+    None
+}
+
+/// Walks the error chain looking for something that tells us whether the failure was transient
+///
+/// Errors we don't recognize are not retried.
+pub fn retry_decision(error: &(dyn Error + 'static)) -> RetryDecision {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(error) = error.downcast_ref::<HttpStatusError>() {
+            return decision_for_status(error.status, error.retry_after);
+        }
+        if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+            if let Some(status) = error.status() {
+                return decision_for_status(status, None);
+            }
+            if error.is_timeout() || error.is_connect() || error.is_request() || error.is_body() {
+                return RetryDecision::RETRY;
+            }
+            return RetryDecision::Fail;
+        }
+        if error.is::<tokio::time::error::Elapsed>() {
+            return RetryDecision::RETRY;
+        }
+        match error.downcast_ref::<HomeSpeakError>() {
+            Some(HomeSpeakError::TtsRequestTimeout) => return RetryDecision::RETRY,
+            Some(HomeSpeakError::AzureTtsError {
+                status: Some(status),
+                ..
+            }) => return decision_for_status(*status, None),
+            _ => (),
+        }
+        current = error.source();
+    }
+    RetryDecision::Fail
+}
+
+/// Exponential backoff with "equal jitter"
+fn backoff_delay(config: &RetryConfig, retries: u32) -> Duration {
+    let multiplier = 1_u64.checked_shl(retries).unwrap_or(u64::MAX);
+    let backoff_ms = config
+        .initial_backoff_ms
+        .saturating_mul(multiplier)
+        .min(config.max_backoff_ms);
+    let jittered_ms = rand::thread_rng().gen_range(backoff_ms / 2..=backoff_ms);
+    Duration::from_millis(jittered_ms)
+}
+
+/// Run operation until it succeeds, fails with a non-retryable error,
+/// runs out of retries or would exceed the configured deadline
+///
+/// The operation is given the time left until the deadline so that a single
+/// slow attempt can't run past it.
+pub async fn with_retry<T, E, F, Fut, C>(
+    operation_name: &str,
+    config: &RetryConfig,
+    classify: C,
+    mut operation: F,
+) -> std::result::Result<T, E>
+where
+    F: FnMut(Duration) -> Fut,
+    Fut: Future<Output = std::result::Result<T, E>>,
+    C: Fn(&E) -> RetryDecision,
+    E: Display,
+{
+    let span = info_span!("retry", operation = operation_name, retries = 0_u32);
+    async move {
+        let deadline = Instant::now() + config.deadline();
+        let mut retries = 0;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let error = match operation(remaining).await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let requested_delay = match classify(&error) {
+                RetryDecision::Retry { after } => after,
+                RetryDecision::Fail => {
+                    warn!(
+                        "{} failed with non-retryable error {}",
+                        operation_name, error
+                    );
+                    return Err(error);
+                }
+            };
+            if retries >= config.max_retries {
+                warn!(
+                    "{} failed after {} retries with {}",
+                    operation_name, retries, error
+                );
+                return Err(error);
+            }
+            let delay = requested_delay.unwrap_or_else(|| backoff_delay(config, retries));
+            if Instant::now() + delay > deadline {
+                warn!(
+                    "{} failed with {} and retrying would exceed the deadline",
+                    operation_name, error
+                );
+                return Err(error);
+            }
+            retries += 1;
+            Span::current().record("retries", retries);
+            warn!(
+                "{} failed with {}. Retry {} in {:?}",
+                operation_name, error, retries, delay
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_server::{MockResponse, MockServer};
+
+    fn config(initial_backoff_ms: u64, max_backoff_ms: u64) -> RetryConfig {
+        RetryConfig {
+            max_retries: 3,
+            initial_backoff_ms,
+            max_backoff_ms,
+            deadline_ms: 1_000,
+        }
+    }
+
+    fn status_error(status: StatusCode, retry_after: Option<Duration>) -> HttpStatusError {
+        HttpStatusError {
+            status,
+            retry_after,
+        }
+    }
+
+    #[test]
+    fn backoff_grows_within_jitter_bounds() {
+        let config = config(100, 10_000);
+        for retries in 0..5 {
+            let backoff = 100 << retries;
+            let delay = backoff_delay(&config, retries).as_millis() as u64;
+            assert!(
+                (backoff / 2..=backoff).contains(&delay),
+                "{} not within bounds of {}",
+                delay,
+                backoff
+            );
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let config = config(100, 1_000);
+        for retries in [10, 63, 64, 1_000, u32::MAX] {
+            let delay = backoff_delay(&config, retries);
+            assert!(delay >= Duration::from_millis(500));
+            assert!(delay <= Duration::from_millis(1_000));
+        }
+    }
+
+    #[test]
+    fn backoff_overflow_saturates() {
+        let config = config(u64::MAX, u64::MAX);
+        let delay = backoff_delay(&config, 5);
+        assert!(delay >= Duration::from_millis(u64::MAX / 2));
+    }
+
+    #[test]
+    fn too_many_requests_uses_retry_after() {
+        let error = status_error(StatusCode::TOO_MANY_REQUESTS, Some(Duration::from_secs(7)));
+        assert_eq!(
+            retry_decision(&error),
+            RetryDecision::Retry {
+                after: Some(Duration::from_secs(7))
+            }
+        );
+    }
+
+    #[test]
+    fn client_errors_fail() {
+        for status in [
+            StatusCode::BAD_REQUEST,
+            StatusCode::UNAUTHORIZED,
+            StatusCode::NOT_FOUND,
+        ] {
+            let error = status_error(status, None);
+            assert_eq!(retry_decision(&error), RetryDecision::Fail);
+        }
+    }
+
+    #[test]
+    fn server_errors_retry() {
+        for status in [
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::REQUEST_TIMEOUT,
+        ] {
+            let error = status_error(status, None);
+            assert_eq!(retry_decision(&error), RetryDecision::RETRY);
+        }
+    }
+
+    #[test]
+    fn wrapped_errors_are_classified_by_their_cause() {
+        let error = anyhow::Error::new(status_error(StatusCode::BAD_GATEWAY, None))
+            .context("Request failed with status");
+        assert_eq!(retry_decision(error.as_ref()), RetryDecision::RETRY);
+
+        let error = HomeSpeakError::google_tts(status_error(StatusCode::FORBIDDEN, None));
+        assert_eq!(retry_decision(&error), RetryDecision::Fail);
+    }
+
+    #[test]
+    fn timeouts_retry_and_unknown_errors_fail() {
+        assert_eq!(
+            retry_decision(&HomeSpeakError::TtsRequestTimeout),
+            RetryDecision::RETRY
+        );
+        assert_eq!(
+            retry_decision(&HomeSpeakError::google_tts("invalid voice")),
+            RetryDecision::Fail
+        );
+    }
+
+    #[test]
+    fn azure_errors_are_classified_by_status() {
+        for (status, decision) in [
+            (StatusCode::TOO_MANY_REQUESTS, RetryDecision::RETRY),
+            (StatusCode::SERVICE_UNAVAILABLE, RetryDecision::RETRY),
+            (StatusCode::BAD_REQUEST, RetryDecision::Fail),
+        ] {
+            let error = HomeSpeakError::AzureTtsError {
+                status: Some(status),
+                source: "rejected".into(),
+            };
+            assert_eq!(retry_decision(&error), decision, "{}", status);
+        }
+        let error = HomeSpeakError::azure_tts("failed to parse voice list");
+        assert_eq!(retry_decision(&error), RetryDecision::Fail);
+    }
+
+    #[tokio::test]
+    async fn azure_status_is_taken_from_nested_http_errors() {
+        let server = MockServer::start(vec![
+            MockResponse::new(429, b""),
+            MockResponse::new(503, b""),
+            MockResponse::new(400, b""),
+        ]);
+        for (status, decision) in [
+            (StatusCode::TOO_MANY_REQUESTS, RetryDecision::RETRY),
+            (StatusCode::SERVICE_UNAVAILABLE, RetryDecision::RETRY),
+            (StatusCode::BAD_REQUEST, RetryDecision::Fail),
+        ] {
+            let http_error = reqwest::get(server.url())
+                .await
+                .unwrap()
+                .error_for_status()
+                .unwrap_err();
+            // the way a client library wraps the response error
+            let nested = anyhow::Error::new(http_error).context("synthesis failed");
+            let error = HomeSpeakError::azure_tts(nested);
+            let HomeSpeakError::AzureTtsError { status: found, .. } = &error else {
+                panic!("unexpected error {:?}", error);
+            };
+            assert_eq!(*found, Some(status));
+            assert_eq!(retry_decision(&error), decision);
+        }
+    }
+
+    #[tokio::test]
+    async fn attempts_are_bounded_by_the_deadline() {
+        let config = RetryConfig {
+            max_retries: 10,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 1,
+            deadline_ms: 200,
+        };
+        let mut budgets = vec![];
+        let result: std::result::Result<(), HomeSpeakError> = with_retry(
+            "test",
+            &config,
+            |error: &HomeSpeakError| retry_decision(error),
+            |remaining| {
+                budgets.push(remaining);
+                async move {
+                    tokio::time::sleep(remaining.min(Duration::from_millis(60))).await;
+                    Err(HomeSpeakError::TtsRequestTimeout)
+                }
+            },
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(budgets.len() > 1);
+        assert!(budgets.iter().all(|budget| *budget <= config.deadline()));
+        assert!(budgets.windows(2).all(|pair| pair[1] < pair[0]));
+    }
+}
@@ -0,0 +1,326 @@
+use crate::{
+    alarms::{parse_cron, sleep_duration},
+    configuration::ScheduleConfig,
+    inbox::SpeechRequest,
+    speech_service::SequenceStep,
+    state_file::{read_json_or_move_aside, write_json_atomically},
+    template_messages::TemplateEngine,
+};
+use chrono::{DateTime, Local, Utc};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::{mpsc::UnboundedSender, Notify};
+use tracing::*;
+
+/// Source of the current time so that schedules can be driven by a fake clock
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> DateTime<Local>;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+#[derive(Debug)]
+struct Job {
+    name: String,
+    config: ScheduleConfig,
+    schedule: cron::Schedule,
+    next_fire: Option<DateTime<Local>>,
+}
+
+#[derive(Debug, Default)]
+struct RunnerState {
+    jobs: Vec<Job>,
+    last_fired: HashMap<String, DateTime<Utc>>,
+}
+
+impl RunnerState {
+    /// Invalid schedules are logged and skipped
+    fn set_jobs(&mut self, schedules: HashMap<String, ScheduleConfig>, now: DateTime<Local>) {
+        self.jobs = schedules
+            .into_iter()
+            .filter_map(|(name, config)| match parse_cron(&config.cron) {
+                Ok(schedule) => {
+                    // never fire the same occurrence twice
+                    let after = self
+                        .last_fired
+                        .get(&name)
+                        .map(|last_fired| last_fired.with_timezone(&Local).max(now))
+                        .unwrap_or(now);
+                    let next_fire = schedule.after(&after).next();
+                    Some(Job {
+                        name,
+                        config,
+                        schedule,
+                        next_fire,
+                    })
+                }
+                Err(e) => {
+                    error!("Skipping schedule {:?} {:?}", name, e);
+                    None
+                }
+            })
+            .collect();
+    }
+}
+
+/// Fires announcements from the `schedules` config
+#[derive(Debug, Clone)]
+pub struct ScheduleRunner {
+    state: Arc<Mutex<RunnerState>>,
+    state_path: Arc<PathBuf>,
+    template_engine: TemplateEngine,
+    clock: Arc<dyn Clock>,
+    reloaded: Arc<Notify>,
+}
+
+impl ScheduleRunner {
+    pub fn new(
+        schedules: HashMap<String, ScheduleConfig>,
+        state_path: PathBuf,
+        template_engine: TemplateEngine,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let last_fired = read_json_or_move_aside(&state_path).unwrap_or_default();
+        let mut state = RunnerState {
+            jobs: vec![],
+            last_fired,
+        };
+        state.set_jobs(schedules, clock.now());
+        info!("Loaded {} schedules", state.jobs.len());
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            state_path: Arc::new(state_path),
+            template_engine,
+            clock,
+            reloaded: Arc::default(),
+        }
+    }
+
+    /// Replace all schedules while keeping track of when they last fired
+    pub fn reload(&self, schedules: HashMap<String, ScheduleConfig>) {
+        let mut state = self.state.lock().unwrap();
+        state.set_jobs(schedules, self.clock.now());
+        info!("Reloaded {} schedules", state.jobs.len());
+        self.reloaded.notify_one();
+    }
+
+    /// Requests for every schedule that is due
+    ///
+    /// Fired schedules are recorded before they are returned.
+    /// Schedules whose text fails to render are recorded but skipped.
+    pub fn take_due(&self) -> Vec<SpeechRequest> {
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap();
+        let mut due = vec![];
+        let mut fired = vec![];
+        for job in state.jobs.iter_mut() {
+            let Some(next_fire) = job.next_fire else {
+                continue;
+            };
+            if next_fire > now {
+                continue;
+            }
+            info!("Firing schedule {:?}", job.name);
+            match self
+                .template_engine
+                .render(&job.config.text, &serde_json::Value::Null)
+            {
+                Ok(text) => due.push(SpeechRequest {
+                    topic: format!("schedules/{}", job.name),
+                    priority: job.config.priority,
+                    expires_at: None,
+                    steps: vec![SequenceStep::Say {
+                        text,
+                        provider: job.config.provider,
+                        style: job.config.style,
+                        voice: job.config.voice.clone(),
+                    }],
+                }),
+                Err(e) => error!("Failed to render schedule {:?} {:?}", job.name, e),
+            }
+            fired.push((job.name.clone(), next_fire.with_timezone(&Utc)));
+            job.next_fire = job.schedule.after(&now.max(next_fire)).next();
+        }
+        if !fired.is_empty() {
+            state.last_fired.extend(fired);
+            if let Err(e) = write_json_atomically(&self.state_path, &state.last_fired) {
+                error!("Failed to save schedule state {:?}", e);
+            }
+        }
+        due
+    }
+
+    fn until_next_fire(&self) -> Duration {
+        let next_fire = self
+            .state
+            .lock()
+            .unwrap()
+            .jobs
+            .iter()
+            .filter_map(|job| job.next_fire)
+            .min();
+        sleep_duration(next_fire, self.clock.now())
+    }
+
+    /// Sends due requests until the channel closes
+    ///
+    /// Waiting between occurrences uses tokio's clock so a fake [`Clock`] only
+    /// decides what is due. Tests drive [`ScheduleRunner::take_due`] directly.
+    pub fn start(&self, requests: UnboundedSender<SpeechRequest>) {
+        let runner = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(runner.until_next_fire()) => (),
+                    _ = runner.reloaded.notified() => continue,
+                }
+                for request in runner.take_due() {
+                    if requests.send(request).is_err() {
+                        error!("Schedule request channel closed");
+                        return;
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::AssistantConfig;
+    use chrono::TimeZone;
+
+    #[derive(Debug)]
+    struct FakeClock(Mutex<DateTime<Local>>);
+
+    impl FakeClock {
+        fn at(hour: u32, minute: u32, second: u32) -> Arc<Self> {
+            Arc::new(Self(Mutex::new(local_time(hour, minute, second))))
+        }
+
+        fn set(&self, hour: u32, minute: u32, second: u32) {
+            *self.0.lock().unwrap() = local_time(hour, minute, second);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> DateTime<Local> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn local_time(hour: u32, minute: u32, second: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 1, 8, hour, minute, second)
+            .unwrap()
+    }
+
+    fn schedules(entries: &[(&str, &str)]) -> HashMap<String, ScheduleConfig> {
+        entries
+            .iter()
+            .map(|(name, cron)| {
+                let config =
+                    serde_json::from_value(serde_json::json!({"cron": cron, "text": "hi"}))
+                        .unwrap();
+                (name.to_string(), config)
+            })
+            .collect()
+    }
+
+    fn runner(
+        dir: &tempfile::TempDir,
+        schedules: HashMap<String, ScheduleConfig>,
+        clock: Arc<FakeClock>,
+    ) -> ScheduleRunner {
+        ScheduleRunner::new(
+            schedules,
+            dir.path().join("schedules.json"),
+            TemplateEngine::new(AssistantConfig::default(), None),
+            clock,
+        )
+    }
+
+    fn topics(requests: Vec<SpeechRequest>) -> Vec<String> {
+        requests.into_iter().map(|request| request.topic).collect()
+    }
+
+    #[test]
+    fn fires_once_per_occurrence() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = FakeClock::at(6, 59, 30);
+        let runner = runner(&dir, schedules(&[("hourly", "0 * * * *")]), clock.clone());
+        assert!(runner.take_due().is_empty());
+
+        clock.set(7, 0, 0);
+        assert_eq!(topics(runner.take_due()), ["schedules/hourly"]);
+        assert!(runner.take_due().is_empty());
+        clock.set(7, 30, 0);
+        assert!(runner.take_due().is_empty());
+
+        // missed occurrences only fire once
+        clock.set(9, 30, 0);
+        assert_eq!(runner.take_due().len(), 1);
+        assert!(runner.take_due().is_empty());
+    }
+
+    #[test]
+    fn reload_does_not_fire_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = FakeClock::at(7, 0, 0);
+        let runner = runner(&dir, schedules(&[]), clock.clone());
+        runner.reload(schedules(&[("hourly", "0 * * * *")]));
+        assert!(runner.take_due().is_empty());
+
+        clock.set(8, 0, 0);
+        assert_eq!(runner.take_due().len(), 1);
+        runner.reload(schedules(&[("hourly", "0 * * * *")]));
+        assert!(runner.take_due().is_empty());
+
+        clock.set(9, 0, 0);
+        assert_eq!(runner.take_due().len(), 1);
+    }
+
+    #[test]
+    fn restart_does_not_fire_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = FakeClock::at(6, 59, 30);
+        let first = runner(&dir, schedules(&[("hourly", "0 * * * *")]), clock.clone());
+        clock.set(7, 0, 0);
+        assert_eq!(first.take_due().len(), 1);
+
+        // a restart within the same second must not repeat the occurrence
+        let restarted = runner(&dir, schedules(&[("hourly", "0 * * * *")]), clock.clone());
+        assert!(restarted.take_due().is_empty());
+        clock.set(8, 0, 0);
+        assert_eq!(restarted.take_due().len(), 1);
+    }
+
+    #[test]
+    fn invalid_cron_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let clock = FakeClock::at(6, 59, 30);
+        let runner = runner(
+            &dir,
+            schedules(&[("broken", "every morning"), ("hourly", "0 * * * *")]),
+            clock.clone(),
+        );
+        clock.set(7, 0, 0);
+        assert_eq!(topics(runner.take_due()), ["schedules/hourly"]);
+
+        runner.reload(schedules(&[("broken", "61 * * * *")]));
+        clock.set(8, 0, 0);
+        assert!(runner.take_due().is_empty());
+    }
+}
@@ -1,17 +1,212 @@
+use crate::configuration::LoudnessConfig;
 use crate::error::{HomeSpeakError, Result};
+use crate::loudness::Loudness;
 use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::{dynamic_mixer::DynamicMixerController, queue::SourcesQueueInput, Source};
+use std::collections::VecDeque;
 use std::io::Seek;
-use std::sync::mpsc::Receiver;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{
     fs::File,
     io::{Cursor, Read},
     sync::mpsc::{channel, Sender},
     thread,
 };
+use tokio::sync::watch;
 use tracing::*;
 
+const MIXER_CHANNELS: u16 = 2;
+const MIXER_SAMPLE_RATE: u32 = 44100;
+/// Used when sounds start, get skipped, stopped or preempted
+const SOUND_FADE: Duration = Duration::from_millis(60);
+const DUCK_FADE: Duration = Duration::from_millis(300);
+/// Background volume while something else is playing
+const DUCKED_GAIN: f32 = 0.25;
+/// How often the player checks whether sounds finished to update ducking
+const STATE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Streams play silence until this much audio is decoded
+const STREAM_PREFILL: Duration = Duration::from_millis(500);
+const STREAM_UNDERRUN_SILENCE: Duration = Duration::from_millis(10);
+const DECODE_CHUNK_SAMPLES: usize = 4096;
+/// Bounds how far the decoding thread runs ahead of playback
+const DECODE_AHEAD_CHUNKS: usize = 64;
+
 pub trait Playable: std::io::Read + std::io::Seek + Send + Sync {
     fn as_bytes(&mut self) -> Result<Vec<u8>>;
+
+    /// Used to normalize playback volume if known
+    fn loudness(&self) -> Option<Loudness> {
+        None
+    }
+
+    /// Relative to the master volume
+    fn volume(&self) -> f32 {
+        1.0
+    }
+
+    /// Discarded instead of played once this passes
+    fn deadline(&self) -> Option<&Deadline> {
+        None
+    }
+
+    /// Still being received so reads may block
+    fn is_stream(&self) -> bool {
+        false
+    }
+}
+
+impl Playable for Box<dyn Playable> {
+    fn as_bytes(&mut self) -> Result<Vec<u8>> {
+        (**self).as_bytes()
+    }
+
+    fn loudness(&self) -> Option<Loudness> {
+        (**self).loudness()
+    }
+
+    fn volume(&self) -> f32 {
+        (**self).volume()
+    }
+
+    fn deadline(&self) -> Option<&Deadline> {
+        (**self).deadline()
+    }
+
+    fn is_stream(&self) -> bool {
+        (**self).is_stream()
+    }
+}
+
+/// Audio together with its measured loudness
+pub struct MeasuredPlayable<P> {
+    inner: P,
+    loudness: Loudness,
+}
+
+impl<P: Playable + 'static> MeasuredPlayable<P> {
+    pub fn boxed(inner: P, loudness: Option<Loudness>) -> Box<dyn Playable> {
+        match loudness {
+            Some(loudness) => Box::new(Self { inner, loudness }),
+            None => Box::new(inner),
+        }
+    }
+}
+
+impl<P: Read> Read for MeasuredPlayable<P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<P: Seek> Seek for MeasuredPlayable<P> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<P: Playable> Playable for MeasuredPlayable<P> {
+    fn as_bytes(&mut self) -> Result<Vec<u8>> {
+        self.inner.as_bytes()
+    }
+
+    fn loudness(&self) -> Option<Loudness> {
+        Some(self.loudness)
+    }
+
+    fn volume(&self) -> f32 {
+        self.inner.volume()
+    }
+
+    fn deadline(&self) -> Option<&Deadline> {
+        self.inner.deadline()
+    }
+
+    fn is_stream(&self) -> bool {
+        self.inner.is_stream()
+    }
+}
+
+/// Latest time at which sounds may start playing
+///
+/// Shared by all sounds of a slot. Receivers from [`Deadline::new`] are notified
+/// if any of the sounds gets discarded.
+#[derive(Debug, Clone)]
+pub struct Deadline {
+    at: Instant,
+    expired: Arc<watch::Sender<bool>>,
+}
+
+impl Deadline {
+    pub fn new(at: Instant) -> (Self, watch::Receiver<bool>) {
+        let (expired, receiver) = watch::channel(false);
+        let deadline = Self {
+            at,
+            expired: Arc::new(expired),
+        };
+        (deadline, receiver)
+    }
+
+    pub fn is_past(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    fn expire(&self) {
+        self.expired.send_replace(true);
+    }
+}
+
+/// Audio with the playback settings of its slot
+pub struct SlotPlayable<P> {
+    inner: P,
+    volume: f32,
+    deadline: Option<Deadline>,
+}
+
+impl<P: Playable + 'static> SlotPlayable<P> {
+    pub fn boxed(inner: P, volume: f32, deadline: Option<Deadline>) -> Box<dyn Playable> {
+        Box::new(Self {
+            inner,
+            volume,
+            deadline,
+        })
+    }
+}
+
+impl<P: Read> Read for SlotPlayable<P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<P: Seek> Seek for SlotPlayable<P> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<P: Playable> Playable for SlotPlayable<P> {
+    fn as_bytes(&mut self) -> Result<Vec<u8>> {
+        self.inner.as_bytes()
+    }
+
+    fn loudness(&self) -> Option<Loudness> {
+        self.inner.loudness()
+    }
+
+    fn volume(&self) -> f32 {
+        self.inner.volume() * self.volume
+    }
+
+    fn deadline(&self) -> Option<&Deadline> {
+        self.deadline.as_ref().or_else(|| self.inner.deadline())
+    }
+
+    fn is_stream(&self) -> bool {
+        self.inner.is_stream()
+    }
 }
 
 impl Playable for Cursor<Vec<u8>> {
@@ -30,6 +225,11 @@ impl Playable for File {
 
 pub enum AudioPlayerCommand {
     Play(Box<dyn Playable>),
+    /// Pause whatever is playing and play this first
+    Preempt(Box<dyn Playable>),
+    /// Ducked while anything else plays
+    PlayBackground(Box<dyn Playable>),
+    StopBackground,
     Pause,
     Resume,
     Stop,
@@ -69,58 +269,521 @@ fn select_output_device() -> anyhow::Result<(rodio::OutputStream, rodio::OutputS
     anyhow::bail!("No audio output device found");
 }
 
-fn audio_player_loop(receiver: &Receiver<AudioPlayerCommand>) -> anyhow::Result<bool> {
+/// Shared with the audio thread so that gain changes don't need to go through the mixer
+#[derive(Debug)]
+struct FadeControl {
+    /// f32 bits
+    gain: AtomicU32,
+    paused: AtomicBool,
+    stopped: AtomicBool,
+}
+
+impl FadeControl {
+    fn new(gain: f32) -> Arc<Self> {
+        Arc::new(Self {
+            gain: AtomicU32::new(gain.to_bits()),
+            paused: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+        })
+    }
+
+    fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    fn set_gain(&self, gain: f32) {
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Fade out and end the source
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+}
+
+/// Ramps gain towards the target of its [`FadeControl`]
+///
+/// Paused sources output silence without consuming the inner source.
+struct FadeSource<S> {
+    inner: S,
+    control: Arc<FadeControl>,
+    fade: Duration,
+    current_gain: f32,
+    position: u64,
+}
+
+impl<S: Source<Item = f32>> FadeSource<S> {
+    fn new(inner: S, control: Arc<FadeControl>, fade: Duration, initial_gain: f32) -> Self {
+        Self {
+            inner,
+            control,
+            fade,
+            current_gain: initial_gain,
+            position: 0,
+        }
+    }
+
+    fn fade_step(&self) -> f32 {
+        let fade_samples = self.fade.as_secs_f32()
+            * self.inner.sample_rate() as f32
+            * self.inner.channels() as f32;
+        if fade_samples < 1.0 {
+            1.0
+        } else {
+            1.0 / fade_samples
+        }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for FadeSource<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let stopped = self.control.is_stopped();
+        let paused = self.control.paused.load(Ordering::Relaxed);
+        let target = if stopped || paused {
+            0.0
+        } else {
+            self.control.gain()
+        };
+        let step = self.fade_step();
+        self.current_gain = if self.current_gain < target {
+            (self.current_gain + step).min(target)
+        } else {
+            (self.current_gain - step).max(target)
+        };
+
+        // only stop between frames so that channels don't get swapped
+        let at_frame_start = self.position % self.inner.channels().max(1) as u64 == 0;
+        if self.current_gain == 0.0 && at_frame_start {
+            if stopped {
+                return None;
+            }
+            if paused {
+                return Some(0.0);
+            }
+        }
+        let sample = self.inner.next()?;
+        self.position += 1;
+        // last line of clipping protection when lanes add up
+        Some((sample * self.current_gain).clamp(-1.0, 1.0))
+    }
+}
+
+impl<S: Source<Item = f32>> Source for FadeSource<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Ends right away if the deadline passed before the first sample was requested
+struct DeadlineSource<S> {
+    inner: S,
+    deadline: Option<Deadline>,
+}
+
+impl<S: Source<Item = f32>> Iterator for DeadlineSource<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(deadline) = self.deadline.take() {
+            if deadline.is_past() {
+                info!("Discarding expired audio");
+                deadline.expire();
+                return None;
+            }
+        }
+        self.inner.next()
+    }
+}
+
+impl<S: Source<Item = f32>> Source for DeadlineSource<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+struct DecodedChunk {
+    channels: u16,
+    sample_rate: u32,
+    samples: Vec<f32>,
+}
+
+impl DecodedChunk {
+    fn duration(&self) -> Duration {
+        Duration::from_secs_f32(
+            self.samples.len() as f32 / (self.channels as f32 * self.sample_rate as f32),
+        )
+    }
+}
+
+/// Decodes on its own thread so that a slow stream can't stall the mixer
+///
+/// Outputs silence until [`STREAM_PREFILL`] is buffered and again whenever decoding falls behind.
+struct DecodeAheadSource {
+    chunks: Receiver<DecodedChunk>,
+    buffered: VecDeque<DecodedChunk>,
+    decoding: bool,
+    buffering: bool,
+    /// Only empty once the stream ended
+    current: std::vec::IntoIter<f32>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl DecodeAheadSource {
+    fn spawn(sound: Box<dyn Playable>) -> Self {
+        let (sender, chunks) = sync_channel(DECODE_AHEAD_CHUNKS);
+        thread::spawn(move || decode_chunks(sound, sender));
+        let mut source = Self {
+            chunks,
+            buffered: VecDeque::new(),
+            decoding: true,
+            buffering: true,
+            current: Vec::new().into_iter(),
+            channels: MIXER_CHANNELS,
+            sample_rate: MIXER_SAMPLE_RATE,
+        };
+        source.refill();
+        source
+    }
+
+    fn refill(&mut self) {
+        loop {
+            match self.chunks.try_recv() {
+                Ok(chunk) => self.buffered.push_back(chunk),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.decoding = false;
+                    break;
+                }
+            }
+        }
+        let buffered: Duration = self.buffered.iter().map(DecodedChunk::duration).sum();
+        if self.buffering && self.decoding && buffered < STREAM_PREFILL {
+            self.play_silence();
+            return;
+        }
+        match self.buffered.pop_front() {
+            Some(chunk) => {
+                self.buffering = false;
+                self.channels = chunk.channels;
+                self.sample_rate = chunk.sample_rate;
+                self.current = chunk.samples.into_iter();
+            }
+            None if self.decoding => {
+                self.buffering = true;
+                self.play_silence();
+            }
+            None => (),
+        }
+    }
+
+    fn play_silence(&mut self) {
+        let frames = (self.sample_rate as f32 * STREAM_UNDERRUN_SILENCE.as_secs_f32()) as usize;
+        self.current = vec![0.0; frames.max(1) * self.channels as usize].into_iter();
+    }
+}
+
+fn decode_chunks(sound: Box<dyn Playable>, sender: SyncSender<DecodedChunk>) {
+    let mut decoder = match rodio::Decoder::new(sound) {
+        Ok(decoder) => decoder.convert_samples::<f32>(),
+        Err(e) => {
+            error!("Failed to decode audio stream {:?}", e);
+            return;
+        }
+    };
+    loop {
+        let channels = decoder.channels();
+        let sample_rate = decoder.sample_rate();
+        // chunks never straddle a format change
+        let length = decoder
+            .current_frame_len()
+            .filter(|length| *length > 0)
+            .unwrap_or(DECODE_CHUNK_SAMPLES)
+            .min(DECODE_CHUNK_SAMPLES);
+        let length = (length - length % channels.max(1) as usize).max(channels.max(1) as usize);
+        let samples: Vec<f32> = decoder.by_ref().take(length).collect();
+        if samples.is_empty() {
+            return;
+        }
+        let chunk = DecodedChunk {
+            channels,
+            sample_rate,
+            samples,
+        };
+        // the player dropped the sound
+        if sender.send(chunk).is_err() {
+            return;
+        }
+    }
+}
+
+impl Iterator for DecodeAheadSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.current.next()?;
+        if self.current.len() == 0 {
+            self.refill();
+        }
+        Some(sample)
+    }
+}
+
+impl Source for DecodeAheadSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.current.len())
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+struct QueuedSound {
+    control: Arc<FadeControl>,
+    finished: Receiver<()>,
+}
+
+/// Sounds that play one after another on a single mixer input
+struct Lane {
+    input: Arc<SourcesQueueInput<f32>>,
+    control: Arc<FadeControl>,
+    sounds: VecDeque<QueuedSound>,
+}
+
+impl Lane {
+    fn new(mixer: &DynamicMixerController<f32>, fade: Duration) -> Self {
+        let (input, output) = rodio::queue::queue(true);
+        let control = FadeControl::new(1.0);
+        mixer.add(FadeSource::new(output, control.clone(), fade, 1.0));
+        Self {
+            input,
+            control,
+            sounds: VecDeque::new(),
+        }
+    }
+
+    fn append(&mut self, sound: Box<dyn Playable>, loudness: &LoudnessConfig) -> Result<()> {
+        let gain = sound
+            .loudness()
+            .map(|measured| measured.gain(loudness))
+            .unwrap_or(1.0)
+            * sound.volume();
+        let deadline = sound.deadline().cloned();
+        let sound: Box<dyn Source<Item = f32> + Send> = if sound.is_stream() {
+            Box::new(DecodeAheadSource::spawn(sound))
+        } else {
+            Box::new(
+                rodio::Decoder::new(sound)
+                    .map_err(|_| HomeSpeakError::FailedToDecodeAudioFile)?
+                    .convert_samples::<f32>(),
+            )
+        };
+        let sound = sound.amplify(gain);
+        let sound = DeadlineSource {
+            inner: sound,
+            deadline,
+        };
+        let control = FadeControl::new(1.0);
+        let finished =
+            self.input
+                .append_with_signal(FadeSource::new(sound, control.clone(), SOUND_FADE, 0.0));
+        self.sounds.push_back(QueuedSound { control, finished });
+        Ok(())
+    }
+
+    fn prune_finished(&mut self) {
+        self.sounds
+            .retain(|sound| matches!(sound.finished.try_recv(), Err(TryRecvError::Empty)));
+    }
+
+    fn is_active(&mut self) -> bool {
+        self.prune_finished();
+        !self.sounds.is_empty()
+    }
+
+    /// Finished sounds are pruned first so that the skip lands on the sound that is playing
+    fn skip_one(&mut self) {
+        self.prune_finished();
+        if let Some(sound) = self.sounds.iter().find(|sound| !sound.control.is_stopped()) {
+            sound.control.stop();
+        }
+    }
+
+    fn stop(&mut self) {
+        for sound in &self.sounds {
+            sound.control.stop();
+        }
+    }
+}
+
+struct Player {
+    loudness: LoudnessConfig,
+    master: Arc<FadeControl>,
+    foreground: Lane,
+    urgent: Lane,
+    background: Lane,
+}
+
+impl Player {
+    fn new(
+        output_stream_handle: &rodio::OutputStreamHandle,
+        loudness: LoudnessConfig,
+        volume: f32,
+    ) -> Result<Self> {
+        let (mixer_controller, mixer) =
+            rodio::dynamic_mixer::mixer(MIXER_CHANNELS, MIXER_SAMPLE_RATE);
+        let master = FadeControl::new(volume);
+        output_stream_handle
+            .play_raw(FadeSource::new(mixer, master.clone(), SOUND_FADE, volume))
+            .map_err(|_| HomeSpeakError::FailedToCreateASink)?;
+        Ok(Self {
+            loudness,
+            master,
+            foreground: Lane::new(&mixer_controller, SOUND_FADE),
+            urgent: Lane::new(&mixer_controller, SOUND_FADE),
+            background: Lane::new(&mixer_controller, DUCK_FADE),
+        })
+    }
+
+    /// Urgent sounds pause the foreground and anything other than background ducks the background
+    fn update_ducking(&mut self) {
+        let urgent_active = self.urgent.is_active();
+        self.foreground.control.set_paused(urgent_active);
+        let speaking = urgent_active || self.foreground.is_active();
+        self.background
+            .control
+            .set_gain(if speaking { DUCKED_GAIN } else { 1.0 });
+    }
+}
+
+/// `volume` outlives the loop so that restarts keep the master volume
+fn audio_player_loop(
+    receiver: &Receiver<AudioPlayerCommand>,
+    loudness: &LoudnessConfig,
+    volume: &mut f32,
+) -> anyhow::Result<bool> {
     // let (_output_stream, output_stream_handle) = rodio::OutputStream::try_default()
     //     .map_err(|_| HomeSpeakError::FailedToCreateAnOutputStream)?;
 
     let (_output_stream, output_stream_handle) = select_output_device()?;
 
-    let sink = rodio::Sink::try_new(&output_stream_handle)
-        .map_err(|_| HomeSpeakError::FailedToCreateASink)?;
+    let mut player = Player::new(&output_stream_handle, loudness.clone(), *volume)?;
     loop {
-        let command = receiver.recv()?;
+        let command = match receiver.recv_timeout(STATE_POLL_INTERVAL) {
+            Ok(command) => command,
+            Err(RecvTimeoutError::Timeout) => {
+                player.update_ducking();
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(true),
+        };
         match command {
             AudioPlayerCommand::Play(sound) => {
-                sink.append(
-                    rodio::Decoder::new(sound)
-                        .map_err(|_| HomeSpeakError::FailedToDecodeAudioFile)?,
-                );
+                if let Err(e) = player.foreground.append(sound, &player.loudness) {
+                    error!("Skipping sound that failed to decode {:?}", e);
+                }
+            }
+            AudioPlayerCommand::Preempt(sound) => {
+                info!("Preempting audio");
+                if let Err(e) = player.urgent.append(sound, &player.loudness) {
+                    error!("Skipping sound that failed to decode {:?}", e);
+                }
+            }
+            AudioPlayerCommand::PlayBackground(sound) => {
+                info!("Playing background audio");
+                if let Err(e) = player.background.append(sound, &player.loudness) {
+                    error!("Skipping sound that failed to decode {:?}", e);
+                }
+            }
+            AudioPlayerCommand::StopBackground => {
+                info!("Stopping background audio");
+                player.background.stop();
             }
             AudioPlayerCommand::Pause => {
                 info!("Pausing audio");
-                sink.pause()
+                player.master.set_paused(true);
             }
             AudioPlayerCommand::Resume => {
                 info!("Resuming audio");
-                sink.play()
+                player.master.set_paused(false);
             }
             AudioPlayerCommand::Stop => {
                 info!("Stopping audio");
-                warn!("Ignoring stop because it destroys the sink");
-                // sink.stop()
+                player.urgent.stop();
+                player.foreground.stop();
             }
             AudioPlayerCommand::Restart => {
                 info!("Restarting audio player");
                 return Ok(false);
             }
-            AudioPlayerCommand::Volume(volume) => {
-                info!("Settings volume to {}", volume);
-                sink.set_volume(volume);
+            AudioPlayerCommand::Volume(new_volume) => {
+                info!("Settings volume to {}", new_volume);
+                *volume = new_volume;
+                player.master.set_gain(new_volume);
             }
             AudioPlayerCommand::SkipOne => {
                 info!("Skipping audio source");
-                sink.skip_one();
+                if player.urgent.is_active() {
+                    player.urgent.skip_one();
+                } else {
+                    player.foreground.skip_one();
+                }
             }
         }
+        player.update_ducking();
     }
 }
 
-pub fn create_player() -> Sender<AudioPlayerCommand> {
+pub fn create_player(loudness: LoudnessConfig) -> Sender<AudioPlayerCommand> {
     let (sender, receiver) = channel();
     thread::spawn(move || {
+        let mut volume = 1.0;
         // This may miss on sender being dead. But if sender is dead we have bigger issues
         loop {
-            match audio_player_loop(&receiver) {
+            match audio_player_loop(&receiver, &loudness, &mut volume) {
                 Err(err) => {
                     error!("Audio player loop failed with {}", err);
                 }
@@ -134,3 +797,77 @@ pub fn create_player() -> Sender<AudioPlayerCommand> {
     });
     sender
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_processing::encode_wav;
+    use crate::speech_service::streaming_playable;
+
+    #[test]
+    fn stream_plays_silence_until_prefilled() {
+        let (writer, playable) = streaming_playable();
+        let mut source = DecodeAheadSource::spawn(Box::new(playable));
+        let silence: Vec<f32> = source.by_ref().take(1000).collect();
+        assert_eq!(silence, vec![0.0; 1000]);
+
+        let tone: Vec<i16> = (0..8000)
+            .map(|i| if i % 2 == 0 { 8000 } else { -8000 })
+            .collect();
+        writer.push(&encode_wav(&tone, 1, 8000));
+        writer.finish();
+        // underruns may add silence in between but never drop audio
+        let played = source.by_ref().filter(|sample| *sample != 0.0).count();
+        assert_eq!(played, tone.len());
+        assert_eq!(source.channels(), 1);
+        assert_eq!(source.sample_rate(), 8000);
+        assert_eq!(source.current_frame_len(), Some(0));
+    }
+
+    #[test]
+    fn undecodable_stream_ends() {
+        let (writer, playable) = streaming_playable();
+        writer.push(b"not audio at all");
+        writer.finish();
+        let mut source = DecodeAheadSource::spawn(Box::new(playable));
+        assert!(source.all(|sample| sample == 0.0));
+    }
+
+    fn tone(samples: usize) -> Box<dyn Playable> {
+        let tone: Vec<i16> = (0..samples)
+            .map(|i| if i % 2 == 0 { 8000 } else { -8000 })
+            .collect();
+        Box::new(Cursor::new(encode_wav(&tone, 1, 8000)))
+    }
+
+    #[test]
+    fn skip_one_ignores_finished_sounds() {
+        let (mixer_controller, mut mixer) =
+            rodio::dynamic_mixer::mixer::<f32>(MIXER_CHANNELS, MIXER_SAMPLE_RATE);
+        let mut lane = Lane::new(&mixer_controller, SOUND_FADE);
+        let loudness = LoudnessConfig::default();
+        lane.append(tone(80), &loudness).unwrap();
+        lane.append(tone(16000), &loudness).unwrap();
+        // plays the first sound to the end and starts on the second
+        mixer
+            .by_ref()
+            .take((MIXER_SAMPLE_RATE * MIXER_CHANNELS as u32) as usize / 2)
+            .for_each(drop);
+
+        lane.skip_one();
+        assert_eq!(lane.sounds.len(), 1);
+        assert!(lane.sounds[0].control.is_stopped());
+    }
+
+    #[test]
+    fn undecodable_sound_is_rejected_without_affecting_the_lane() {
+        let (mixer_controller, _mixer) =
+            rodio::dynamic_mixer::mixer::<f32>(MIXER_CHANNELS, MIXER_SAMPLE_RATE);
+        let mut lane = Lane::new(&mixer_controller, SOUND_FADE);
+        let loudness = LoudnessConfig::default();
+        lane.append(tone(16000), &loudness).unwrap();
+        let garbage = Box::new(Cursor::new(b"not audio at all".to_vec()));
+        assert!(lane.append(garbage, &loudness).is_err());
+        assert_eq!(lane.sounds.len(), 1);
+    }
+}
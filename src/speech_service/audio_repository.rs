@@ -1,97 +1,417 @@
-use super::AudioService;
-use crate::error::{HomeSpeakError, Result};
+use super::{
+    audio_player::{MeasuredPlayable, Playable},
+    shuffle_bag::ShuffleBag,
+    sound_library::{is_supported_format, sidecar_path, SoundEntry, SoundLibrary},
+    AudioService,
+};
+use crate::configuration::AudioLibraryConfig;
+use crate::error::{AudioRepositoryError, HomeSpeakError, Result};
+use crate::file_watcher::watch_debounced;
+use bytes::Bytes;
+use notify::{RecommendedWatcher, RecursiveMode};
 use rand::seq::SliceRandom;
+use std::collections::HashMap;
 use std::fs::{self, File};
-use std::path::{Path, PathBuf};
+use std::io::{Cursor, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::watch;
+use tracing::*;
 
 #[derive(Debug, Clone)]
 pub struct AudioRepository {
     dir_path: PathBuf,
+    library: Arc<RwLock<SoundLibrary>>,
+    random_exclude: Vec<String>,
+    shuffle_bags: Arc<Mutex<HashMap<PathBuf, ShuffleBag>>>,
+    index_updates: Arc<watch::Sender<()>>,
     audio_service: AudioService,
 }
 
+/// Resolve a path relative to the canonical repository root
+///
+/// Symlinks are followed but the final path has to stay inside of the repository.
+fn resolve_in(
+    dir_path: &Path,
+    relative_path: &str,
+) -> std::result::Result<PathBuf, AudioRepositoryError> {
+    let relative_path = Path::new(relative_path);
+    for component in relative_path.components() {
+        match component {
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(AudioRepositoryError::AbsolutePath)
+            }
+            Component::ParentDir => return Err(AudioRepositoryError::PathOutsideRepository),
+            Component::CurDir | Component::Normal(_) => (),
+        }
+    }
+    let canonical_path = dir_path
+        .join(relative_path)
+        .canonicalize()
+        .map_err(|_| AudioRepositoryError::NotFound)?;
+    if !canonical_path.starts_with(dir_path) {
+        return Err(AudioRepositoryError::PathOutsideRepository);
+    }
+    Ok(canonical_path)
+}
+
 impl AudioRepository {
-    pub fn new(dir_path: &Path, audio_service: AudioService) -> Result<Self> {
+    pub fn new(
+        dir_path: &Path,
+        library_config: &AudioLibraryConfig,
+        audio_service: AudioService,
+    ) -> Result<Self> {
         let path = Path::new(&dir_path);
         fs::create_dir_all(path)?;
         if !path.exists() {
             return Err(HomeSpeakError::AudioCacheDirError);
         }
+        // canonical so that resolved paths can be checked against it
+        let dir_path = path.canonicalize()?;
+        let library = SoundLibrary::scan(&dir_path);
+        let (index_updates, _) = watch::channel(());
         Ok(Self {
-            dir_path: dir_path.to_owned(),
+            dir_path,
+            library: Arc::new(RwLock::new(library)),
+            random_exclude: library_config.random_exclude.clone(),
+            shuffle_bags: Arc::default(),
+            index_updates: Arc::new(index_updates),
             audio_service,
         })
     }
 
-    pub fn play_file(&self, sound_name: &str) -> anyhow::Result<bool> {
-        let file_path = self.dir_path.join(sound_name);
-        if let Ok(file) = File::open(file_path) {
-            self.audio_service.play(Box::new(file))?;
-            Ok(true)
-        } else {
-            Ok(false)
+    /// Rebuild the index from the files currently on disk
+    ///
+    /// Only new and changed sounds get decoded again.
+    pub fn refresh_index(&self) {
+        // not holding the lock while decoding
+        let previous = self.library.read().unwrap().clone();
+        let library = previous.rescan(&self.dir_path);
+        *self.library.write().unwrap() = library;
+        self.index_updates.send_replace(());
+    }
+
+    /// Notified every time the index is rebuilt
+    pub fn subscribe_index_updates(&self) -> watch::Receiver<()> {
+        self.index_updates.subscribe()
+    }
+
+    /// Keep the index in sync with the repository directory
+    ///
+    /// Watching stops when the returned watcher is dropped.
+    pub fn watch_for_changes(&self) -> anyhow::Result<RecommendedWatcher> {
+        let repository = self.clone();
+        let watcher = watch_debounced(&self.dir_path, RecursiveMode::Recursive, move || {
+            info!("Audio repository changed. Rebuilding index");
+            let repository = repository.clone();
+            async move {
+                // decoding every file to get durations is slow
+                if let Err(error) =
+                    tokio::task::spawn_blocking(move || repository.refresh_index()).await
+                {
+                    error!("Failed to rebuild audio index {:?}", error);
+                }
+            }
+        })?;
+        Ok(watcher)
+    }
+
+    pub fn catalog(&self) -> Vec<SoundEntry> {
+        self.library.read().unwrap().sounds().to_vec()
+    }
+
+    fn resolve(&self, relative_path: &str) -> std::result::Result<PathBuf, AudioRepositoryError> {
+        resolve_in(&self.dir_path, relative_path)
+    }
+
+    pub fn open_file(&self, sound_name: &str) -> anyhow::Result<Box<dyn Playable>> {
+        let file_path = self.resolve(sound_name)?;
+        if !file_path.is_file() {
+            return Err(AudioRepositoryError::NotAFile.into());
+        }
+        let relative_path = file_path
+            .strip_prefix(&self.dir_path)
+            .map_err(|_| AudioRepositoryError::PathOutsideRepository)?;
+        self.open_indexed(relative_path)
+    }
+
+    pub fn play_file(&self, sound_name: &str) -> anyhow::Result<()> {
+        let sound = self.open_file(sound_name)?;
+        self.audio_service.play(sound)?;
+        Ok(())
+    }
+
+    pub fn play_by_name(&self, name: &str) -> anyhow::Result<()> {
+        let path = self
+            .library
+            .read()
+            .unwrap()
+            .by_name(name)
+            .map(|sound| sound.path.clone())
+            .ok_or(AudioRepositoryError::NotFound)?;
+        self.play_indexed(&path)
+    }
+
+    /// Play all sounds with tag one after another
+    pub fn play_tag(&self, tag: &str) -> anyhow::Result<()> {
+        let paths: Vec<_> = self
+            .library
+            .read()
+            .unwrap()
+            .with_tag(tag)
+            .map(|sound| sound.path.clone())
+            .collect();
+        if paths.is_empty() {
+            return Err(AudioRepositoryError::NotFound.into());
+        }
+        // one slot so that other messages don't end up in between
+        let slot = self.audio_service.reserve_slot();
+        for path in paths {
+            slot.play(self.open_indexed(&path)?)?;
+        }
+        Ok(())
+    }
+
+    pub fn play_random_with_tag(&self, tag: &str) -> anyhow::Result<()> {
+        let path = {
+            let library = self.library.read().unwrap();
+            let sounds: Vec<_> = library.with_tag(tag).collect();
+            sounds
+                .choose(&mut rand::thread_rng())
+                .map(|sound| sound.path.clone())
+                .ok_or(AudioRepositoryError::NotFound)?
+        };
+        self.play_indexed(&path)
+    }
+
+    /// Index only stores paths relative to the repository
+    ///
+    /// Sounds that are indexed come with their measured loudness.
+    fn open_indexed(&self, relative_path: &Path) -> anyhow::Result<Box<dyn Playable>> {
+        let file = File::open(self.dir_path.join(relative_path))
+            .map_err(|_| AudioRepositoryError::NotFound)?;
+        let loudness = self
+            .library
+            .read()
+            .unwrap()
+            .by_path(relative_path)
+            .and_then(|sound| sound.loudness);
+        Ok(MeasuredPlayable::boxed(file, loudness))
+    }
+
+    fn play_indexed(&self, relative_path: &Path) -> anyhow::Result<()> {
+        self.audio_service.play(self.open_indexed(relative_path)?)?;
+        Ok(())
+    }
+
+    /// Uploaded sounds are stored in the root of the repository
+    fn upload_path(&self, name: &str) -> std::result::Result<PathBuf, AudioRepositoryError> {
+        let path = Path::new(name);
+        let mut components = path.components();
+        let is_file_name =
+            matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+        // hidden names are reserved for files that are still being written
+        if !is_file_name || name.starts_with('.') || !is_supported_format(path) {
+            return Err(AudioRepositoryError::InvalidName);
         }
+        Ok(self.dir_path.join(path))
     }
 
+    /// Store a new sound, replacing any sound with the same name
+    ///
+    /// The data is written to a temporary file first so that a half written
+    /// sound never shows up in the library.
+    pub fn add_sound(&self, name: &str, data: Bytes) -> anyhow::Result<()> {
+        let path = self.upload_path(name)?;
+        let mut decoder = rodio::Decoder::new(Cursor::new(data.clone()))
+            .map_err(|_| AudioRepositoryError::InvalidAudio)?;
+        if decoder.next().is_none() {
+            return Err(AudioRepositoryError::InvalidAudio.into());
+        }
+
+        let temp_path = self.dir_path.join(format!(".{}.upload", name));
+        let write_result = File::create(&temp_path).and_then(|mut file| {
+            file.write_all(&data)?;
+            file.sync_all()
+        });
+        if let Err(error) = write_result.and_then(|_| fs::rename(&temp_path, &path)) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(error.into());
+        }
+        info!("Stored uploaded sound {:?}", path);
+        Ok(())
+    }
+
+    /// Remove a sound from the root of the repository together with its metadata
+    pub fn delete_sound(&self, name: &str) -> anyhow::Result<()> {
+        let path = self.upload_path(name)?;
+        if !path.is_file() {
+            return Err(AudioRepositoryError::NotFound.into());
+        }
+        fs::remove_file(&path)?;
+        let sidecar_path = sidecar_path(&path);
+        if sidecar_path.exists() {
+            fs::remove_file(sidecar_path)?;
+        }
+        info!("Deleted sound {:?}", path);
+        Ok(())
+    }
+
+    /// Shuffle through the sounds in subdirectory without repeating any of them
+    /// until all of them were played
     pub fn random_file_from_dir(&self, subdirectory: &str) -> anyhow::Result<bool> {
-        // This is a pretty complicated way to do this
-        // but oh well... it's fast enough for now
-
-        let full_path = self.dir_path.join(subdirectory);
-
-        if let Ok(paths) = std::fs::read_dir(full_path) {
-            let files: Vec<_> = paths
-                .filter_map(|path| path.ok())
-                .filter(|entry| {
-                    entry
-                        .file_type()
-                        .map(|file_type| file_type.is_file())
-                        .unwrap_or(false)
-                })
-                .map(|file_entry| file_entry.path())
-                .collect();
-            let mut rng = rand::thread_rng();
-
-            let audio_file = files
-                .choose(&mut rng)
-                .and_then(|path| File::open(path).ok())
-                .map(Box::new);
-
-            if let Some(audio_file) = audio_file {
-                self.audio_service.play(audio_file)?;
-                Ok(true)
-            } else {
-                Ok(false)
-            }
+        let full_path = self.resolve(subdirectory)?;
+        let directory = full_path
+            .strip_prefix(&self.dir_path)
+            .map_err(|_| AudioRepositoryError::PathOutsideRepository)?;
+
+        let candidates: Vec<_> = self
+            .library
+            .read()
+            .unwrap()
+            .in_directory(directory)
+            .map(|sound| (sound.path.clone(), sound.weight))
+            .collect();
+
+        let path = self
+            .shuffle_bags
+            .lock()
+            .unwrap()
+            .entry(directory.to_owned())
+            .or_default()
+            .draw(&candidates, &mut rand::thread_rng());
+
+        if let Some(path) = path {
+            self.play_indexed(&path)?;
+            Ok(true)
         } else {
             Ok(false)
         }
     }
 
     pub fn random_file_recursive(&self) -> anyhow::Result<()> {
-        let walker = walkdir::WalkDir::new(&self.dir_path).into_iter();
-
-        let files = walker
-            // ignore errors
-            .filter_map(|entry| entry.ok())
-            // take only files
-            .filter(|entry| entry.file_type().is_file())
-            // turn to paths
-            .map(|file_entry| file_entry.path().to_path_buf())
-            // ignore astromech because those files are boring
-            .filter(|path| !path.to_string_lossy().contains("astromech"))
-            .collect::<Vec<_>>();
-
-        let mut rng = rand::thread_rng();
-
-        let audio_file = files
-            .choose(&mut rng)
-            .and_then(|path| File::open(path).ok())
-            .map(Box::new);
-
-        if let Some(audio_file) = audio_file {
-            self.audio_service.play(audio_file)?;
+        let path = {
+            let library = self.library.read().unwrap();
+            let sounds: Vec<_> = library
+                .sounds()
+                .iter()
+                .filter(|sound| !self.is_excluded_from_random(sound))
+                .collect();
+            sounds
+                .choose(&mut rand::thread_rng())
+                .map(|sound| sound.path.clone())
+        };
+
+        if let Some(path) = path {
+            self.play_indexed(&path)?;
         }
         Ok(())
     }
+
+    fn is_excluded_from_random(&self, sound: &SoundEntry) -> bool {
+        let path = sound.path.to_string_lossy();
+        self.random_exclude
+            .iter()
+            .any(|excluded| path.contains(excluded.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// Repository inside of a directory that also holds files outside of it
+    fn repository() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("repository");
+        fs::create_dir_all(root.join("doors/front")).unwrap();
+        fs::write(root.join("bell.mp3"), b"").unwrap();
+        fs::write(root.join("doors/front/open.mp3"), b"").unwrap();
+        fs::write(dir.path().join("secret.mp3"), b"").unwrap();
+        let root = root.canonicalize().unwrap();
+        (dir, root)
+    }
+
+    fn error(root: &Path, relative_path: &str) -> AudioRepositoryError {
+        resolve_in(root, relative_path).unwrap_err()
+    }
+
+    #[test]
+    fn nested_paths_resolve() {
+        let (_dir, root) = repository();
+        assert_eq!(
+            resolve_in(&root, "bell.mp3").unwrap(),
+            root.join("bell.mp3")
+        );
+        assert_eq!(
+            resolve_in(&root, "doors/front/open.mp3").unwrap(),
+            root.join("doors/front/open.mp3")
+        );
+        assert_eq!(
+            resolve_in(&root, "./doors/front").unwrap(),
+            root.join("doors/front")
+        );
+    }
+
+    #[test]
+    fn parent_directories_are_rejected() {
+        let (_dir, root) = repository();
+        for path in [
+            "../secret.mp3",
+            "doors/../../secret.mp3",
+            "doors/../bell.mp3",
+        ] {
+            assert!(matches!(
+                error(&root, path),
+                AudioRepositoryError::PathOutsideRepository
+            ));
+        }
+    }
+
+    #[test]
+    fn absolute_paths_are_rejected() {
+        let (dir, root) = repository();
+        let outside = dir.path().join("secret.mp3");
+        let inside = root.join("bell.mp3");
+        for path in [
+            outside.to_str().unwrap(),
+            inside.to_str().unwrap(),
+            "/etc/passwd",
+        ] {
+            assert!(matches!(
+                error(&root, path),
+                AudioRepositoryError::AbsolutePath
+            ));
+        }
+    }
+
+    #[test]
+    fn symlinks_may_not_escape() {
+        let (dir, root) = repository();
+        symlink(dir.path().join("secret.mp3"), root.join("escape.mp3")).unwrap();
+        symlink(dir.path(), root.join("outside")).unwrap();
+        symlink(root.join("doors/front/open.mp3"), root.join("alias.mp3")).unwrap();
+        assert!(matches!(
+            error(&root, "escape.mp3"),
+            AudioRepositoryError::PathOutsideRepository
+        ));
+        assert!(matches!(
+            error(&root, "outside/secret.mp3"),
+            AudioRepositoryError::PathOutsideRepository
+        ));
+        assert_eq!(
+            resolve_in(&root, "alias.mp3").unwrap(),
+            root.join("doors/front/open.mp3")
+        );
+    }
+
+    #[test]
+    fn missing_sounds_are_not_found() {
+        let (_dir, root) = repository();
+        assert!(matches!(
+            error(&root, "missing.mp3"),
+            AudioRepositoryError::NotFound
+        ));
+    }
 }
@@ -2,10 +2,13 @@ use anyhow::Result;
 use base64::{engine::general_purpose, Engine as _};
 use serde::{Deserialize, Serialize};
 use std::sync::mpsc::Sender;
-use tokio::sync::mpsc::UnboundedSender as TokioSender;
+use tokio::sync::mpsc::{
+    unbounded_channel, UnboundedReceiver as TokioReceiver, UnboundedSender as TokioSender,
+};
+use tracing::*;
 
-use super::audio_player::{create_player, AudioPlayerCommand, Playable};
-use crate::{error::HomeSpeakError, AUDIO_FILE_EXTENSION};
+use super::audio_player::{create_player, AudioPlayerCommand, Deadline, Playable, SlotPlayable};
+use crate::{configuration::LoudnessConfig, error::HomeSpeakError, AUDIO_FILE_EXTENSION};
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct AudioMessage {
@@ -13,42 +16,138 @@ pub struct AudioMessage {
     pub format: String,
 }
 
+/// Processed cache entries are stored as wav
+fn audio_format(payload: &[u8]) -> &'static str {
+    if payload.starts_with(b"RIFF") {
+        "wav"
+    } else {
+        AUDIO_FILE_EXTENSION
+    }
+}
+
+type SlotReceiver = TokioReceiver<Box<dyn Playable>>;
+
+/// Forwards sounds to the player one slot at a time in the order in which the slots were reserved
+fn start_playback_sequencer(audio_sender: Sender<AudioPlayerCommand>) -> TokioSender<SlotReceiver> {
+    let (slot_sender, mut slot_receiver) = unbounded_channel::<SlotReceiver>();
+    tokio::spawn(async move {
+        while let Some(mut slot) = slot_receiver.recv().await {
+            while let Some(sound) = slot.recv().await {
+                if audio_sender.send(AudioPlayerCommand::Play(sound)).is_err() {
+                    error!("Audio player channel closed");
+                }
+            }
+        }
+    });
+    slot_sender
+}
+
 #[derive(Debug, Clone)]
 pub struct AudioService {
     audio_sender: Sender<AudioPlayerCommand>,
+    slot_sender: TokioSender<SlotReceiver>,
     audio_data_broadcaster: Option<TokioSender<AudioMessage>>,
 }
 
 impl AudioService {
-    pub fn new(audio_data_broadcaster: Option<TokioSender<AudioMessage>>) -> Result<Self> {
-        let audio_sender = create_player();
+    pub fn new(
+        audio_data_broadcaster: Option<TokioSender<AudioMessage>>,
+        loudness: LoudnessConfig,
+    ) -> Result<Self> {
+        let audio_sender = create_player(loudness);
+        let slot_sender = start_playback_sequencer(audio_sender.clone());
 
         Ok(AudioService {
             audio_sender,
+            slot_sender,
             audio_data_broadcaster,
         })
     }
 
-    pub fn play(&self, mut data: Box<dyn Playable>) -> Result<()> {
+    /// Reserve a place in the playback queue
+    ///
+    /// Sounds played through the slot wait until all previously reserved slots are dropped.
+    /// This lets requests synthesize concurrently while still playing in the order they arrived.
+    pub fn reserve_slot(&self) -> PlaybackSlot {
+        self.reserve_slot_with(1.0, None)
+    }
+
+    /// Like [`AudioService::reserve_slot`] but everything in the slot plays at a reduced volume
+    /// and gets discarded if it didn't start playing before the deadline
+    pub fn reserve_slot_with(&self, volume: f32, deadline: Option<Deadline>) -> PlaybackSlot {
+        let (sender, receiver) = unbounded_channel();
+        if self.slot_sender.send(receiver).is_err() {
+            error!("Playback sequencer stopped");
+        }
+        PlaybackSlot {
+            sender,
+            audio_service: self.clone(),
+            volume,
+            deadline,
+        }
+    }
+
+    /// Slot that collects sounds instead of playing them
+    ///
+    /// Drop the slot before collecting the [`StagedSounds`].
+    pub fn staging_slot(&self) -> (PlaybackSlot, StagedSounds) {
+        let (sender, receiver) = unbounded_channel();
+        let slot = PlaybackSlot {
+            sender,
+            audio_service: self.clone(),
+            volume: 1.0,
+            deadline: None,
+        };
+        (slot, StagedSounds { receiver })
+    }
+
+    pub fn play(&self, data: Box<dyn Playable>) -> Result<()> {
+        self.reserve_slot().play(data)
+    }
+
+    /// Pause the current playback and play data right away
+    ///
+    /// Paused playback resumes once data finishes.
+    pub fn preempt(&self, mut data: Box<dyn Playable>) -> Result<()> {
         self.publish_audio_file(&mut data)?;
         self.audio_sender
-            .send(AudioPlayerCommand::Play(data))
-            .unwrap();
+            .send(AudioPlayerCommand::Preempt(data))
+            .map_err(|_| HomeSpeakError::AudioChannelSendError)?;
+        Ok(())
+    }
+
+    /// Background audio is ducked while anything else plays
+    pub fn play_background(&self, data: Box<dyn Playable>) -> Result<()> {
+        self.audio_sender
+            .send(AudioPlayerCommand::PlayBackground(data))
+            .map_err(|_| HomeSpeakError::AudioChannelSendError)?;
         Ok(())
     }
 
+    pub fn stop_background(&self) {
+        self.audio_sender
+            .send(AudioPlayerCommand::StopBackground)
+            .unwrap();
+    }
+
     pub fn restart_player(&self) -> Result<()> {
         self.audio_sender.send(AudioPlayerCommand::Restart).unwrap();
         Ok(())
     }
 
     fn publish_audio_file(&self, data: &mut Box<dyn Playable>) -> Result<()> {
+        if self.audio_data_broadcaster.is_some() {
+            self.publish_audio_data(&data.as_bytes()?)?;
+        }
+        Ok(())
+    }
+
+    pub fn publish_audio_data(&self, payload: &[u8]) -> Result<()> {
         if let Some(sender) = self.audio_data_broadcaster.as_ref().cloned() {
-            let payload = data.as_bytes()?;
             let base64_wav_file: String = general_purpose::STANDARD.encode(payload);
             let message = AudioMessage {
                 data: base64_wav_file,
-                format: AUDIO_FILE_EXTENSION.to_owned(),
+                format: audio_format(payload).to_owned(),
             };
             sender
                 .send(message)
@@ -79,3 +178,52 @@ impl AudioService {
             .unwrap();
     }
 }
+
+/// Place in the playback queue reserved by [`AudioService::reserve_slot`]
+#[derive(Debug)]
+pub struct PlaybackSlot {
+    sender: TokioSender<Box<dyn Playable>>,
+    audio_service: AudioService,
+    volume: f32,
+    deadline: Option<Deadline>,
+}
+
+impl PlaybackSlot {
+    pub fn play(&self, mut data: Box<dyn Playable>) -> Result<()> {
+        self.audio_service.publish_audio_file(&mut data)?;
+        self.play_streaming(data)
+    }
+
+    /// Play audio that is still being received
+    ///
+    /// Doesn't publish the audio since the data isn't complete yet.
+    /// Use [`AudioService::publish_audio_data`] once the stream is done.
+    pub fn play_streaming(&self, data: Box<dyn Playable>) -> Result<()> {
+        let data = if self.volume < 1.0 || self.deadline.is_some() {
+            SlotPlayable::boxed(data, self.volume, self.deadline.clone())
+        } else {
+            data
+        };
+        self.sender
+            .send(data)
+            .map_err(|_| HomeSpeakError::AudioChannelSendError)?;
+        Ok(())
+    }
+}
+
+/// Sounds collected by a slot from [`AudioService::staging_slot`]
+#[derive(Debug)]
+pub struct StagedSounds {
+    receiver: SlotReceiver,
+}
+
+impl StagedSounds {
+    /// Waits until the staging slot is dropped
+    pub async fn into_sounds(mut self) -> Vec<Box<dyn Playable>> {
+        let mut sounds = vec![];
+        while let Some(sound) = self.receiver.recv().await {
+            sounds.push(sound);
+        }
+        sounds
+    }
+}
@@ -1,13 +1,19 @@
 use anyhow::Result;
-use secrecy::{ExposeSecret, Secret};
+use futures::StreamExt;
+use secrecy::ExposeSecret;
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
-use std::io::Cursor;
 use tracing::*;
 
-use crate::{audio_cache::AudioCache, error::HomeSpeakError};
+use crate::{
+    audio_cache::AudioCache,
+    configuration::{TextChunkingConfig, TtsRequestConfig, TtsServiceConfig},
+    error::HomeSpeakError,
+    retry::{retry_decision, with_retry},
+    text_chunking::split_into_chunks,
+};
 
-use super::{AudioService, Playable};
+use super::{AudioService, Playable, PlaybackSlot};
 
 fn hash_google_tts(text: &str, voice: &google_tts::VoiceProps) -> String {
     let mut hasher = Sha256::new();
@@ -49,6 +55,37 @@ fn hash_azure_tts(
     format!("{}-{:x}", voice.name, hashed)
 }
 
+fn azure_segments(text: &str, style: AzureVoiceStyle) -> Vec<azure_tts::VoiceSegment> {
+    let mut segments = vec![
+        azure_tts::VoiceSegment::silence(
+            azure_tts::SilenceAttributeType::Sentenceboundary,
+            "50ms".to_owned(),
+        ),
+        azure_tts::VoiceSegment::silence(
+            azure_tts::SilenceAttributeType::Tailing,
+            "25ms".to_owned(),
+        ),
+        azure_tts::VoiceSegment::silence(
+            azure_tts::SilenceAttributeType::Leading,
+            "25ms".to_owned(),
+        ),
+    ];
+    let contents = match style {
+        AzureVoiceStyle::Plain => azure_tts::VoiceSegment::plain(text),
+        AzureVoiceStyle::Angry => {
+            azure_tts::VoiceSegment::with_expression(text, azure_tts::Style::Angry)
+        }
+        AzureVoiceStyle::Sad => {
+            azure_tts::VoiceSegment::with_expression(text, azure_tts::Style::Sad)
+        }
+        AzureVoiceStyle::Cheerful => {
+            azure_tts::VoiceSegment::with_expression(text, azure_tts::Style::Cheerful)
+        }
+    };
+    segments.push(contents);
+    segments
+}
+
 #[derive(Deserialize, Debug, Clone, Copy)]
 pub enum TtsService {
     Azure,
@@ -73,34 +110,23 @@ pub struct SpeechService {
     google_voice: google_tts::VoiceProps,
     azure_voice: azure_tts::VoiceSettings,
     azure_audio_format: azure_tts::AudioFormat,
+    azure_request_config: TtsRequestConfig,
+    google_request_config: TtsRequestConfig,
+    text_chunking: TextChunkingConfig,
     audio_service: AudioService,
 }
 
 impl SpeechService {
     pub fn new(
-        google_api_key: Secret<String>,
-        azure_subscription_key: Secret<String>,
+        tts_service_config: &TtsServiceConfig,
         audio_cache: AudioCache,
         audio_service: AudioService,
     ) -> Result<SpeechService> {
-        Self::new_with_mqtt(
-            google_api_key,
-            azure_subscription_key,
-            audio_cache,
-            audio_service,
-        )
-    }
-
-    pub fn new_with_mqtt(
-        google_api_key: Secret<String>,
-        azure_subscription_key: Secret<String>,
-        audio_cache: AudioCache,
-        audio_service: AudioService,
-    ) -> Result<SpeechService> {
-        let google_speech_client =
-            google_tts::GoogleTtsClient::new(google_api_key.expose_secret().to_owned());
+        let google_speech_client = google_tts::GoogleTtsClient::new(
+            tts_service_config.google_api_key.expose_secret().to_owned(),
+        );
         let azure_speech_client = azure_tts::VoiceService::new(
-            azure_subscription_key.expose_secret(),
+            tts_service_config.azure_api_key.expose_secret(),
             azure_tts::Region::uksouth,
         );
 
@@ -111,116 +137,149 @@ impl SpeechService {
             google_voice: google_tts::VoiceProps::default_english_female_wavenet(),
             azure_voice: azure_tts::EnUsVoices::SaraNeural.to_voice_settings(),
             azure_audio_format: azure_tts::AudioFormat::Audio48khz192kbitrateMonoMp3,
+            azure_request_config: tts_service_config.azure.clone(),
+            google_request_config: tts_service_config.google.clone(),
+            text_chunking: tts_service_config.text_chunking.clone(),
             audio_service,
         })
     }
 
-    async fn say_google(&mut self, text: &str) -> Result<()> {
+    async fn say_google(&self, text: &str, slot: &PlaybackSlot) -> Result<()> {
+        let chunks = split_into_chunks(text, self.text_chunking.max_chunk_length);
+        let mut sounds = futures::stream::iter(chunks)
+            .map(|chunk| async move { self.synthesize_google(&chunk).await })
+            .buffered(self.text_chunking.max_concurrent_chunks.max(1));
+
+        while let Some(sound) = sounds.next().await {
+            slot.play(sound?)?;
+        }
+        Ok(())
+    }
+
+    async fn synthesize_google(&self, text: &str) -> Result<Box<dyn Playable>> {
         let file_key = hash_google_tts(text, &self.google_voice);
-        let playable: Box<dyn Playable> = if let Some(file) = self.audio_cache.get(&file_key) {
+        let cached = self.audio_cache.get(&file_key).await;
+        let playable: Box<dyn Playable> = if let Some(file) = cached {
             info!("Using cached value with key {}", file_key);
             file
         } else {
             info!("Writing new file with key {}", file_key);
-            let data = self
-                .google_speech_client
-                .synthesize(
-                    google_tts::TextInput::with_text(text.to_owned()),
-                    self.google_voice.clone(),
-                    google_tts::AudioConfig::default_with_encoding(google_tts::AudioEncoding::Mp3),
-                )
-                .await
-                .map_err(|_| HomeSpeakError::GoogleTtsError)?;
-            self.audio_cache.set(
-                &file_key,
-                data.as_byte_stream()
-                    .map_err(|_| HomeSpeakError::GoogleTtsError)?,
-            )?;
-            Box::new(Cursor::new(
-                data.as_byte_stream()
-                    .map_err(|_| HomeSpeakError::GoogleTtsError)?,
-            ))
+            let google_client = &self.google_speech_client;
+            let google_voice = &self.google_voice;
+            let request_timeout = self.google_request_config.request_timeout();
+            let data = with_retry(
+                "google_tts",
+                &self.google_request_config.retry,
+                |error: &HomeSpeakError| retry_decision(error),
+                |remaining| async move {
+                    let request = google_client.synthesize(
+                        google_tts::TextInput::with_text(text.to_owned()),
+                        google_voice.clone(),
+                        google_tts::AudioConfig::default_with_encoding(
+                            google_tts::AudioEncoding::Mp3,
+                        ),
+                    );
+                    tokio::time::timeout(request_timeout.min(remaining), request)
+                        .await
+                        .map_err(|_| HomeSpeakError::TtsRequestTimeout)?
+                        .map_err(HomeSpeakError::google_tts)
+                },
+            )
+            .await?;
+            let data = data.as_byte_stream().map_err(HomeSpeakError::google_tts)?;
+            self.audio_cache.set(&file_key, data).await?
         };
-
-        self.audio_service.play(playable)?;
-        Ok(())
+        Ok(playable)
     }
 
     async fn say_azure_with_voice(
-        &mut self,
+        &self,
         text: &str,
         voice: &azure_tts::VoiceSettings,
         style: AzureVoiceStyle,
+        slot: &PlaybackSlot,
     ) -> Result<()> {
         info!("Using {:?} style", &style);
-        let mut segments = vec![
-            azure_tts::VoiceSegment::silence(
-                azure_tts::SilenceAttributeType::Sentenceboundary,
-                "50ms".to_owned(),
-            ),
-            azure_tts::VoiceSegment::silence(
-                azure_tts::SilenceAttributeType::Tailing,
-                "25ms".to_owned(),
-            ),
-            azure_tts::VoiceSegment::silence(
-                azure_tts::SilenceAttributeType::Leading,
-                "25ms".to_owned(),
-            ),
-        ];
-        let contents = match style {
-            AzureVoiceStyle::Plain => azure_tts::VoiceSegment::plain(text),
-            AzureVoiceStyle::Angry => {
-                azure_tts::VoiceSegment::with_expression(text, azure_tts::Style::Angry)
-            }
-            AzureVoiceStyle::Sad => {
-                azure_tts::VoiceSegment::with_expression(text, azure_tts::Style::Sad)
-            }
-            AzureVoiceStyle::Cheerful => {
-                azure_tts::VoiceSegment::with_expression(text, azure_tts::Style::Cheerful)
-            }
-        };
-        segments.push(contents);
+        let chunks = split_into_chunks(text, self.text_chunking.max_chunk_length);
+        let mut sounds = futures::stream::iter(chunks)
+            .map(|chunk| async move { self.synthesize_azure(&chunk, voice, style).await })
+            .buffered(self.text_chunking.max_concurrent_chunks.max(1));
+
+        while let Some(sound) = sounds.next().await {
+            slot.play(sound?)?;
+        }
+        Ok(())
+    }
 
+    async fn synthesize_azure(
+        &self,
+        text: &str,
+        voice: &azure_tts::VoiceSettings,
+        style: AzureVoiceStyle,
+    ) -> Result<Box<dyn Playable>> {
         let file_key = hash_azure_tts(text, voice, self.azure_audio_format, style);
-        let sound: Box<dyn Playable> = if let Some(file) = self.audio_cache.get(&file_key) {
+        let sound: Box<dyn Playable> = if let Some(file) = self.audio_cache.get(&file_key).await {
             info!("Using cached value with key {}", file_key);
             file
         } else {
             info!("Writing new file with key {}", file_key);
-            let data = self
-                .azure_speech_client
-                .synthesize_segments(segments, voice, self.azure_audio_format)
-                .await?;
-            self.audio_cache.set(&file_key, data.clone())?;
-            Box::new(Cursor::new(data))
+            let azure_client = &self.azure_speech_client;
+            let audio_format = self.azure_audio_format;
+            let request_timeout = self.azure_request_config.request_timeout();
+            let data = with_retry(
+                "azure_tts",
+                &self.azure_request_config.retry,
+                |error: &HomeSpeakError| retry_decision(error),
+                |remaining| async move {
+                    let request = azure_client.synthesize_segments(
+                        azure_segments(text, style),
+                        voice,
+                        audio_format,
+                    );
+                    tokio::time::timeout(request_timeout.min(remaining), request)
+                        .await
+                        .map_err(|_| HomeSpeakError::TtsRequestTimeout)?
+                        .map_err(HomeSpeakError::azure_tts)
+                },
+            )
+            .await?;
+            self.audio_cache.set(&file_key, data).await?
         };
-
-        self.audio_service.play(sound)?;
-        Ok(())
+        Ok(sound)
     }
 
-    pub async fn say_azure(&mut self, text: &str) -> Result<()> {
-        // This cloning here is lame...
-        self.say_azure_with_voice(text, &self.azure_voice.clone(), AzureVoiceStyle::Plain)
+    pub async fn say_azure(&self, text: &str, slot: &PlaybackSlot) -> Result<()> {
+        self.say_azure_with_voice(text, &self.azure_voice, AzureVoiceStyle::Plain, slot)
             .await
     }
 
-    pub async fn say_azure_with_style(&mut self, text: &str, style: AzureVoiceStyle) -> Result<()> {
-        // This cloning here is lame...
-        self.say_azure_with_voice(text, &self.azure_voice.clone(), style)
+    pub async fn say_azure_with_style(
+        &self,
+        text: &str,
+        style: AzureVoiceStyle,
+        slot: &PlaybackSlot,
+    ) -> Result<()> {
+        self.say_azure_with_voice(text, &self.azure_voice, style, slot)
             .await
     }
 
-    pub async fn say(&mut self, text: &str, service: TtsService) -> Result<()> {
+    pub async fn say(&self, text: &str, service: TtsService, slot: &PlaybackSlot) -> Result<()> {
         match service {
-            TtsService::Azure => self.say_azure(text).await?,
-            TtsService::Google => self.say_google(text).await?,
+            TtsService::Azure => self.say_azure(text, slot).await?,
+            TtsService::Google => self.say_google(text, slot).await?,
         }
         Ok(())
     }
 
-    pub async fn sample_azure_languages(&mut self, text: &str) -> Result<()> {
-        let languages = self.azure_speech_client.list_voices().await?;
+    pub async fn sample_azure_languages(&self, text: &str) -> Result<()> {
+        let slot = self.audio_service.reserve_slot();
+        let languages = tokio::time::timeout(
+            self.azure_request_config.request_timeout(),
+            self.azure_speech_client.list_voices(),
+        )
+        .await
+        .map_err(|_| HomeSpeakError::TtsRequestTimeout)?
+        .map_err(HomeSpeakError::azure_tts)?;
         for language in languages {
             if language.locale == "en-US" {
                 info!(
@@ -229,7 +288,7 @@ impl SpeechService {
                 );
                 let message = format!("Hey, my name is {} and {}", language.display_name, text);
                 let voice_settings = language.to_voice_settings();
-                self.say_azure_with_voice(&message, &voice_settings, AzureVoiceStyle::Plain)
+                self.say_azure_with_voice(&message, &voice_settings, AzureVoiceStyle::Plain, &slot)
                     .await?;
             }
         }
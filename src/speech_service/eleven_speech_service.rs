@@ -1,17 +1,22 @@
 use anyhow::Context;
 use anyhow::Result;
-use secrecy::{ExposeSecret, Secret};
+use futures::StreamExt;
+use secrecy::ExposeSecret;
 use sha2::{Digest, Sha256};
-use std::io::Cursor;
 use tracing::*;
 
 use crate::audio_cache::AudioCache;
+use crate::configuration::{TextChunkingConfig, TtsServiceConfig};
 use crate::eleven_labs_client;
+use crate::eleven_labs_client::TtsStream;
 use crate::eleven_labs_client::VoiceSettings;
 use crate::eleven_labs_client::DEFAULT_MODEL;
-use crate::speech_service::Playable;
+use crate::text_chunking::split_into_chunks;
 
-use super::AudioService;
+use super::{
+    streaming_playable::{streaming_playable, StreamingPlayableWriter},
+    AudioService, Playable, PlaybackSlot,
+};
 
 // Used to invalidate old cache
 const ELEVEN_LABS_FORMAT_VERSION: u32 = 6;
@@ -41,18 +46,23 @@ pub struct ElevenSpeechService {
     voice_name_to_voice_id_table: std::collections::HashMap<String, String>,
     audio_cache: AudioCache,
     eleven_labs_default_voice_id: String,
+    text_chunking: TextChunkingConfig,
     audio_service: AudioService,
 }
 
 impl ElevenSpeechService {
     pub async fn new(
-        eleven_labs_api_key: Secret<String>,
+        tts_service_config: &TtsServiceConfig,
         audio_cache: AudioCache,
         audio_service: AudioService,
     ) -> Result<Self> {
-        let eleven_labs_client = eleven_labs_client::ElevenLabsTtsClient::new(
-            eleven_labs_api_key.expose_secret().to_owned(),
-        );
+        let eleven_labs_client = eleven_labs_client::ElevenLabsTtsClient::with_config(
+            tts_service_config
+                .eleven_labs_api_key
+                .expose_secret()
+                .to_owned(),
+            &tts_service_config.eleven_labs,
+        )?;
 
         let voices = eleven_labs_client.voices().await?;
         let voice_name_to_voice_id_table = voices.name_to_id_table();
@@ -64,46 +74,188 @@ impl ElevenSpeechService {
             voice_name_to_voice_id_table,
             audio_cache,
             eleven_labs_default_voice_id: DEFAULT_ELEVEN_LABS_VOICE_ID.to_owned(),
+            text_chunking: tts_service_config.text_chunking.clone(),
             audio_service,
         })
     }
 
-    pub async fn say_eleven_with_default_voice(&self, text: &str) -> Result<()> {
-        self.say_eleven_with_voice_id(text, &self.eleven_labs_default_voice_id.clone())
+    pub async fn say_eleven_with_default_voice(
+        &self,
+        text: &str,
+        slot: &PlaybackSlot,
+    ) -> Result<()> {
+        self.say_eleven_with_voice_id(text, &self.eleven_labs_default_voice_id, slot)
             .await?;
         Ok(())
     }
 
-    pub async fn say_eleven(&self, text: &str, voice_name: &str) -> Result<()> {
+    pub async fn say_eleven(
+        &self,
+        text: &str,
+        voice_name: &str,
+        slot: &PlaybackSlot,
+    ) -> Result<()> {
         let voice_id = self
             .voice_name_to_voice_id_table
             .get(voice_name)
             .context("Unknown voice")?
             .clone();
         info!("Using voice id {} for voice {}", voice_id, voice_name);
-        self.say_eleven_with_voice_id(text, &voice_id).await?;
+        self.say_eleven_with_voice_id(text, &voice_id, slot).await?;
         Ok(())
     }
 
-    pub async fn say_eleven_with_voice_id(&self, text: &str, voice_id: &str) -> Result<()> {
+    pub async fn say_eleven_with_voice_id(
+        &self,
+        text: &str,
+        voice_id: &str,
+        slot: &PlaybackSlot,
+    ) -> Result<()> {
+        let chunks = split_into_chunks(text, self.text_chunking.max_chunk_length);
+        let mut sounds = futures::stream::iter(chunks)
+            .map(|chunk| async move { self.synthesize(&chunk, voice_id).await })
+            .buffered(self.text_chunking.max_concurrent_chunks.max(1));
+
+        while let Some(sound) = sounds.next().await {
+            match sound? {
+                ElevenSound::Cached(sound) => slot.play(sound)?,
+                ElevenSound::Streaming(sound) => slot.play_streaming(sound)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves as soon as the audio can start playing
+    ///
+    /// New audio keeps streaming in a background task which writes it
+    /// into the cache once the stream completes.
+    /// Streamed audio can't be measured before it plays so the first playback
+    /// isn't loudness normalized. Later requests use the measured cache entry.
+    async fn synthesize(&self, text: &str, voice_id: &str) -> Result<ElevenSound> {
         let voice_settings = VoiceSettings::default();
         let voice_model = DEFAULT_MODEL;
 
         let file_key = hash_eleven_labs_tts(text, voice_id, &voice_settings, voice_model);
-        let sound: Box<dyn Playable> = if let Some(file) = self.audio_cache.get(&file_key) {
+        if let Some(file) = self.audio_cache.get(&file_key).await {
             info!("Using cached value with key {}", file_key);
-            file
-        } else {
-            info!("Writing new file with key {}", file_key);
-            let data = self
-                .eleven_labs_client
-                .tts(text, voice_id, Some(voice_settings), voice_model)
-                .await?;
-            let sound: Box<dyn Playable> = Box::new(Cursor::new(data.to_vec()));
-            self.audio_cache.set(&file_key, data.to_vec())?;
-            sound
+            return Ok(ElevenSound::Cached(file));
+        }
+
+        info!("Streaming new file with key {}", file_key);
+        let stream = self
+            .eleven_labs_client
+            .tts_stream(text, voice_id, Some(voice_settings), voice_model)
+            .await?;
+
+        let (writer, sound) = streaming_playable();
+        let audio_cache = self.audio_cache.clone();
+        let audio_service = self.audio_service.clone();
+        tokio::spawn(async move {
+            match pump_stream(stream, writer, &audio_cache, &file_key).await {
+                Ok(data) => {
+                    if let Err(error) = audio_service.publish_audio_data(&data) {
+                        error!("Failed to publish ElevenLabs audio {:?}", error);
+                    }
+                }
+                Err(error) => error!("Failed to stream ElevenLabs audio {:?}", error),
+            }
+        });
+        Ok(ElevenSound::Streaming(Box::new(sound)))
+    }
+}
+
+enum ElevenSound {
+    Cached(Box<dyn Playable>),
+    Streaming(Box<dyn Playable>),
+}
+
+/// Copy the stream into the writer and the cache and return the received audio
+///
+/// Dropping the writer on error marks the stream as failed.
+/// Incomplete audio is never cached.
+async fn pump_stream(
+    mut stream: TtsStream,
+    writer: StreamingPlayableWriter,
+    audio_cache: &AudioCache,
+    file_key: &str,
+) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    while let Some(chunk) = stream.next_chunk().await? {
+        writer.push(&chunk);
+        data.extend_from_slice(&chunk);
+    }
+    writer.finish();
+
+    info!("Writing new file with key {}", file_key);
+    // the next request for this text will use the normalized cached version
+    audio_cache.set(file_key, data.clone()).await?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        configuration::{ElevenLabsConfig, SilenceTrimmingConfig},
+        eleven_labs_client::ElevenLabsTtsClient,
+        mock_server::{MockResponse, MockServer},
+    };
+    use std::io::Read;
+
+    async fn start_stream(response: MockResponse) -> TtsStream {
+        let server = MockServer::start(vec![response]);
+        let config = ElevenLabsConfig {
+            base_url: server.url().to_owned(),
+            ..Default::default()
         };
-        self.audio_service.play(sound)?;
-        Ok(())
+        ElevenLabsTtsClient::with_config("key".to_owned(), &config)
+            .unwrap()
+            .tts_stream("hello", "voice", None, DEFAULT_MODEL)
+            .await
+            .unwrap()
+    }
+
+    fn audio_cache(dir: &tempfile::TempDir) -> AudioCache {
+        AudioCache::new(
+            dir.path().to_str().unwrap().to_owned(),
+            SilenceTrimmingConfig::default(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn complete_stream_is_played_and_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let audio_cache = audio_cache(&dir);
+        let stream = start_stream(MockResponse::new(200, b"audio data")).await;
+        let (writer, mut sound) = streaming_playable();
+
+        let data = pump_stream(stream, writer, &audio_cache, "key")
+            .await
+            .unwrap();
+
+        assert_eq!(data, b"audio data");
+        let mut played = vec![];
+        sound.read_to_end(&mut played).unwrap();
+        assert_eq!(played, b"audio data");
+        let mut cached = audio_cache.get("key").await.unwrap();
+        assert_eq!(cached.as_bytes().unwrap(), b"audio data");
+    }
+
+    #[tokio::test]
+    async fn broken_stream_fails_playback_and_is_not_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let audio_cache = audio_cache(&dir);
+        let stream = start_stream(MockResponse::new(200, b"audio data").truncate_at(5)).await;
+        let (writer, mut sound) = streaming_playable();
+
+        assert!(pump_stream(stream, writer, &audio_cache, "key")
+            .await
+            .is_err());
+
+        let mut played = vec![];
+        let error = sound.read_to_end(&mut played).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(audio_cache.get("key").await.is_none());
     }
 }
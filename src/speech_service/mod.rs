@@ -3,11 +3,22 @@ mod audio_repository;
 mod audio_service;
 mod azure_gcp_speech_service;
 mod eleven_speech_service;
+mod pre_roll;
+mod sequence;
+mod shuffle_bag;
+mod sound_library;
+mod streaming_playable;
 
 pub use self::{
-    audio_player::Playable,
+    audio_player::{Deadline, MeasuredPlayable, Playable},
     audio_repository::AudioRepository,
-    audio_service::{AudioMessage, AudioService},
+    audio_service::{AudioMessage, AudioService, PlaybackSlot, StagedSounds},
     azure_gcp_speech_service::{AzureVoiceStyle, SpeechService, TtsService},
     eleven_speech_service::{ElevenSpeechService, DEFAULT_ELEVEN_LABS_VOICE_ID},
+    pre_roll::PreRoll,
+    sequence::{SequencePlayer, SequenceStep, SpeechProvider},
+    sound_library::{SoundEntry, SoundLibrary},
+    streaming_playable::{streaming_playable, StreamingPlayable, StreamingPlayableWriter},
 };
+
+pub(crate) use self::pre_roll::is_route_prefix;
@@ -0,0 +1,79 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tracing::*;
+
+use super::{AudioRepository, PlaybackSlot};
+use crate::configuration::PreRollConfig;
+
+/// Plays an earcon before speech so that people know to pay attention
+#[derive(Debug, Clone)]
+pub struct PreRoll {
+    config: PreRollConfig,
+    base_topic: String,
+    audio_repository: AudioRepository,
+    last_speech: Arc<Mutex<Option<Instant>>>,
+}
+
+impl PreRoll {
+    pub fn new(config: PreRollConfig, base_topic: &str, audio_repository: AudioRepository) -> Self {
+        Self {
+            config,
+            base_topic: base_topic.to_owned(),
+            audio_repository,
+            last_speech: Arc::default(),
+        }
+    }
+
+    /// Should be called for every speech request even if it doesn't want a pre-roll
+    /// so that the cooldown covers back to back messages
+    pub fn play_before_speech(&self, topic: &str, slot: &PlaybackSlot) {
+        let now = Instant::now();
+        let previous_speech = self.last_speech.lock().unwrap().replace(now);
+        if previous_speech
+            .map(|previous| now.duration_since(previous) < self.config.cooldown())
+            .unwrap_or(false)
+        {
+            return;
+        }
+
+        let Some(sound) = self.sound_for_topic(topic) else {
+            return;
+        };
+        let result = self
+            .audio_repository
+            .open_file(sound)
+            .and_then(|sound| slot.play(sound));
+        if let Err(e) = result {
+            warn!("Failed to play pre-roll {:?} {:?}", sound, e);
+        }
+    }
+
+    fn sound_for_topic(&self, topic: &str) -> Option<&str> {
+        let route = topic
+            .strip_prefix(&self.base_topic)
+            .unwrap_or(topic)
+            .trim_start_matches('/');
+        let route_override = self
+            .config
+            .routes
+            .iter()
+            .filter(|(prefix, _)| is_route_prefix(prefix, route))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, sound)| sound.as_str());
+        match route_override {
+            Some("") => None,
+            Some(sound) => Some(sound),
+            None => self.config.sound.as_deref(),
+        }
+    }
+}
+
+/// Matches whole segments so that `say/eleven` covers `say/eleven/voice/Freya`
+pub(crate) fn is_route_prefix(prefix: &str, route: &str) -> bool {
+    route
+        .strip_prefix(prefix)
+        .map(|rest| rest.is_empty() || rest.starts_with('/'))
+        .unwrap_or(false)
+}
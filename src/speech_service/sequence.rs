@@ -0,0 +1,188 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{io::Cursor, sync::Arc, time::Duration};
+use tracing::*;
+
+use crate::audio_processing::encode_wav;
+
+use super::{
+    AudioRepository, AudioService, AzureVoiceStyle, ElevenSpeechService, MeasuredPlayable,
+    Playable, PlaybackSlot, SpeechService, TtsService,
+};
+
+const SILENCE_SAMPLE_RATE: u32 = 16000;
+/// Silence is generated in memory so requests have to be bounded
+const MAX_SILENCE: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SpeechProvider {
+    #[default]
+    Azure,
+    Google,
+    Eleven,
+}
+
+/// ```yaml
+/// - type: play_file
+///   file: chimes/ding.mp3
+/// - type: say
+///   text: Someone is at the door
+///   provider: eleven
+/// - type: silence
+///   duration_ms: 500
+/// ```
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SequenceStep {
+    PlayFile {
+        file: String,
+    },
+    Say {
+        text: String,
+        #[serde(default)]
+        provider: SpeechProvider,
+        /// Only used by Azure
+        #[serde(default)]
+        style: AzureVoiceStyle,
+        /// ElevenLabs voice name
+        #[serde(default)]
+        voice: Option<String>,
+    },
+    /// At most 10 seconds
+    Silence {
+        duration_ms: u64,
+    },
+}
+
+/// Plays a list of steps as one uninterrupted announcement
+#[derive(Debug, Clone)]
+pub struct SequencePlayer {
+    speech_service: Arc<SpeechService>,
+    eleven_speech_service: ElevenSpeechService,
+    audio_repository: AudioRepository,
+    audio_service: AudioService,
+}
+
+impl SequencePlayer {
+    pub fn new(
+        speech_service: Arc<SpeechService>,
+        eleven_speech_service: ElevenSpeechService,
+        audio_repository: AudioRepository,
+        audio_service: AudioService,
+    ) -> Self {
+        Self {
+            speech_service,
+            eleven_speech_service,
+            audio_repository,
+            audio_service,
+        }
+    }
+
+    /// Every step is synthesized before anything is queued
+    ///
+    /// Nothing plays if any of the steps fail.
+    pub async fn play(&self, steps: &[SequenceStep]) -> Result<()> {
+        let sounds = self.stage(steps).await?;
+        info!("Playing sequence of {} sounds", sounds.len());
+        let slot = self.audio_service.reserve_slot();
+        for sound in sounds {
+            // already published while staging
+            slot.play_streaming(sound)?;
+        }
+        Ok(())
+    }
+
+    /// Same as [`SequencePlayer::play`] but into a slot that was reserved up front
+    ///
+    /// A single step streams straight into the slot.
+    pub async fn play_into(&self, steps: &[SequenceStep], slot: &PlaybackSlot) -> Result<()> {
+        if let [step] = steps {
+            return self.play_step(step, slot).await;
+        }
+        let sounds = self.stage(steps).await?;
+        info!("Playing sequence of {} sounds", sounds.len());
+        for sound in sounds {
+            slot.play_streaming(sound)?;
+        }
+        Ok(())
+    }
+
+    async fn stage(&self, steps: &[SequenceStep]) -> Result<Vec<Box<dyn Playable>>> {
+        let (staging_slot, staged_sounds) = self.audio_service.staging_slot();
+        for step in steps {
+            self.play_step(step, &staging_slot).await?;
+        }
+        drop(staging_slot);
+        let sounds = staged_sounds.into_sounds().await;
+
+        // streamed speech has to finish downloading before we can commit to playing it
+        tokio::task::spawn_blocking(move || {
+            sounds
+                .into_iter()
+                .map(|mut sound| {
+                    let loudness = sound.loudness();
+                    let data = sound.as_bytes()?;
+                    Ok(MeasuredPlayable::boxed(Cursor::new(data), loudness))
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .await?
+    }
+
+    /// Play a single step into a slot
+    pub async fn play_step(&self, step: &SequenceStep, slot: &PlaybackSlot) -> Result<()> {
+        match step {
+            SequenceStep::PlayFile { file } => {
+                slot.play(self.audio_repository.open_file(file)?)?;
+            }
+            SequenceStep::Say {
+                text,
+                provider,
+                style,
+                voice,
+            } => match provider {
+                SpeechProvider::Azure => {
+                    self.speech_service
+                        .say_azure_with_style(text, *style, slot)
+                        .await?
+                }
+                SpeechProvider::Google => {
+                    self.speech_service
+                        .say(text, TtsService::Google, slot)
+                        .await?
+                }
+                SpeechProvider::Eleven => match voice {
+                    Some(voice) => {
+                        self.eleven_speech_service
+                            .say_eleven(text, voice, slot)
+                            .await?
+                    }
+                    None => {
+                        self.eleven_speech_service
+                            .say_eleven_with_default_voice(text, slot)
+                            .await?
+                    }
+                },
+            },
+            SequenceStep::Silence { duration_ms } => {
+                let duration = Duration::from_millis(*duration_ms);
+                if duration > MAX_SILENCE {
+                    anyhow::bail!(
+                        "Silence of {:?} is longer than the maximum of {:?}",
+                        duration,
+                        MAX_SILENCE
+                    );
+                }
+                // silence isn't worth publishing
+                slot.play_streaming(Box::new(silence(duration)))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn silence(duration: Duration) -> Cursor<Vec<u8>> {
+    let sample_count = (duration.as_secs_f64() * SILENCE_SAMPLE_RATE as f64) as usize;
+    Cursor::new(encode_wav(&vec![0; sample_count], 1, SILENCE_SAMPLE_RATE))
+}
@@ -0,0 +1,143 @@
+use rand::{seq::SliceRandom, Rng};
+use std::{collections::HashSet, path::PathBuf};
+
+/// Picks every candidate once before any of them repeats
+///
+/// Candidates are passed in on every draw so that sounds added to or removed
+/// from the library are picked up without resetting the bag.
+#[derive(Debug, Default)]
+pub struct ShuffleBag {
+    played: HashSet<PathBuf>,
+    last: Option<PathBuf>,
+}
+
+impl ShuffleBag {
+    /// Weights only change the order in which sounds come out of the bag
+    pub fn draw(&mut self, candidates: &[(PathBuf, f64)], rng: &mut impl Rng) -> Option<PathBuf> {
+        let mut eligible = self.eligible(candidates);
+        if eligible.is_empty() {
+            self.played.clear();
+            eligible = self.eligible(candidates);
+            // don't start the new round with the sound that ended the last one
+            if eligible.len() > 1 {
+                eligible.retain(|(path, _)| Some(path) != self.last.as_ref());
+            }
+        }
+        let (path, _) = match eligible.choose_weighted(rng, |(_, weight)| *weight) {
+            Ok(choice) => *choice,
+            // all weights are zero
+            Err(_) => *eligible.choose(rng)?,
+        };
+        let path = path.clone();
+        self.played.insert(path.clone());
+        self.last = Some(path.clone());
+        Some(path)
+    }
+
+    fn eligible<'a>(&self, candidates: &'a [(PathBuf, f64)]) -> Vec<&'a (PathBuf, f64)> {
+        candidates
+            .iter()
+            .filter(|(path, _)| !self.played.contains(path))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::path::Path;
+
+    fn candidates(weights: &[(&str, f64)]) -> Vec<(PathBuf, f64)> {
+        weights
+            .iter()
+            .map(|(name, weight)| (PathBuf::from(name), *weight))
+            .collect()
+    }
+
+    fn draw_names(
+        bag: &mut ShuffleBag,
+        candidates: &[(PathBuf, f64)],
+        count: usize,
+        rng: &mut StdRng,
+    ) -> Vec<String> {
+        (0..count)
+            .map(|_| {
+                let path = bag.draw(candidates, rng).unwrap();
+                path.to_string_lossy().into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nothing_repeats_until_the_bag_is_empty() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let candidates = candidates(&[("a", 1.0), ("b", 2.0), ("c", 1.0), ("d", 0.5), ("e", 1.0)]);
+        let mut bag = ShuffleBag::default();
+        let mut previous_last = None;
+        for _ in 0..20 {
+            let mut round = draw_names(&mut bag, &candidates, candidates.len(), &mut rng);
+            assert_ne!(previous_last.as_ref(), round.first());
+            previous_last = round.last().cloned();
+            round.sort();
+            assert_eq!(round, ["a", "b", "c", "d", "e"]);
+        }
+    }
+
+    #[test]
+    fn heavier_sounds_come_first_more_often() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let candidates = candidates(&[("heavy", 9.0), ("light", 1.0)]);
+        let heavy_first = (0..1000)
+            .filter(|_| {
+                let first = ShuffleBag::default().draw(&candidates, &mut rng).unwrap();
+                first == Path::new("heavy")
+            })
+            .count();
+        assert!((850..=950).contains(&heavy_first), "{}", heavy_first);
+    }
+
+    #[test]
+    fn zero_weights_come_last() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let candidates = candidates(&[("never first", 0.0), ("a", 1.0), ("b", 1.0)]);
+        let mut bag = ShuffleBag::default();
+        for _ in 0..10 {
+            let round = draw_names(&mut bag, &candidates, 3, &mut rng);
+            assert_eq!(round[2], "never first");
+        }
+
+        let all_zero = self::candidates(&[("a", 0.0), ("b", 0.0), ("c", 0.0)]);
+        let mut round = draw_names(&mut ShuffleBag::default(), &all_zero, 3, &mut rng);
+        round.sort();
+        assert_eq!(round, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn empty_directory_draws_nothing() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut bag = ShuffleBag::default();
+        assert_eq!(bag.draw(&[], &mut rng), None);
+
+        let single = candidates(&[("only", 1.0)]);
+        assert_eq!(draw_names(&mut bag, &single, 3, &mut rng), ["only"; 3]);
+        assert_eq!(bag.draw(&[], &mut rng), None);
+    }
+
+    #[test]
+    fn library_changes_are_picked_up() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut bag = ShuffleBag::default();
+        let first = bag
+            .draw(&candidates(&[("a", 1.0), ("b", 1.0)]), &mut rng)
+            .unwrap();
+        let remaining = candidates(&[("a", 1.0), ("b", 1.0), ("c", 1.0)]);
+        let mut round = draw_names(&mut bag, &remaining, 2, &mut rng);
+        round.push(first.to_string_lossy().into_owned());
+        round.sort();
+        assert_eq!(round, ["a", "b", "c"]);
+
+        let removed = candidates(&[("c", 1.0)]);
+        assert_eq!(bag.draw(&removed, &mut rng), Some(PathBuf::from("c")));
+    }
+}
@@ -0,0 +1,304 @@
+use crate::loudness::{Loudness, LoudnessMeter};
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tracing::*;
+
+const SUPPORTED_EXTENSIONS: [&str; 4] = ["mp3", "wav", "ogg", "flac"];
+const SIDECAR_EXTENSION: &str = "yaml";
+const DEFAULT_WEIGHT: f64 = 1.0;
+
+/// Optional metadata stored next to a sound as `<file name>.yaml`
+///
+/// ```yaml
+/// name: doorbell
+/// tags: [chime, door]
+/// # how likely the sound is to come up early in random playback
+/// weight: 2.0
+/// ```
+#[derive(Deserialize, Debug, Clone, Default)]
+struct SoundMetadata {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    weight: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SoundEntry {
+    /// Name from the metadata file or the relative path without extension
+    pub name: String,
+    /// Path relative to the repository root
+    pub path: PathBuf,
+    pub format: String,
+    pub duration_ms: Option<u64>,
+    pub tags: Vec<String>,
+    pub weight: f64,
+    pub loudness: Option<Loudness>,
+}
+
+impl SoundEntry {
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|sound_tag| sound_tag == tag)
+    }
+}
+
+/// Duration and loudness of a sound if it could be decoded
+type Analysis = Option<(Duration, Option<Loudness>)>;
+
+/// Tells whether a file changed without reading it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl FileStamp {
+    fn of(entry: &walkdir::DirEntry) -> Option<Self> {
+        let metadata = entry.metadata().ok()?;
+        Some(Self {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SoundLibrary {
+    sounds: Vec<SoundEntry>,
+    /// Decoding is slow so results are kept for files that didn't change
+    analyses: HashMap<PathBuf, (FileStamp, Analysis)>,
+}
+
+impl SoundLibrary {
+    /// Index all supported sounds under root
+    ///
+    /// Symlinks are not followed so the index can't reach outside of root.
+    pub fn scan(root: &Path) -> Self {
+        Self::default().rescan(root)
+    }
+
+    /// Index root again while only decoding sounds that are new or changed
+    ///
+    /// Metadata files are cheap to read so they are always read again.
+    pub fn rescan(&self, root: &Path) -> Self {
+        let mut analyses = HashMap::new();
+        let mut decoded = 0;
+        let mut sounds: Vec<_> = walkdir::WalkDir::new(root)
+            .into_iter()
+            // ignore errors
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file() && is_supported_format(entry.path()))
+            .filter_map(|entry| {
+                let relative_path = entry.path().strip_prefix(root).ok()?.to_owned();
+                let stamp = FileStamp::of(&entry);
+                let analysis = match (stamp, self.analyses.get(&relative_path)) {
+                    (Some(stamp), Some((known_stamp, analysis))) if stamp == *known_stamp => {
+                        *analysis
+                    }
+                    _ => {
+                        decoded += 1;
+                        analyze(entry.path())
+                    }
+                };
+                if let Some(stamp) = stamp {
+                    analyses.insert(relative_path.clone(), (stamp, analysis));
+                }
+                index_sound(entry.path(), relative_path, analysis)
+            })
+            .collect();
+        sounds.sort_by(|a, b| a.path.cmp(&b.path));
+        info!(
+            "Indexed {} sounds in {:?} decoding {} of them",
+            sounds.len(),
+            root,
+            decoded
+        );
+        Self { sounds, analyses }
+    }
+
+    pub fn sounds(&self) -> &[SoundEntry] {
+        &self.sounds
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&SoundEntry> {
+        self.sounds.iter().find(|sound| sound.name == name)
+    }
+
+    /// Sounds directly inside of directory
+    pub fn in_directory<'a>(&'a self, directory: &'a Path) -> impl Iterator<Item = &'a SoundEntry> {
+        self.sounds
+            .iter()
+            .filter(move |sound| sound.path.parent() == Some(directory))
+    }
+
+    pub fn by_path(&self, path: &Path) -> Option<&SoundEntry> {
+        self.sounds.iter().find(|sound| sound.path == path)
+    }
+
+    pub fn with_tag<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a SoundEntry> {
+        self.sounds.iter().filter(move |sound| sound.has_tag(tag))
+    }
+}
+
+pub fn is_supported_format(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| SUPPORTED_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Path of the metadata file describing sound
+pub fn sidecar_path(sound_path: &Path) -> PathBuf {
+    let mut sidecar_path = sound_path.as_os_str().to_owned();
+    sidecar_path.push(".");
+    sidecar_path.push(SIDECAR_EXTENSION);
+    PathBuf::from(sidecar_path)
+}
+
+fn index_sound(path: &Path, relative_path: PathBuf, analysis: Analysis) -> Option<SoundEntry> {
+    let format = path.extension()?.to_str()?.to_lowercase();
+    let metadata = read_metadata(path);
+    let name = metadata.name.unwrap_or_else(|| {
+        relative_path
+            .with_extension("")
+            .to_string_lossy()
+            .into_owned()
+    });
+    let weight = match metadata.weight {
+        Some(weight) if weight.is_finite() && weight >= 0.0 => weight,
+        Some(weight) => {
+            warn!("Ignoring invalid weight {} for {:?}", weight, path);
+            DEFAULT_WEIGHT
+        }
+        None => DEFAULT_WEIGHT,
+    };
+    if analysis.is_none() {
+        warn!("Failed to decode {:?}", path);
+    }
+    let duration_ms = analysis.map(|(duration, _)| duration.as_millis() as u64);
+    let loudness = analysis.and_then(|(_, loudness)| loudness);
+    Some(SoundEntry {
+        name,
+        path: relative_path,
+        format,
+        duration_ms,
+        tags: metadata.tags,
+        weight,
+        loudness,
+    })
+}
+
+fn read_metadata(sound_path: &Path) -> SoundMetadata {
+    let sidecar_path = sidecar_path(sound_path);
+    let Ok(file) = File::open(&sidecar_path) else {
+        return SoundMetadata::default();
+    };
+    serde_yaml::from_reader(BufReader::new(file)).unwrap_or_else(|error| {
+        error!(
+            "Failed to parse sound metadata {:?} {}",
+            sidecar_path, error
+        );
+        SoundMetadata::default()
+    })
+}
+
+/// Decodes the whole sound once to get both its duration and loudness
+fn analyze(path: &Path) -> Analysis {
+    let file = File::open(path).ok()?;
+    let decoder = rodio::Decoder::new(BufReader::new(file)).ok()?;
+    let samples_per_second = decoder.sample_rate() as u64 * decoder.channels() as u64;
+    if samples_per_second == 0 {
+        return None;
+    }
+    let known_duration = decoder.total_duration();
+    let mut meter = LoudnessMeter::new(decoder.channels(), decoder.sample_rate());
+    let mut samples = 0_u64;
+    for sample in decoder.convert_samples::<f32>() {
+        meter.push(sample);
+        samples += 1;
+    }
+    // Not all decoders know the duration up front
+    let duration = known_duration
+        .unwrap_or_else(|| Duration::from_secs_f64(samples as f64 / samples_per_second as f64));
+    Some((duration, meter.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_processing::encode_wav;
+    use std::fs;
+
+    fn write_tone(path: &Path, seconds: usize) {
+        let samples: Vec<i16> = (0..8000 * seconds)
+            .map(|i| if i % 2 == 0 { 8000 } else { -8000 })
+            .collect();
+        fs::write(path, encode_wav(&samples, 1, 8000)).unwrap();
+    }
+
+    /// Replaces the contents while keeping size and modification time
+    fn corrupt_keeping_stamp(path: &Path) {
+        let metadata = fs::metadata(path).unwrap();
+        fs::write(path, vec![0; metadata.len() as usize]).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(metadata.modified().unwrap())
+            .unwrap();
+    }
+
+    fn duration_ms(library: &SoundLibrary, path: &str) -> Option<u64> {
+        library.by_path(Path::new(path)).unwrap().duration_ms
+    }
+
+    #[test]
+    fn rescan_only_decodes_changed_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write_tone(&dir.path().join("kept.wav"), 1);
+        write_tone(&dir.path().join("changed.wav"), 1);
+        let library = SoundLibrary::scan(dir.path());
+        assert_eq!(duration_ms(&library, "kept.wav"), Some(1000));
+
+        // a decode would fail on the corrupted file so the old result must be reused
+        corrupt_keeping_stamp(&dir.path().join("kept.wav"));
+        write_tone(&dir.path().join("changed.wav"), 2);
+        write_tone(&dir.path().join("added.wav"), 3);
+        let library = library.rescan(dir.path());
+        assert_eq!(duration_ms(&library, "kept.wav"), Some(1000));
+        assert_eq!(duration_ms(&library, "changed.wav"), Some(2000));
+        assert_eq!(duration_ms(&library, "added.wav"), Some(3000));
+    }
+
+    #[test]
+    fn removed_files_leave_the_index() {
+        let dir = tempfile::tempdir().unwrap();
+        write_tone(&dir.path().join("gone.wav"), 1);
+        let library = SoundLibrary::scan(dir.path());
+        fs::remove_file(dir.path().join("gone.wav")).unwrap();
+        let library = library.rescan(dir.path());
+        assert!(library.sounds().is_empty());
+        assert!(library.analyses.is_empty());
+    }
+
+    #[test]
+    fn metadata_is_read_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bell.wav");
+        write_tone(&path, 1);
+        let library = SoundLibrary::scan(dir.path());
+        assert_eq!(library.sounds()[0].name, "bell");
+        fs::write(sidecar_path(&path), "name: doorbell\ntags: [chime]\n").unwrap();
+        let library = library.rescan(dir.path());
+        assert!(library.by_name("doorbell").unwrap().has_tag("chime"));
+    }
+}
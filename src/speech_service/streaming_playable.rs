@@ -0,0 +1,244 @@
+use crate::error::Result;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::{Arc, Condvar, Mutex};
+
+use super::Playable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamState {
+    Open,
+    Finished,
+    Failed,
+}
+
+#[derive(Debug)]
+struct StreamBuffer {
+    data: Vec<u8>,
+    state: StreamState,
+}
+
+#[derive(Debug)]
+struct SharedStream {
+    buffer: Mutex<StreamBuffer>,
+    data_available: Condvar,
+}
+
+impl SharedStream {
+    fn update<F: FnOnce(&mut StreamBuffer)>(&self, action: F) {
+        let mut buffer = self.buffer.lock().unwrap();
+        action(&mut buffer);
+        self.data_available.notify_all();
+    }
+}
+
+/// Create a connected pair of writer and playable
+///
+/// The playable can be handed to the audio player before any data arrives.
+/// Reads block until the writer pushes more data or closes the stream.
+pub fn streaming_playable() -> (StreamingPlayableWriter, StreamingPlayable) {
+    let shared = Arc::new(SharedStream {
+        buffer: Mutex::new(StreamBuffer {
+            data: vec![],
+            state: StreamState::Open,
+        }),
+        data_available: Condvar::new(),
+    });
+    (
+        StreamingPlayableWriter {
+            shared: shared.clone(),
+        },
+        StreamingPlayable {
+            shared,
+            position: 0,
+        },
+    )
+}
+
+/// Writing half of a [`StreamingPlayable`]
+///
+/// Dropping the writer without calling [`StreamingPlayableWriter::finish`]
+/// marks the stream as failed so that the player doesn't wait forever.
+#[derive(Debug)]
+pub struct StreamingPlayableWriter {
+    shared: Arc<SharedStream>,
+}
+
+impl StreamingPlayableWriter {
+    pub fn push(&self, chunk: &[u8]) {
+        self.shared
+            .update(|buffer| buffer.data.extend_from_slice(chunk));
+    }
+
+    pub fn finish(self) {
+        self.shared
+            .update(|buffer| buffer.state = StreamState::Finished);
+    }
+
+    pub fn fail(self) {
+        self.shared
+            .update(|buffer| buffer.state = StreamState::Failed);
+    }
+}
+
+impl Drop for StreamingPlayableWriter {
+    fn drop(&mut self) {
+        self.shared.update(|buffer| {
+            if buffer.state == StreamState::Open {
+                buffer.state = StreamState::Failed;
+            }
+        });
+    }
+}
+
+/// Audio source that is still being downloaded
+///
+/// All received data is kept in memory so that decoders can seek freely.
+/// Seeking relative to the end blocks until the stream is complete.
+#[derive(Debug)]
+pub struct StreamingPlayable {
+    shared: Arc<SharedStream>,
+    position: u64,
+}
+
+impl Read for StreamingPlayable {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut buffer = self.shared.buffer.lock().unwrap();
+        while self.position >= buffer.data.len() as u64 && buffer.state == StreamState::Open {
+            buffer = self.shared.data_available.wait(buffer).unwrap();
+        }
+        let available = buffer.data.len() as u64;
+        if self.position < available {
+            let start = self.position as usize;
+            let count = buf.len().min(buffer.data.len() - start);
+            buf[..count].copy_from_slice(&buffer.data[start..start + count]);
+            self.position += count as u64;
+            return Ok(count);
+        }
+        match buffer.state {
+            StreamState::Failed => Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "audio stream failed",
+            )),
+            _ => Ok(0),
+        }
+    }
+}
+
+impl Seek for StreamingPlayable {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                let mut buffer = self.shared.buffer.lock().unwrap();
+                while buffer.state == StreamState::Open {
+                    buffer = self.shared.data_available.wait(buffer).unwrap();
+                }
+                (buffer.data.len() as u64).checked_add_signed(offset)
+            }
+        };
+        self.position = new_position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl Playable for StreamingPlayable {
+    /// Blocks until the whole stream has been received
+    fn as_bytes(&mut self) -> Result<Vec<u8>> {
+        let mut buffer = self.shared.buffer.lock().unwrap();
+        while buffer.state == StreamState::Open {
+            buffer = self.shared.data_available.wait(buffer).unwrap();
+        }
+        Ok(buffer.data.clone())
+    }
+
+    /// Decoded on a separate thread so that waiting for data doesn't stall playback
+    fn is_stream(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn read_blocks_until_data_is_pushed() {
+        let (writer, mut playable) = streaming_playable();
+        let reader = thread::spawn(move || {
+            let mut buf = [0; 8];
+            let count = playable.read(&mut buf).unwrap();
+            buf[..count].to_vec()
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert!(!reader.is_finished());
+        writer.push(b"abc");
+        assert_eq!(reader.join().unwrap(), b"abc");
+    }
+
+    #[test]
+    fn finished_stream_reads_to_end() {
+        let (writer, mut playable) = streaming_playable();
+        writer.push(b"hello ");
+        writer.push(b"world");
+        writer.finish();
+        let mut data = vec![];
+        playable.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"hello world");
+        assert_eq!(playable.read(&mut [0; 4]).unwrap(), 0);
+    }
+
+    #[test]
+    fn dropped_writer_fails_after_buffered_data() {
+        let (writer, mut playable) = streaming_playable();
+        writer.push(b"partial");
+        drop(writer);
+        let mut buf = [0; 16];
+        assert_eq!(playable.read(&mut buf).unwrap(), 7);
+        let error = playable.read(&mut buf).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn failed_stream_reports_unexpected_eof() {
+        let (writer, mut playable) = streaming_playable();
+        writer.fail();
+        let error = playable.read(&mut [0; 4]).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn seek_from_end_waits_for_finish() {
+        let (writer, mut playable) = streaming_playable();
+        let seeker = thread::spawn(move || playable.seek(SeekFrom::End(-2)).unwrap());
+        writer.push(b"abcd");
+        thread::sleep(Duration::from_millis(50));
+        assert!(!seeker.is_finished());
+        writer.push(b"ef");
+        writer.finish();
+        assert_eq!(seeker.join().unwrap(), 4);
+    }
+
+    #[test]
+    fn seek_before_start_is_rejected() {
+        let (_writer, mut playable) = streaming_playable();
+        let error = playable.seek(SeekFrom::Current(-1)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn as_bytes_returns_whole_stream() {
+        let (writer, mut playable) = streaming_playable();
+        let collector = thread::spawn(move || playable.as_bytes().unwrap());
+        writer.push(b"one ");
+        writer.push(b"two");
+        writer.finish();
+        assert_eq!(collector.join().unwrap(), b"one two");
+    }
+}
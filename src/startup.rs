@@ -0,0 +1,82 @@
+use crate::{
+    configuration::StartupConfig,
+    speech_service::{SequencePlayer, SequenceStep},
+    state_file::write_json_atomically,
+    template_messages::TemplateEngine,
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+use tracing::*;
+
+/// What was announced on the last boot
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct StartupState {
+    ip_addresses: Vec<String>,
+}
+
+/// Say the startup announcement in the background
+///
+/// Startup doesn't wait for the announcement so an unreachable TTS provider only gets logged.
+pub fn start_announcement(
+    config: StartupConfig,
+    template_engine: &TemplateEngine,
+    sequence_player: SequencePlayer,
+) {
+    let mut ip_addresses = template_engine.ip_addresses();
+    ip_addresses.sort();
+    let previous_ip_addresses = load_state(&config.state_path).map(|state| state.ip_addresses);
+    let ip_changed = previous_ip_addresses.as_ref() != Some(&ip_addresses);
+    if config.only_on_ip_change && !ip_changed {
+        info!("IP addresses unchanged since last boot. Skipping startup announcement");
+        return;
+    }
+
+    let message = match &config.template {
+        Some(template) => {
+            let context = serde_json::json!({
+                "ip_changed": ip_changed,
+                "previous_ip_addresses": previous_ip_addresses,
+            });
+            match template_engine.render(template, &context) {
+                Ok(message) => vec![message],
+                Err(e) => {
+                    error!("Failed to render startup template {:?}", e);
+                    template_engine.startup_message(&config.sections)
+                }
+            }
+        }
+        None => template_engine.startup_message(&config.sections),
+    };
+    // templates can decide to say nothing
+    let steps: Vec<_> = message
+        .into_iter()
+        .filter(|text| !text.trim().is_empty())
+        .map(|text| SequenceStep::Say {
+            text,
+            provider: config.provider,
+            style: config.style,
+            voice: config.voice.clone(),
+        })
+        .collect();
+    if steps.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = sequence_player.play(&steps).await {
+            error!("Failed to play startup announcement {:?}", e);
+            return;
+        }
+        // only remember addresses that were actually announced
+        if let Err(e) = write_json_atomically(&config.state_path, &StartupState { ip_addresses }) {
+            error!("Failed to save startup state {:?}", e);
+        }
+    });
+}
+
+fn load_state(path: &Path) -> Option<StartupState> {
+    let data = fs::read(path).ok()?;
+    serde_json::from_slice(&data)
+        .map_err(|e| warn!("Failed to parse startup state {:?} {:?}", path, e))
+        .ok()
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fs, path::Path};
+use tracing::*;
+
+/// Serialize to a temporary file first and rename it over the old state
+///
+/// A crash while writing leaves the previous state instead of a truncated file.
+pub fn write_json_atomically<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, serde_json::to_vec(value)?)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// None if the file doesn't exist or can't be parsed
+///
+/// Unparsable files are renamed so that the next save doesn't destroy them.
+pub fn read_json_or_move_aside<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let data = fs::read(path).ok()?;
+    match serde_json::from_slice(&data) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            let mut unreadable_path = path.as_os_str().to_owned();
+            unreadable_path.push(format!(
+                ".unreadable-{}",
+                Utc::now().format("%Y%m%dT%H%M%S")
+            ));
+            error!(
+                "Failed to parse {:?} {:?}. Moving it to {:?}",
+                path, e, unreadable_path
+            );
+            if let Err(e) = fs::rename(path, &unreadable_path) {
+                error!("Failed to move {:?} aside {:?}", path, e);
+            }
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_previous_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("state.json");
+        write_json_atomically(&path, &vec![1, 2, 3]).unwrap();
+        write_json_atomically(&path, &vec![4]).unwrap();
+
+        let state: Vec<u32> = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(state, vec![4]);
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn unreadable_state_is_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        assert_eq!(read_json_or_move_aside::<Vec<u32>>(&path), None);
+
+        fs::write(&path, b"[1, 2").unwrap();
+        assert_eq!(read_json_or_move_aside::<Vec<u32>>(&path), None);
+        assert!(!path.exists());
+        let moved: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(moved.len(), 1);
+        let moved_path = moved[0].as_ref().unwrap().path();
+        assert_eq!(fs::read(moved_path).unwrap(), b"[1, 2");
+    }
+}
@@ -1,82 +1,284 @@
 use chrono::prelude::*;
 use local_ip_address::list_afinet_netifas;
-use num_traits::FromPrimitive;
-use ordinal::Ordinal;
+use notify::{RecommendedWatcher, RecursiveMode};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::fs;
 use std::net::IpAddr;
-use std::{fmt::Write, process::Command, str};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use std::{process::Command, str};
 use tracing::*;
 
-use crate::configuration::AssistantConfig;
+use crate::configuration::{AssistantConfig, StartupSection};
+use crate::error::TemplateError;
+use crate::file_watcher::watch_debounced;
+use crate::localization::{fill, Humanizer};
+use crate::speech_service::{AzureVoiceStyle, SpeechProvider};
+use crate::templating::{Template, TemplateValue, Variables};
+
+/// ```yaml
+/// # door_open.yaml
+/// text: "The {{ context.door }} door has been open for {{ context.minutes }} minutes"
+/// provider: azure
+/// style: serious
+/// ```
+#[derive(Deserialize, Debug, Clone)]
+struct TemplateFile {
+    text: String,
+    #[serde(default)]
+    provider: Option<SpeechProvider>,
+    #[serde(default)]
+    style: Option<AzureVoiceStyle>,
+    #[serde(default)]
+    voice: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct NamedTemplate {
+    template: Template,
+    provider: Option<SpeechProvider>,
+    style: Option<AzureVoiceStyle>,
+    voice: Option<String>,
+}
+
+/// Named template rendered along with its voice defaults
+#[derive(Debug, Clone)]
+pub struct RenderedTemplate {
+    pub text: String,
+    pub provider: Option<SpeechProvider>,
+    pub style: Option<AzureVoiceStyle>,
+    pub voice: Option<String>,
+}
 
 #[derive(Debug, Clone)]
 pub struct TemplateEngine {
     assistant_config: AssistantConfig,
+    humanizer: Humanizer,
     hostname: Option<String>,
     network_interfaces: Option<Vec<(String, IpAddr)>>,
+    started_at: Instant,
+    templates_path: Option<PathBuf>,
+    templates: Arc<RwLock<HashMap<String, NamedTemplate>>>,
 }
 
 impl TemplateEngine {
-    pub fn new(assistant_config: AssistantConfig) -> Self {
+    pub fn new(assistant_config: AssistantConfig, templates_path: Option<PathBuf>) -> Self {
         let hostname = hostname();
         let network_interfaces = network_interfaces();
+        let templates = templates_path
+            .as_deref()
+            .map(load_templates)
+            .unwrap_or_default();
         Self {
+            humanizer: assistant_config.humanizer(),
             assistant_config,
             hostname,
             network_interfaces,
+            started_at: Instant::now(),
+            templates_path,
+            templates: Arc::new(RwLock::new(templates)),
         }
     }
 
-    pub fn startup_message(&self) -> Vec<String> {
-        let mut message_buffer = vec![];
-        message_buffer.push(format!(
-            "Good morning, my name is {}!",
-            self.assistant_config.name
-        ));
-        message_buffer.push(format!("It's {}. ", get_human_current_date_time()));
-        if let Some(ref network_interfaces) = self.network_interfaces {
-            if network_interfaces.is_empty() {
-                error!("No NICs found");
-                message_buffer.push(String::from(
-                    "Huh, It looks like this device has no network interfaces?",
-                ))
-            } else {
-                let mut network_message = String::new();
-                network_message.push_str("My network interfaces are ");
-                let interface_message: String = network_interfaces
-                    .iter()
-                    .filter(|(_, ip)| ip.is_ipv4() && !ip.is_loopback())
-                    .fold(String::new(), |mut output, (name, ip)| {
-                        let _ = write!(output, "{} at {}, ", name, ip);
-                        output
-                    });
-                info!("local interfaces are: {:?}", interface_message);
-                network_message.push_str(interface_message.trim_end_matches(", "));
-                network_message.push('.');
-                message_buffer.push(network_message);
-            }
-        } else {
-            error!("Failed to query local network interfaces");
-            message_buffer.push(String::from("I can't tell you how to reach me because it looks like I failed to query the local interfaces for some reason."));
+    /// Load the templates directory again
+    pub fn reload_templates(&self) {
+        if let Some(templates_path) = &self.templates_path {
+            *self.templates.write().unwrap() = load_templates(templates_path);
         }
+    }
 
-        if let Some(ref hostname) = self.hostname {
-            message_buffer.push(format!("My hostname is {}. ", hostname));
-        } else {
-            message_buffer.push(String::from(
-                "I can't detect my hostname. Maybe this platform isn't supported?",
-            ));
+    /// Keep named templates in sync with the templates directory
+    ///
+    /// Watching stops when the returned watcher is dropped.
+    /// Returns `None` if no templates directory is configured or it can't be created.
+    pub fn watch_for_changes(&self) -> anyhow::Result<Option<RecommendedWatcher>> {
+        let Some(templates_path) = &self.templates_path else {
+            return Ok(None);
+        };
+        // a read only state directory shouldn't stop the server from starting
+        if let Err(e) = fs::create_dir_all(templates_path) {
+            warn!(
+                "Not watching templates. Failed to create {:?} {:?}",
+                templates_path, e
+            );
+            return Ok(None);
         }
+        let engine = self.clone();
+        let watcher = watch_debounced(templates_path, RecursiveMode::NonRecursive, move || {
+            info!("Templates changed. Reloading");
+            engine.reload_templates();
+            async {}
+        })?;
+        Ok(Some(watcher))
+    }
+
+    /// Render a template from the templates directory
+    pub fn render_named(
+        &self,
+        name: &str,
+        context: &JsonValue,
+    ) -> Result<RenderedTemplate, TemplateError> {
+        let named = self
+            .templates
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| TemplateError::UnknownTemplate(name.to_owned()))?;
+        let now = Local::now();
+        Ok(RenderedTemplate {
+            text: named
+                .template
+                .render(&self.variables(context, now), &self.humanizer, now)?,
+            provider: named.provider,
+            style: named.style,
+            voice: named.voice,
+        })
+    }
 
+    /// Render a message template
+    ///
+    /// The caller supplied `context` is available as `{{ context.* }}`.
+    pub fn render(&self, template: &str, context: &JsonValue) -> Result<String, TemplateError> {
+        let now = Local::now();
+        Template::parse(template)?.render(&self.variables(context, now), &self.humanizer, now)
+    }
+
+    fn variables(&self, context: &JsonValue, now: DateTime<Local>) -> Variables {
+        let hostname = self
+            .hostname
+            .clone()
+            .map(JsonValue::String)
+            .unwrap_or_default();
+        let ip_addresses = self
+            .ip_addresses()
+            .into_iter()
+            .map(JsonValue::String)
+            .collect();
+        Variables::from([
+            (String::from("time"), TemplateValue::Time(now)),
+            (String::from("date"), TemplateValue::Date(now)),
+            (
+                String::from("assistant_name"),
+                JsonValue::String(self.assistant_config.name.clone()).into(),
+            ),
+            (
+                String::from("user_name"),
+                JsonValue::String(self.assistant_config.primary_user_name.clone()).into(),
+            ),
+            (String::from("hostname"), hostname.into()),
+            (
+                String::from("ip_addresses"),
+                JsonValue::Array(ip_addresses).into(),
+            ),
+            (
+                String::from("uptime"),
+                JsonValue::String(self.humanizer.duration(self.started_at.elapsed())).into(),
+            ),
+            (String::from("context"), context.clone().into()),
+        ])
+    }
+
+    /// IPv4 addresses of this device other than loopback
+    pub fn ip_addresses(&self) -> Vec<String> {
+        self.network_interfaces
+            .iter()
+            .flatten()
+            .filter(|(_, ip)| ip.is_ipv4() && !ip.is_loopback())
+            .map(|(_, ip)| ip.to_string())
+            .collect()
+    }
+
+    pub fn startup_message(&self, sections: &[StartupSection]) -> Vec<String> {
+        let phrases = self.humanizer.language().startup_phrases();
+        let mut message_buffer = vec![];
+        for section in sections {
+            match section {
+                StartupSection::Greeting => {
+                    message_buffer.push(fill(phrases.greeting, &[&self.assistant_config.name]))
+                }
+                StartupSection::Date => {
+                    let date = self.humanizer.date_time(Local::now().naive_local());
+                    message_buffer.push(fill(phrases.date, &[&date]));
+                }
+                StartupSection::Interfaces => match self.network_interfaces {
+                    Some(ref network_interfaces) if network_interfaces.is_empty() => {
+                        error!("No NICs found");
+                        message_buffer.push(String::from(phrases.no_interfaces))
+                    }
+                    Some(ref network_interfaces) => {
+                        let interface_message = network_interfaces
+                            .iter()
+                            .filter(|(_, ip)| ip.is_ipv4() && !ip.is_loopback())
+                            .map(|(name, ip)| fill(phrases.interface, &[name, &ip.to_string()]))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        info!("local interfaces are: {:?}", interface_message);
+                        message_buffer.push(fill(phrases.interfaces, &[&interface_message]));
+                    }
+                    None => {
+                        error!("Failed to query local network interfaces");
+                        message_buffer.push(String::from(phrases.interfaces_failed));
+                    }
+                },
+                StartupSection::Hostname => {
+                    if let Some(ref hostname) = self.hostname {
+                        message_buffer.push(fill(phrases.hostname, &[hostname]));
+                    } else {
+                        message_buffer.push(String::from(phrases.no_hostname));
+                    }
+                }
+            }
+        }
         message_buffer
     }
+}
 
-    pub fn template_substitute(message: &str) -> String {
-        let current_time = get_human_current_time();
-        let current_date_time = get_human_current_date_time();
-        message
-            .replace("/time", &current_time)
-            .replace("/date", &current_date_time)
+/// Templates are named after their file
+///
+/// Files that fail to load are logged and skipped.
+fn load_templates(templates_path: &Path) -> HashMap<String, NamedTemplate> {
+    let entries = match fs::read_dir(templates_path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to read templates dir {:?} {:?}", templates_path, e);
+            return HashMap::new();
+        }
+    };
+    let mut templates = HashMap::new();
+    for path in entries.flatten().map(|entry| entry.path()) {
+        let is_yaml = path
+            .extension()
+            .map(|extension| extension == "yaml" || extension == "yml")
+            .unwrap_or(false);
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !is_yaml {
+            continue;
+        }
+        match load_template(&path) {
+            Ok(template) => {
+                templates.insert(name.to_owned(), template);
+            }
+            Err(e) => error!("Skipping template {:?} {:?}", path, e),
+        }
     }
+    info!("Loaded {} templates", templates.len());
+    templates
+}
+
+fn load_template(path: &Path) -> anyhow::Result<NamedTemplate> {
+    let file: TemplateFile = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+    Ok(NamedTemplate {
+        template: Template::parse(&file.text)?,
+        provider: file.provider,
+        style: file.style,
+        voice: file.voice,
+    })
 }
 
 fn network_interfaces() -> Option<Vec<(String, IpAddr)>> {
@@ -87,7 +289,7 @@ fn network_interfaces() -> Option<Vec<(String, IpAddr)>> {
 fn hostname() -> Option<String> {
     if let Ok(output) = Command::new("hostname").output() {
         if let Ok(hostname) = str::from_utf8(&output.stdout) {
-            Some(hostname.to_owned())
+            Some(hostname.trim().to_owned())
         } else {
             error!("Failed to convert output of hostname command");
             None
@@ -97,32 +299,3 @@ fn hostname() -> Option<String> {
         None
     }
 }
-
-// This could use a humanizer library.
-// But this is okay for now I think
-
-pub fn get_human_current_date_time() -> String {
-    let now: chrono::DateTime<chrono::Local> = chrono::Local::now();
-    humanize_date_time(now)
-}
-
-pub fn get_human_current_time() -> String {
-    let now: chrono::DateTime<chrono::Local> = chrono::Local::now();
-    humanize_time(now)
-}
-
-fn humanize_time(date_time: chrono::DateTime<chrono::Local>) -> String {
-    format!("{}:{:02}, ", date_time.hour(), date_time.minute())
-}
-
-fn humanize_date_time(date_time: chrono::DateTime<chrono::Local>) -> String {
-    format!(
-        "{}, {} of {:?}, {} at {}:{:02}, ",
-        date_time.weekday(),
-        Ordinal(date_time.day()),
-        Month::from_u32(date_time.month()).unwrap(),
-        date_time.year(),
-        date_time.hour(),
-        date_time.minute()
-    )
-}
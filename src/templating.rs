@@ -0,0 +1,602 @@
+//! Small template language for spoken messages
+//!
+//! `{{ name }}` outputs a variable, `{{ name | filter }}` passes it through filters
+//! and dotted paths like `{{ context.door }}` reach into objects.
+//! `{% if %}`, `{% elif %}`, `{% else %}` and `{% endif %}` choose between branches.
+//! Conditions can be negated with `not` and compared with `==` or `!=`.
+//! Quoted literals may contain any of the syntax, so `{{ "{{" }}` outputs literal braces.
+//! Referencing a variable that doesn't exist is an error.
+
+use crate::{error::TemplateError, localization::Humanizer};
+use chrono::{DateTime, Local, TimeZone};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+
+pub type Variables = HashMap<String, TemplateValue>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateValue {
+    Json(JsonValue),
+    /// Spoken as a time of day
+    Time(DateTime<Local>),
+    /// Spoken as a full date with time
+    Date(DateTime<Local>),
+}
+
+impl TemplateValue {
+    /// Timestamps and RFC 3339 or unix time values from JSON
+    fn as_timestamp(&self) -> Option<DateTime<Local>> {
+        match self {
+            TemplateValue::Time(date_time) | TemplateValue::Date(date_time) => Some(*date_time),
+            TemplateValue::Json(JsonValue::String(text)) => DateTime::parse_from_rfc3339(text)
+                .ok()
+                .map(|date_time| date_time.with_timezone(&Local)),
+            TemplateValue::Json(JsonValue::Number(number)) => number
+                .as_i64()
+                .and_then(|seconds| Local.timestamp_opt(seconds, 0).single()),
+            TemplateValue::Json(_) => None,
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        match self {
+            TemplateValue::Json(value) => match value {
+                JsonValue::Null => false,
+                JsonValue::Bool(value) => *value,
+                JsonValue::Number(number) => number.as_f64() != Some(0.0),
+                JsonValue::String(text) => !text.is_empty(),
+                JsonValue::Array(items) => !items.is_empty(),
+                JsonValue::Object(fields) => !fields.is_empty(),
+            },
+            TemplateValue::Time(_) | TemplateValue::Date(_) => true,
+        }
+    }
+
+    fn render(&self, humanizer: &Humanizer) -> String {
+        match self {
+            TemplateValue::Json(value) => render_json(value),
+            TemplateValue::Time(date_time) => humanizer.time(date_time.time()),
+            TemplateValue::Date(date_time) => humanizer.date_time(date_time.naive_local()),
+        }
+    }
+}
+
+impl From<JsonValue> for TemplateValue {
+    fn from(value: JsonValue) -> Self {
+        TemplateValue::Json(value)
+    }
+}
+
+fn render_json(value: &JsonValue) -> String {
+    match value {
+        JsonValue::Null => String::new(),
+        JsonValue::String(text) => text.clone(),
+        // lists are read out as enumerations
+        JsonValue::Array(items) => items.iter().map(render_json).collect::<Vec<_>>().join(", "),
+        other => other.to_string(),
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Variable(Vec<String>),
+    Literal(JsonValue),
+}
+
+#[derive(Debug, Clone)]
+struct Expression {
+    operand: Operand,
+    filters: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Condition {
+    negated: bool,
+    expression: Expression,
+    /// `true` for `==` and `false` for `!=`
+    comparison: Option<(bool, Expression)>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Output(Expression),
+    If {
+        branches: Vec<(Condition, Vec<Node>)>,
+        otherwise: Vec<Node>,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Token<'a> {
+    Text(&'a str),
+    Output(&'a str),
+    Tag(&'a str),
+}
+
+/// Parsed template that can be rendered repeatedly
+#[derive(Debug, Clone)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let mut tokens = tokenize(source)?.into_iter();
+        match parse_nodes(&mut tokens)? {
+            (nodes, None) => Ok(Self { nodes }),
+            (_, Some(tag)) => Err(TemplateError::Syntax(format!("unexpected {{% {} %}}", tag))),
+        }
+    }
+
+    pub fn render(
+        &self,
+        variables: &Variables,
+        humanizer: &Humanizer,
+        now: DateTime<Local>,
+    ) -> Result<String, TemplateError> {
+        let scope = Scope {
+            variables,
+            humanizer,
+            now,
+        };
+        let mut output = String::new();
+        render_nodes(&self.nodes, &scope, &mut output)?;
+        Ok(output)
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token<'_>>, TemplateError> {
+    let mut tokens = vec![];
+    let mut rest = source;
+    loop {
+        let Some(start) = [rest.find("{{"), rest.find("{%")]
+            .into_iter()
+            .flatten()
+            .min()
+        else {
+            if !rest.is_empty() {
+                tokens.push(Token::Text(rest));
+            }
+            return Ok(tokens);
+        };
+        if start > 0 {
+            tokens.push(Token::Text(&rest[..start]));
+        }
+        let is_output = rest[start..].starts_with("{{");
+        let closing = if is_output { "}}" } else { "%}" };
+        let inner = &rest[start + 2..];
+        let end = find_unquoted(inner, closing).ok_or_else(|| {
+            TemplateError::Syntax(format!("{:?} is never closed", &rest[start..start + 2]))
+        })?;
+        let body = inner[..end].trim();
+        tokens.push(if is_output {
+            Token::Output(body)
+        } else {
+            Token::Tag(body)
+        });
+        rest = &inner[end + 2..];
+    }
+}
+
+/// Byte offset of the first `pattern` that isn't inside a quoted literal
+fn find_unquoted(text: &str, pattern: &str) -> Option<usize> {
+    let mut quote = None;
+    for (index, character) in text.char_indices() {
+        match quote {
+            Some(open) if character == open => quote = None,
+            Some(_) => (),
+            None if character == '"' || character == '\'' => quote = Some(character),
+            None if text[index..].starts_with(pattern) => return Some(index),
+            None => (),
+        }
+    }
+    None
+}
+
+fn split_unquoted<'a>(text: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = vec![];
+    let mut rest = text;
+    while let Some(index) = find_unquoted(rest, separator) {
+        parts.push(&rest[..index]);
+        rest = &rest[index + separator.len()..];
+    }
+    parts.push(rest);
+    parts
+}
+
+fn split_keyword(tag: &str) -> (&str, &str) {
+    tag.split_once(char::is_whitespace)
+        .map(|(keyword, argument)| (keyword, argument.trim()))
+        .unwrap_or((tag, ""))
+}
+
+/// Nodes up to the end of input or the first tag that closes a block
+fn parse_nodes<'a>(
+    tokens: &mut impl Iterator<Item = Token<'a>>,
+) -> Result<(Vec<Node>, Option<&'a str>), TemplateError> {
+    let mut nodes = vec![];
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Text(text) => nodes.push(Node::Text(text.to_owned())),
+            Token::Output(expression) => nodes.push(Node::Output(parse_expression(expression)?)),
+            Token::Tag(tag) => match split_keyword(tag) {
+                ("if", condition) => nodes.push(parse_if(condition, tokens)?),
+                ("elif" | "else" | "endif", _) => return Ok((nodes, Some(tag))),
+                (keyword, _) => {
+                    return Err(TemplateError::Syntax(format!("unknown tag {:?}", keyword)))
+                }
+            },
+        }
+    }
+    Ok((nodes, None))
+}
+
+fn parse_if<'a>(
+    condition: &str,
+    tokens: &mut impl Iterator<Item = Token<'a>>,
+) -> Result<Node, TemplateError> {
+    let mut branches = vec![];
+    let mut condition = parse_condition(condition)?;
+    loop {
+        let (body, terminator) = parse_nodes(tokens)?;
+        branches.push((condition, body));
+        match terminator.map(split_keyword) {
+            Some(("elif", next_condition)) => condition = parse_condition(next_condition)?,
+            Some(("else", _)) => {
+                let (otherwise, terminator) = parse_nodes(tokens)?;
+                return match terminator.map(split_keyword) {
+                    Some(("endif", _)) => Ok(Node::If {
+                        branches,
+                        otherwise,
+                    }),
+                    _ => Err(TemplateError::Syntax(String::from(
+                        "expected {% endif %} after {% else %}",
+                    ))),
+                };
+            }
+            Some(("endif", _)) => {
+                return Ok(Node::If {
+                    branches,
+                    otherwise: vec![],
+                })
+            }
+            _ => {
+                return Err(TemplateError::Syntax(String::from(
+                    "{% if %} is missing {% endif %}",
+                )))
+            }
+        }
+    }
+}
+
+fn parse_condition(text: &str) -> Result<Condition, TemplateError> {
+    let (negated, text) = match text.strip_prefix("not ") {
+        Some(rest) => (true, rest.trim()),
+        None => (false, text),
+    };
+    if text.is_empty() {
+        return Err(TemplateError::Syntax(String::from(
+            "{% if %} needs a condition",
+        )));
+    }
+    let split = find_unquoted(text, "!=")
+        .map(|index| (index, false))
+        .or_else(|| find_unquoted(text, "==").map(|index| (index, true)));
+    Ok(match split {
+        Some((index, equal)) => Condition {
+            negated,
+            expression: parse_expression(&text[..index])?,
+            comparison: Some((equal, parse_expression(&text[index + 2..])?)),
+        },
+        None => Condition {
+            negated,
+            expression: parse_expression(text)?,
+            comparison: None,
+        },
+    })
+}
+
+fn parse_expression(text: &str) -> Result<Expression, TemplateError> {
+    let mut parts = split_unquoted(text, "|").into_iter().map(str::trim);
+    let operand = parse_operand(parts.next().unwrap_or_default())?;
+    let filters = parts
+        .map(|filter| {
+            if filter.is_empty() {
+                Err(TemplateError::Syntax(format!("empty filter in {:?}", text)))
+            } else {
+                Ok(filter.to_owned())
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Expression { operand, filters })
+}
+
+fn parse_operand(text: &str) -> Result<Operand, TemplateError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(TemplateError::Syntax(String::from("empty expression")));
+    }
+    for quote in ['"', '\''] {
+        if text.len() >= 2 && text.starts_with(quote) && text.ends_with(quote) {
+            let literal = &text[1..text.len() - 1];
+            return Ok(Operand::Literal(JsonValue::String(literal.to_owned())));
+        }
+    }
+    if matches!(text, "true" | "false" | "null")
+        || text.starts_with(|first: char| first.is_ascii_digit() || first == '-')
+    {
+        return serde_json::from_str(text)
+            .map(Operand::Literal)
+            .map_err(|_| TemplateError::Syntax(format!("invalid literal {:?}", text)));
+    }
+    let path: Vec<String> = text.split('.').map(str::to_owned).collect();
+    let valid = path.iter().all(|segment| {
+        !segment.is_empty()
+            && segment
+                .chars()
+                .all(|character| character.is_alphanumeric() || character == '_')
+    });
+    if valid {
+        Ok(Operand::Variable(path))
+    } else {
+        Err(TemplateError::Syntax(format!(
+            "invalid variable name {:?}",
+            text
+        )))
+    }
+}
+
+/// Everything needed to evaluate expressions
+struct Scope<'a> {
+    variables: &'a Variables,
+    humanizer: &'a Humanizer,
+    now: DateTime<Local>,
+}
+
+fn render_nodes(nodes: &[Node], scope: &Scope, output: &mut String) -> Result<(), TemplateError> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Output(expression) => {
+                output.push_str(&evaluate(expression, scope)?.render(scope.humanizer))
+            }
+            Node::If {
+                branches,
+                otherwise,
+            } => {
+                let mut chosen = otherwise;
+                for (condition, body) in branches {
+                    if check(condition, scope)? {
+                        chosen = body;
+                        break;
+                    }
+                }
+                render_nodes(chosen, scope, output)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check(condition: &Condition, scope: &Scope) -> Result<bool, TemplateError> {
+    let value = evaluate(&condition.expression, scope)?;
+    let result = match &condition.comparison {
+        Some((equal, other)) => {
+            let other = evaluate(other, scope)?;
+            let same = match (&value, &other) {
+                (TemplateValue::Json(left), TemplateValue::Json(right)) => left == right,
+                (left, right) => left.render(scope.humanizer) == right.render(scope.humanizer),
+            };
+            same == *equal
+        }
+        None => value.is_truthy(),
+    };
+    Ok(result != condition.negated)
+}
+
+fn evaluate(expression: &Expression, scope: &Scope) -> Result<TemplateValue, TemplateError> {
+    let mut value = match &expression.operand {
+        Operand::Literal(literal) => TemplateValue::Json(literal.clone()),
+        Operand::Variable(path) => lookup(path, scope.variables)?,
+    };
+    for filter in &expression.filters {
+        value = apply_filter(filter, value, scope)?;
+    }
+    Ok(value)
+}
+
+fn lookup(path: &[String], variables: &Variables) -> Result<TemplateValue, TemplateError> {
+    let unknown = || TemplateError::UnknownVariable(path.join("."));
+    let (name, fields) = path.split_first().ok_or_else(unknown)?;
+    let value = variables.get(name).ok_or_else(unknown)?;
+    if fields.is_empty() {
+        return Ok(value.clone());
+    }
+    let TemplateValue::Json(root) = value else {
+        return Err(unknown());
+    };
+    let mut json = root;
+    for field in fields {
+        json = match json {
+            JsonValue::Object(object) => object.get(field),
+            JsonValue::Array(items) => field.parse().ok().and_then(|index: usize| items.get(index)),
+            _ => None,
+        }
+        .ok_or_else(unknown)?;
+    }
+    Ok(TemplateValue::Json(json.clone()))
+}
+
+fn apply_filter(
+    filter: &str,
+    value: TemplateValue,
+    scope: &Scope,
+) -> Result<TemplateValue, TemplateError> {
+    let timestamp = || {
+        value
+            .as_timestamp()
+            .ok_or_else(|| TemplateError::InvalidFilterInput {
+                filter: filter.to_owned(),
+                value: value.render(scope.humanizer),
+            })
+    };
+    match filter {
+        "time" => Ok(TemplateValue::Time(timestamp()?)),
+        "date" => Ok(TemplateValue::Date(timestamp()?)),
+        "relative" => {
+            let relative = scope
+                .humanizer
+                .relative(timestamp()?.naive_local(), scope.now.naive_local());
+            Ok(TemplateValue::Json(JsonValue::String(relative)))
+        }
+        other => Err(TemplateError::UnknownFilter(other.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::localization::{ClockFormat, Language};
+    use serde_json::json;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2022, 1, 3, hour, minute, 0).unwrap()
+    }
+
+    fn variables() -> Variables {
+        Variables::from([
+            (String::from("name"), json!("Anna").into()),
+            (String::from("count"), json!(3).into()),
+            (String::from("empty"), json!("").into()),
+            (String::from("mode"), json!("a!=b").into()),
+            (String::from("rooms"), json!(["kitchen", "hall"]).into()),
+            (
+                String::from("context"),
+                json!({"door": "front", "sensors": [{"id": 7}]}).into(),
+            ),
+            (String::from("alarm"), TemplateValue::Date(at(9, 15))),
+            (String::from("soon"), TemplateValue::Time(at(9, 5))),
+        ])
+    }
+
+    fn render(source: &str) -> Result<String, TemplateError> {
+        let humanizer = Humanizer::new(Language::En, ClockFormat::H24, true);
+        Template::parse(source)?.render(&variables(), &humanizer, at(9, 0))
+    }
+
+    #[test]
+    fn outputs_variables_and_literals() {
+        assert_eq!(render("Hello {{ name }}!").unwrap(), "Hello Anna!");
+        assert_eq!(render("{{context.door}} door").unwrap(), "front door");
+        assert_eq!(render("{{ context.sensors.0.id }}").unwrap(), "7");
+        assert_eq!(render("{{ rooms }}").unwrap(), "kitchen, hall");
+        assert_eq!(
+            render("{{ count }} {{ 1.5 }} {{ true }}").unwrap(),
+            "3 1.5 true"
+        );
+        assert_eq!(render("{{ null }}{{ 'single' }}").unwrap(), "single");
+        assert_eq!(render("no tags at all").unwrap(), "no tags at all");
+    }
+
+    #[test]
+    fn quoted_literals_may_contain_syntax() {
+        assert_eq!(render(r#"{{ "a|b" }}"#).unwrap(), "a|b");
+        assert_eq!(render(r#"{{ "}}" }}"#).unwrap(), "}}");
+        assert_eq!(render(r#"{{ "{{" }} name }}"#).unwrap(), "{{ name }}");
+        assert_eq!(render(r#"{{ "{%" }} if %}"#).unwrap(), "{% if %}");
+        assert_eq!(
+            render(r#"{% if mode == "a!=b" %}yes{% endif %}"#).unwrap(),
+            "yes"
+        );
+        assert_eq!(
+            render(r#"{% if "x==y" != mode %}yes{% endif %}"#).unwrap(),
+            "yes"
+        );
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(render("{{ alarm | time }}").unwrap(), "quarter past nine");
+        assert_eq!(render("{{ soon | relative }}").unwrap(), "in 5 minutes");
+        assert_eq!(
+            render("{{ soon | date | time }}").unwrap(),
+            render("{{ soon | time }}").unwrap()
+        );
+        assert_eq!(
+            render("{{ name | shout }}"),
+            Err(TemplateError::UnknownFilter(String::from("shout")))
+        );
+        assert_eq!(
+            render("{{ name | time }}"),
+            Err(TemplateError::InvalidFilterInput {
+                filter: String::from("time"),
+                value: String::from("Anna"),
+            })
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        let source = "{% if count == 1 %}one{% elif count != 3 %}some{% else %}three{% endif %}";
+        assert_eq!(render(source).unwrap(), "three");
+        assert_eq!(
+            render("{% if name %}hi {{ name }}{% endif %}").unwrap(),
+            "hi Anna"
+        );
+        assert_eq!(render("{% if empty %}text{% endif %}").unwrap(), "");
+        assert_eq!(render("{% if not empty %}none{% endif %}").unwrap(), "none");
+        assert_eq!(
+            render("{% if context.door == 'front' %}front{% else %}back{% endif %}").unwrap(),
+            "front"
+        );
+        assert_eq!(
+            render("{% if rooms %}{% if not count %}a{% elif name %}b{% endif %}{% endif %}")
+                .unwrap(),
+            "b"
+        );
+        // only the chosen branch is evaluated
+        assert_eq!(
+            render("{% if true %}ok{% else %}{{ missing }}{% endif %}").unwrap(),
+            "ok"
+        );
+    }
+
+    #[test]
+    fn unknown_variables_are_errors() {
+        let unknown = |name: &str| Err(TemplateError::UnknownVariable(name.to_owned()));
+        assert_eq!(render("{{ missing }}"), unknown("missing"));
+        assert_eq!(render("{{ context.window }}"), unknown("context.window"));
+        assert_eq!(render("{{ rooms.5 }}"), unknown("rooms.5"));
+        assert_eq!(render("{{ name.first }}"), unknown("name.first"));
+        assert_eq!(render("{{ alarm.hour }}"), unknown("alarm.hour"));
+        assert_eq!(render("{% if missing %}x{% endif %}"), unknown("missing"));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for source in [
+            "{{ name",
+            "{% if name %}",
+            "{% if name %}a{% else %}b",
+            "{% if name %}a{% else %}b{% elif count %}c{% endif %}",
+            "{% endif %}",
+            "{% else %}",
+            "{% for room in rooms %}{% endfor %}",
+            "{% if %}x{% endif %}",
+            "{{ }}",
+            "{{ name | }}",
+            "{{ name || time }}",
+            "{{ first name }}",
+            "{{ context..door }}",
+            "{{ 12abc }}",
+            r#"{{ "unterminated }}"#,
+        ] {
+            assert!(
+                matches!(Template::parse(source), Err(TemplateError::Syntax(_))),
+                "{:?} should not parse",
+                source
+            );
+        }
+    }
+}
@@ -0,0 +1,131 @@
+/// Split text into sentences so that each one can be synthesized and cached on its own
+///
+/// Paragraphs are split on line breaks.
+/// Sentences longer than `max_chunk_length` characters are split between words.
+pub fn split_into_chunks(text: &str, max_chunk_length: usize) -> Vec<String> {
+    let mut chunks = vec![];
+    for paragraph in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        for sentence in split_sentences(paragraph) {
+            push_with_length_limit(sentence, max_chunk_length, &mut chunks);
+        }
+    }
+    chunks
+}
+
+fn is_sentence_terminator(character: char) -> bool {
+    matches!(character, '.' | '!' | '?')
+}
+
+fn split_sentences(paragraph: &str) -> Vec<&str> {
+    let mut sentences = vec![];
+    let mut start = 0;
+    let mut characters = paragraph.char_indices().peekable();
+    while let Some((_, character)) = characters.next() {
+        if !is_sentence_terminator(character) {
+            continue;
+        }
+        // keep runs such as "?!" or "..." together
+        while let Some(&(_, next)) = characters.peek() {
+            if !is_sentence_terminator(next) {
+                break;
+            }
+            characters.next();
+        }
+        // only split when followed by whitespace so that "3.5" stays in one piece
+        if let Some(&(next_index, next)) = characters.peek() {
+            if next.is_whitespace() {
+                sentences.push(paragraph[start..next_index].trim());
+                start = next_index;
+            }
+        }
+    }
+    sentences.push(paragraph[start..].trim());
+    sentences.retain(|sentence| !sentence.is_empty());
+    sentences
+}
+
+fn push_with_length_limit(sentence: &str, max_length: usize, chunks: &mut Vec<String>) {
+    if sentence.chars().count() <= max_length {
+        chunks.push(sentence.to_owned());
+        return;
+    }
+    let mut current = String::new();
+    for word in sentence.split_whitespace() {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > max_length {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_sentences() {
+        assert_eq!(
+            split_into_chunks("Hello there. How are you? Great!", 100),
+            vec!["Hello there.", "How are you?", "Great!"]
+        );
+    }
+
+    #[test]
+    fn keeps_terminator_runs_and_decimals_together() {
+        assert_eq!(
+            split_into_chunks("Wait... What?! It is 3.5 degrees.", 100),
+            vec!["Wait...", "What?!", "It is 3.5 degrees."]
+        );
+    }
+
+    #[test]
+    fn splits_paragraphs_without_terminators() {
+        assert_eq!(
+            split_into_chunks("First line\n\n  Second line  \r\nThird. Fourth", 100),
+            vec!["First line", "Second line", "Third.", "Fourth"]
+        );
+    }
+
+    #[test]
+    fn long_sentences_are_split_between_words() {
+        let chunks = split_into_chunks("one two three four five six", 10);
+        assert_eq!(chunks, vec!["one two", "three four", "five six"]);
+        assert!(chunks.iter().all(|chunk| chunk.chars().count() <= 10));
+    }
+
+    #[test]
+    fn words_longer_than_the_limit_are_kept_whole() {
+        assert_eq!(
+            split_into_chunks("a supercalifragilistic word", 10),
+            vec!["a", "supercalifragilistic", "word"]
+        );
+    }
+
+    #[test]
+    fn limit_counts_characters_not_bytes() {
+        // each word is 5 characters but 10 bytes
+        let text = "čšťžý ďáéíó úůňľř";
+        assert_eq!(split_into_chunks(text, 17), vec![text]);
+        assert_eq!(split_into_chunks(text, 11), vec!["čšťžý ďáéíó", "úůňľř"]);
+    }
+
+    #[test]
+    fn splits_sentences_with_multi_byte_characters() {
+        assert_eq!(
+            split_into_chunks("Dobrý den! Jak se máš? Příliš žluťoučký kůň.", 100),
+            vec!["Dobrý den!", "Jak se máš?", "Příliš žluťoučký kůň."]
+        );
+    }
+
+    #[test]
+    fn empty_input_has_no_chunks() {
+        assert!(split_into_chunks("", 100).is_empty());
+        assert!(split_into_chunks("  \n\t\n ", 100).is_empty());
+    }
+}